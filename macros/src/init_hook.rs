@@ -0,0 +1,48 @@
+use proc_macro_error::emit_error;
+use syn::parse::{Parse, ParseStream};
+
+use crate::web_handler::is_named;
+
+pub struct Attributes {
+    pub priority: i32,
+}
+
+impl Parse for Attributes {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut priority = 0;
+        if !input.is_empty() {
+            let name: syn::Ident = input.parse()?;
+            if name != "priority" {
+                return Err(syn::Error::new_spanned(name,
+                    "only `priority` attribute is supported"));
+            }
+            input.parse::<syn::Token![=]>()?;
+            let negative = input.parse::<Option<syn::Token![-]>>()?.is_some();
+            let value: syn::LitInt = input.parse()?;
+            priority = value.base10_parse::<i32>()?;
+            if negative {
+                priority = -priority;
+            }
+            input.parse::<Option<syn::Token![,]>>()?;
+        }
+        Ok(Attributes { priority })
+    }
+}
+
+pub fn returns_unit(output: &syn::ReturnType) -> bool {
+    match output {
+        syn::ReturnType::Default => true,
+        syn::ReturnType::Type(_, ty) => {
+            matches!(&**ty, syn::Type::Tuple(t) if t.elems.is_empty())
+        }
+    }
+}
+
+/// Emits an error if return type is neither `()` nor a `Result`
+pub fn check_output(output: &syn::ReturnType) {
+    if let syn::ReturnType::Type(_, ty) = output {
+        if !returns_unit(output) && !is_named(ty, "Result") {
+            emit_error!(ty, "init hook must return `()` or a `Result`");
+        }
+    }
+}
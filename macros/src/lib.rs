@@ -2,23 +2,19 @@ use proc_macro::TokenStream;
 use proc_macro_error::emit_error;
 use quote::quote;
 
+mod init_hook;
+mod static_dir;
+mod web_handler;
+
 /// Register web handler
+///
+/// Function must receive `web::Request` and return either `web::Response` or
+/// `Result<web::Response, E>`. It may also be an `async fn`.
 #[proc_macro_error::proc_macro_error]
 #[proc_macro_attribute]
 pub fn web_handler(_attr: TokenStream, item: TokenStream) -> TokenStream {
     let input = syn::parse_macro_input!(item as syn::ItemFn);
-    let func_name = &input.sig.ident;
-    let hook_name = quote::format_ident!(
-        "_edgedb_sdk_init_web_handler_{}", func_name);
-    quote! {
-        #input
-
-        #[export_name = stringify!(#hook_name)]
-        extern fn #hook_name() {
-            ::edgedb_sdk::web::register_handler(#func_name);
-        }
-
-    }.into()
+    web_handler::expand(input).into()
 }
 
 /// Mark function to run at wasm initialization
@@ -32,6 +28,23 @@ pub fn web_handler(_attr: TokenStream, item: TokenStream) -> TokenStream {
 ///    used though (e.g. [`web_handler`](macro@web_handler)).
 /// 2. For smaller latency during request processing (but see below).
 ///
+/// # Ordering
+///
+/// Hooks run in the order of `priority` (lower first, default is zero), then
+/// in the order of function names:
+///
+/// ```rust,ignore
+/// #[edgedb_sdk::init_hook(priority = -10)]
+/// fn load_config() {
+/// }
+/// ```
+///
+/// # Errors
+///
+/// Hook may return `Result<(), E>` where `E` implements `Display`. If it
+/// returns an error, the error is logged and the worker is not started:
+/// requests fail with `503 Service Unavailable`.
+///
 /// # Influence on Request Latency
 ///
 /// Note: while we will provide request processing latency metric distinct from
@@ -44,7 +57,8 @@ pub fn web_handler(_attr: TokenStream, item: TokenStream) -> TokenStream {
 /// [`lazy_static`]: https://crates.io/crates/lazy_static
 #[proc_macro_error::proc_macro_error]
 #[proc_macro_attribute]
-pub fn init_hook(_attr: TokenStream, item: TokenStream) -> TokenStream {
+pub fn init_hook(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let attr = syn::parse_macro_input!(attr as init_hook::Attributes);
     let input = syn::parse_macro_input!(item as syn::ItemFn);
     if !input.sig.generics.params.is_empty() {
         emit_error!(input.sig.generics, "no generics allowed on init hook");
@@ -52,18 +66,226 @@ pub fn init_hook(_attr: TokenStream, item: TokenStream) -> TokenStream {
     if !input.sig.inputs.is_empty() {
         emit_error!(input.sig.inputs, "no params allowed on init hook");
     }
+    init_hook::check_output(&input.sig.output);
+    proc_macro_error::abort_if_dirty();
+    let func_name = &input.sig.ident;
+    let hook_name = quote::format_ident!("_edgedb_sdk_init_{}", func_name);
+    let export_name = if attr.priority == 0 {
+        hook_name.to_string()
+    } else {
+        format!("{}@{}", hook_name, attr.priority)
+    };
+    if init_hook::returns_unit(&input.sig.output) {
+        quote! {
+            #input
+
+            #[export_name = #export_name]
+            extern fn #hook_name() {
+                #func_name();
+            }
+        }.into()
+    } else {
+        quote! {
+            #input
+
+            #[export_name = #export_name]
+            extern fn #hook_name() -> i32 {
+                ::edgedb_sdk::hooks::init_result(
+                    stringify!(#func_name), #func_name())
+            }
+        }.into()
+    }
+}
+
+/// Mark function to run before the worker is unloaded
+///
+/// Worker is unloaded when the module is updated or its directory is
+/// changed. Shutdown hooks can be used to flush buffered data. All hooks of
+/// the module must finish within a few seconds, otherwise they are
+/// interrupted.
+///
+/// Hooks are not run if the worker crashed or if the server is killed.
+#[proc_macro_error::proc_macro_error]
+#[proc_macro_attribute]
+pub fn shutdown_hook(_attr: TokenStream, item: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(item as syn::ItemFn);
+    if !input.sig.generics.params.is_empty() {
+        emit_error!(input.sig.generics,
+                    "no generics allowed on shutdown hook");
+    }
+    if !input.sig.inputs.is_empty() {
+        emit_error!(input.sig.inputs, "no params allowed on shutdown hook");
+    }
     if !matches!(input.sig.output, syn::ReturnType::Default) {
-        emit_error!(input.sig.output, "no return value allowed on init hook");
+        emit_error!(input.sig.output,
+                    "no return value allowed on shutdown hook");
     }
     let func_name = &input.sig.ident;
-    let hook_name = quote::format_ident!("_edgedb_sdk_init_{}", func_name);
+    let hook_name = quote::format_ident!("_edgedb_sdk_shutdown_{}", func_name);
+    quote! {
+        #input
+
+        #[export_name = stringify!(#hook_name)]
+        extern fn #hook_name() {
+            #func_name();
+        }
+
+    }.into()
+}
+
+/// Run function periodically according to the cron expression
+///
+/// ```rust,ignore
+/// #[edgedb_sdk::schedule("0 3 * * *")]
+/// fn nightly_cleanup() -> anyhow::Result<()> {
+///     Ok(())
+/// }
+/// ```
+///
+/// Function must have no arguments and return either `()` or a `Result`.
+/// Task is named after the function. See `edgedb_sdk::schedule` module for
+/// details.
+#[proc_macro_error::proc_macro_error]
+#[proc_macro_attribute]
+pub fn schedule(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let cron = syn::parse_macro_input!(attr as syn::LitStr);
+    let input = syn::parse_macro_input!(item as syn::ItemFn);
+    let fields = cron.value().split_whitespace().count();
+    if !(5..=7).contains(&fields) {
+        emit_error!(cron, "cron expression must have 5 to 7 fields");
+    }
+    if cron.value().contains(':') {
+        emit_error!(cron, "colon is not allowed in cron expression");
+    }
+    if !input.sig.generics.params.is_empty() {
+        emit_error!(input.sig.generics,
+                    "no generics allowed on scheduled task");
+    }
+    if !input.sig.inputs.is_empty() {
+        emit_error!(input.sig.inputs, "no params allowed on scheduled task");
+    }
+    let func_name = &input.sig.ident;
+    let hook_name = quote::format_ident!(
+        "_edgedb_sdk_init_schedule_{}", func_name);
+    // host reads schedules from export names without instantiating module
+    let marker_name = quote::format_ident!(
+        "_edgedb_sdk_schedule_{}", func_name);
+    let marker = format!("_edgedb_sdk_schedule:{}:{}",
+                         func_name, cron.value());
     quote! {
         #input
 
         #[export_name = stringify!(#hook_name)]
         extern fn #hook_name() {
+            ::edgedb_sdk::schedule::register(
+                stringify!(#func_name), #cron, #func_name);
+        }
+
+        #[export_name = #marker]
+        extern fn #marker_name() {}
+
+    }.into()
+}
+
+/// Run function as a background job
+///
+/// ```rust,ignore
+/// #[edgedb_sdk::job]
+/// fn send_welcome(user_id: String) -> anyhow::Result<()> {
+///     Ok(())
+/// }
+/// ```
+///
+/// Function must have a single `String` argument (job payload) and return
+/// either `()` or a `Result`. Job is named after the function. See
+/// `edgedb_sdk::jobs` module for details.
+#[proc_macro_error::proc_macro_error]
+#[proc_macro_attribute]
+pub fn job(_attr: TokenStream, item: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(item as syn::ItemFn);
+    if !input.sig.generics.params.is_empty() {
+        emit_error!(input.sig.generics, "no generics allowed on job");
+    }
+    if input.sig.inputs.len() != 1 {
+        emit_error!(input.sig.inputs, "job must have a single payload param");
+    }
+    let func_name = &input.sig.ident;
+    let hook_name = quote::format_ident!(
+        "_edgedb_sdk_init_job_{}", func_name);
+    quote! {
+        #input
+
+        #[export_name = stringify!(#hook_name)]
+        extern fn #hook_name() {
+            ::edgedb_sdk::jobs::register(stringify!(#func_name), #func_name);
+        }
+
+    }.into()
+}
+
+/// Mark function as a test run inside the WebAssembly host
+///
+/// ```rust,ignore
+/// #[edgedb_sdk::test]
+/// fn query_works() -> anyhow::Result<()> {
+///     let client = edgedb_sdk::client::create_client();
+///     assert_eq!(client.query_required_single::<i64, _>("SELECT 1", &())?, 1);
+///     Ok(())
+/// }
+/// ```
+///
+/// Tests are run with `edgedb-wasm-server test <file.wasm>`, each one in a
+/// fresh instance of the module. Test fails if it panics or returns an error.
+///
+/// Tests are exported from every build of the module, so it may be useful to
+/// put them behind a cargo feature.
+#[proc_macro_error::proc_macro_error]
+#[proc_macro_attribute]
+pub fn test(_attr: TokenStream, item: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(item as syn::ItemFn);
+    if !input.sig.generics.params.is_empty() {
+        emit_error!(input.sig.generics, "no generics allowed on test");
+    }
+    if !input.sig.inputs.is_empty() {
+        emit_error!(input.sig.inputs, "no params allowed on test");
+    }
+    let func_name = &input.sig.ident;
+    let test_name = quote::format_ident!("_edgedb_sdk_test_{}", func_name);
+    let call = if init_hook::returns_unit(&input.sig.output) {
+        quote! {
             #func_name();
+            0
+        }
+    } else {
+        quote! {
+            ::edgedb_sdk::hooks::test_result(
+                stringify!(#func_name), #func_name())
+        }
+    };
+    quote! {
+        #input
+
+        #[export_name = stringify!(#test_name)]
+        extern fn #test_name() -> i32 {
+            #call
         }
 
     }.into()
 }
+
+/// Embed a directory of static files into the WebAssembly module
+///
+/// Path is relative to the crate root (the directory containing
+/// `Cargo.toml`). Files named like `app.js.gz` and `app.js.br` are served
+/// as precompressed variants of `app.js` if the latter exists.
+///
+/// Note: adding or removing files doesn't trigger recompilation, while
+/// changes in the existing files do.
+///
+/// See `edgedb_sdk::web::StaticDir` for usage.
+#[proc_macro_error::proc_macro_error]
+#[proc_macro]
+pub fn static_dir(input: TokenStream) -> TokenStream {
+    let dir = syn::parse_macro_input!(input as syn::LitStr);
+    static_dir::expand(dir).into()
+}
@@ -0,0 +1,173 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use proc_macro2::TokenStream;
+use proc_macro_error::{abort, emit_error};
+use quote::quote;
+use sha2::{Digest, Sha256};
+
+struct File {
+    path: String,
+    full_path: PathBuf,
+    gzip: Option<PathBuf>,
+    brotli: Option<PathBuf>,
+}
+
+fn content_type(path: &str) -> &'static str {
+    let ext = path.rsplit_once('.').map(|(_, ext)| ext).unwrap_or("");
+    match &ext.to_ascii_lowercase()[..] {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+fn walk(dir: &Path, prefix: &str, files: &mut Vec<File>)
+    -> std::io::Result<()>
+{
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(name) => {
+                emit_error!(proc_macro2::Span::call_site(),
+                            "skipping non-utf-8 file name {:?}", name);
+                continue;
+            }
+        };
+        let path = format!("{}{}", prefix, name);
+        if entry.file_type()?.is_dir() {
+            walk(&entry.path(), &format!("{}/", path), files)?;
+        } else {
+            files.push(File {
+                path,
+                full_path: entry.path(),
+                gzip: None,
+                brotli: None,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Attaches `*.gz` and `*.br` files to the original files
+fn precompressed(mut files: Vec<File>) -> Vec<File> {
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut result = Vec::<File>::with_capacity(files.len());
+    let mut variants = Vec::new();
+    for file in files {
+        let base = file.path.strip_suffix(".gz")
+            .or_else(|| file.path.strip_suffix(".br"));
+        match base {
+            Some(base) if files_contain(&result, base) => variants.push(file),
+            _ => result.push(file),
+        }
+    }
+    for variant in variants {
+        let (base, ext) = variant.path.split_at(variant.path.len() - 3);
+        let file = result.iter_mut().find(|f| f.path == base)
+            .expect("base file exists");
+        if ext == ".gz" {
+            file.gzip = Some(variant.full_path);
+        } else {
+            file.brotli = Some(variant.full_path);
+        }
+    }
+    result
+}
+
+fn files_contain(files: &[File], path: &str) -> bool {
+    // `file.gz` sorts after `file`, so base file is already there
+    files.iter().rev().any(|f| f.path == path)
+}
+
+fn include(path: &Path) -> TokenStream {
+    match path.to_str() {
+        Some(path) => quote! { include_bytes!(#path) },
+        None => abort!(proc_macro2::Span::call_site(),
+                       "non-utf-8 path {:?}", path),
+    }
+}
+
+fn include_opt(path: &Option<PathBuf>) -> TokenStream {
+    match path {
+        Some(path) => {
+            let data = include(path);
+            quote! { Some(#data) }
+        }
+        None => quote! { None },
+    }
+}
+
+fn etag(path: &Path) -> String {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) => abort!(proc_macro2::Span::call_site(),
+                         "cannot read {:?}: {}", path, e),
+    };
+    // truncated, as 128 bits are enough to tell versions of a file apart
+    let hash = Sha256::digest(&data);
+    let hex = hash[..16].iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    format!("\"{}\"", hex)
+}
+
+pub fn expand(dir: syn::LitStr) -> TokenStream {
+    let root = match env::var_os("CARGO_MANIFEST_DIR") {
+        Some(manifest_dir) => Path::new(&manifest_dir).join(dir.value()),
+        None => PathBuf::from(dir.value()),
+    };
+    let mut files = Vec::new();
+    if let Err(e) = walk(&root, "", &mut files) {
+        abort!(dir, "cannot read directory {:?}: {}", root, e);
+    }
+    let files = precompressed(files).into_iter().map(|file| {
+        let path = &file.path;
+        let content_type = content_type(&file.path);
+        let etag = etag(&file.full_path);
+        let data = include(&file.full_path);
+        let gzip = include_opt(&file.gzip);
+        let brotli = include_opt(&file.brotli);
+        quote! {
+            ::edgedb_sdk::web::static_files::StaticFile {
+                path: #path,
+                content_type: #content_type,
+                etag: #etag,
+                data: #data,
+                gzip: #gzip,
+                brotli: #brotli,
+            }
+        }
+    });
+    quote! {
+        {
+            static FILES: &[::edgedb_sdk::web::static_files::StaticFile] = &[
+                #(#files),*
+            ];
+            ::edgedb_sdk::web::static_files::StaticDir::new(FILES)
+        }
+    }
+}
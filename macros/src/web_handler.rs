@@ -0,0 +1,101 @@
+use proc_macro2::TokenStream;
+use proc_macro_error::emit_error;
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
+
+/// Returns `true` if type is a path with the specified last segment
+///
+/// Types can't be resolved in the macro, so this only catches obvious
+/// mistakes. The rest is checked by the compiler.
+pub fn is_named(ty: &syn::Type, name: &str) -> bool {
+    match ty {
+        syn::Type::Path(path) => {
+            path.qself.is_none() &&
+            path.path.segments.last().map(|s| s.ident == name)
+                .unwrap_or(false)
+        }
+        syn::Type::Group(group) => is_named(&group.elem, name),
+        syn::Type::Paren(paren) => is_named(&paren.elem, name),
+        _ => false,
+    }
+}
+
+fn check_input(sig: &syn::Signature) {
+    if sig.inputs.len() != 1 {
+        emit_error!(sig.paren_token.span,
+            "web handler must have exactly one argument of type \
+             `web::Request`, found {}", sig.inputs.len());
+        return;
+    }
+    match &sig.inputs[0] {
+        syn::FnArg::Receiver(arg) => {
+            emit_error!(arg, "web handler can't be a method");
+        }
+        syn::FnArg::Typed(arg) => match &*arg.ty {
+            syn::Type::Reference(r) if is_named(&r.elem, "Request") => {
+                emit_error!(arg.ty, "web handler receives request by value";
+                    help = "use `web::Request` instead");
+            }
+            ty if !is_named(ty, "Request") => {
+                emit_error!(arg.ty, "argument must be `web::Request`");
+            }
+            _ => {}
+        }
+    }
+}
+
+fn check_output(sig: &syn::Signature) {
+    match &sig.output {
+        syn::ReturnType::Default => {
+            emit_error!(sig.ident, "web handler must return a response";
+                help = "add `-> web::Response` or \
+                        `-> Result<web::Response, E>`");
+        }
+        syn::ReturnType::Type(_, ty) => {
+            if !is_named(ty, "Response") && !is_named(ty, "Result") {
+                emit_error!(ty,
+                    "return type must be `web::Response` or a `Result`");
+            }
+        }
+    }
+}
+
+pub fn expand(input: syn::ItemFn) -> TokenStream {
+    let sig = &input.sig;
+    if !sig.generics.params.is_empty() {
+        emit_error!(sig.generics, "no generics allowed on web handler");
+    }
+    if let Some(variadic) = &sig.variadic {
+        emit_error!(variadic, "no variadic arguments allowed on web handler");
+    }
+    check_input(sig);
+    check_output(sig);
+    // generated code only adds confusing errors on top of the ones above
+    proc_macro_error::abort_if_dirty();
+
+    let func_name = &sig.ident;
+    let hook_name = quote::format_ident!(
+        "_edgedb_sdk_init_web_handler_{}", func_name);
+    let call = if sig.asyncness.is_some() {
+        quote! { ::edgedb_sdk::executor::block_on(#func_name(req)) }
+    } else {
+        quote! { #func_name(req) }
+    };
+    // errors of the conversion point to the return type of the function
+    let output_span = match &sig.output {
+        syn::ReturnType::Default => sig.ident.span(),
+        syn::ReturnType::Type(_, ty) => ty.span(),
+    };
+    let convert = quote_spanned! {output_span=>
+        ::edgedb_sdk::web::IntoResponse::into_response(#call)
+    };
+    quote! {
+        #input
+
+        #[export_name = stringify!(#hook_name)]
+        extern fn #hook_name() {
+            ::edgedb_sdk::web::register_handler(|req| #convert);
+        }
+
+    }
+}
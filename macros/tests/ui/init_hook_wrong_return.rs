@@ -0,0 +1,6 @@
+#[edgedb_sdk_macros::init_hook]
+fn init() -> i32 {
+    0
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+mod web {
+    pub struct Request;
+    pub struct Response;
+}
+
+#[edgedb_sdk_macros::web_handler]
+fn handler() -> web::Response {
+    web::Response
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+mod web {
+    pub struct Request;
+    pub struct Response;
+}
+
+#[edgedb_sdk_macros::web_handler]
+fn handler(_req: web::Request) {
+}
+
+fn main() {}
@@ -0,0 +1,70 @@
+//! Key-value cache held by the server
+//!
+//! Unlike statics in the module, cached values are shared between all
+//! instances of all modules of the database and survive module reloads and
+//! crashes. Any entry can be evicted before its time-to-live expires, when
+//! the cache exceeds its size limit.
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//! use edgedb_sdk::cache;
+//!
+//! # fn main() -> Result<(), cache::Error> {
+//! let greeting = match cache::get("greeting") {
+//!     Some(value) => value,
+//!     None => {
+//!         let value = b"Hello".to_vec();
+//!         cache::set_with_ttl("greeting", &value, Duration::from_secs(60))?;
+//!         value
+//!     }
+//! };
+//! # Ok(())
+//! # }
+//! ```
+use std::time::Duration;
+
+wit_bindgen_rust::import!("../wit/edgedb_cache_v1.wit");
+
+use edgedb_cache_v1 as v1;
+
+/// Error storing value in the cache
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// Key and value together exceed the size limit of the cache
+    #[error("value is too large for the cache")]
+    TooLarge,
+}
+
+impl From<v1::Error> for Error {
+    fn from(e: v1::Error) -> Error {
+        match e {
+            v1::Error::TooLarge => Error::TooLarge,
+        }
+    }
+}
+
+/// Get a value, returns `None` if key is not found or has expired
+pub fn get(key: &str) -> Option<Vec<u8>> {
+    v1::get(key)
+}
+
+/// Store a value which never expires (but can still be evicted)
+pub fn set(key: &str, value: &[u8]) -> Result<(), Error> {
+    v1::set(key, value, None)?;
+    Ok(())
+}
+
+/// Store a value which expires after `ttl`
+pub fn set_with_ttl(key: &str, value: &[u8], ttl: Duration)
+    -> Result<(), Error>
+{
+    let ttl_ms = ttl.as_millis().min(u64::MAX.into()) as u64;
+    v1::set(key, value, Some(ttl_ms))?;
+    Ok(())
+}
+
+/// Delete a value, returns `true` if key existed
+pub fn delete(key: &str) -> bool {
+    v1::delete(key)
+}
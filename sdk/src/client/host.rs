@@ -0,0 +1,323 @@
+//! Client sending queries to the host
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use bytes::{Bytes, BytesMut};
+use edgedb_errors::{ClientError, ProtocolEncodingError, NoResultExpected};
+use edgedb_errors::{NoDataError};
+use edgedb_protocol::model::Json;
+
+use crate::client::{Cardinality, CommandDataDescription, ProtocolVersion};
+use crate::client::{Encoder, Error, ErrorKind, QueryArgs, QueryResult};
+
+wit_bindgen_rust::import!("../wit/edgedb-client-v1.wit");
+
+use edgedb_client_v1 as v1;
+
+/// Type of the rows returned by the query methods
+///
+/// Implemented for every [`QueryResult`]. With the `testing` feature, when
+/// compiled natively, rows must also be `'static`.
+pub trait QueryRow: QueryResult {}
+impl<T: QueryResult> QueryRow for T {}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    client: Arc<v1::Client>,
+}
+
+#[derive(Debug)]
+pub struct Transaction {
+    client: Client,
+    transaction: Option<v1::Transaction>,
+}
+
+pub(crate) trait StartQuery {
+    fn prepare(self, flags: v1::CompilationFlags, query: &str)
+        -> Result<(v1::Query, v1::PrepareComplete), v1::Error>;
+}
+
+impl Client {
+    pub fn connect() -> Client {
+        Client {
+            client: Arc::new(v1::Client::connect()),
+        }
+    }
+}
+
+impl Transaction {
+    pub fn new(client: &Client) -> Transaction {
+        Transaction {
+            client: client.clone(),
+            transaction: None,
+        }
+    }
+    /// Commits transaction if any query was executed
+    pub fn commit(&mut self) -> Result<(), Error> {
+        if let Some(tx) = self.transaction.take() {
+            tx.commit().map_err(|e| e.into_err())?;
+        }
+        Ok(())
+    }
+    /// Rolls back transaction if any query was executed
+    pub fn rollback(&mut self) -> Result<(), Error> {
+        if let Some(tx) = self.transaction.take() {
+            tx.rollback().map_err(|e| e.into_err())?;
+        }
+        Ok(())
+    }
+}
+
+impl v1::Error {
+    fn into_err(self) -> Error {
+        let mut err = Error::from_code(self.code);
+        for msg in self.messages {
+            err = err.context(msg);
+        }
+        // TODO(tailhook) headers
+        return err;
+    }
+}
+
+impl StartQuery for &'_ Client {
+    fn prepare(self, flags: v1::CompilationFlags, query: &str)
+        -> Result<(v1::Query, v1::PrepareComplete), v1::Error>
+    {
+        self.client.prepare(flags, query)
+    }
+}
+
+impl StartQuery for &'_ mut Transaction {
+    fn prepare(self, flags: v1::CompilationFlags, query: &str)
+        -> Result<(v1::Query, v1::PrepareComplete), v1::Error>
+    {
+        if self.transaction.is_none() {
+            self.transaction = Some(self.client.client.transaction()?);
+        }
+        self.transaction.as_mut().unwrap().prepare(flags, query)
+    }
+}
+
+pub(crate) fn execute_query<T: StartQuery, R, A>(target: T,
+    query: &str, arguments: &A)
+    -> Result<Vec<R>, Error>
+    where A: QueryArgs,
+          R: QueryResult,
+{
+    let flags = v1::CompilationFlags {
+        implicit_limit: None,
+        implicit_typenames: false,
+        implicit_typeids: false,
+        explicit_objectids: true,
+        // host app will remove everything else anyway
+        allow_capabilities: v1::Capabilities::MODIFICATIONS,
+        io_format: v1::IoFormat::Binary,
+        expected_cardinality: v1::Cardinality::Many,
+    };
+    let (query, _prepare_info) = target.prepare(flags, query)
+        .map_err(|e| e.into_err())?;
+    let desc = query.describe_data().map_err(|e| e.into_err())?;
+    let desc = CommandDataDescription::try_from(desc)?;
+    let inp_desc = desc.input()
+        .map_err(ProtocolEncodingError::with_source)?;
+
+    let mut arg_buf = BytesMut::with_capacity(8);
+    arguments.encode(&mut Encoder::new(
+        &inp_desc.as_query_arg_context(),
+        &mut arg_buf,
+    ))?;
+
+    let data = query.execute(&arg_buf).map_err(|e| e.into_err())?;
+
+    let out_desc = desc.output()
+        .map_err(ProtocolEncodingError::with_source)?;
+    match out_desc.root_pos() {
+        Some(root_pos) => {
+            let ctx = out_desc.as_queryable_context();
+            let mut state = R::prepare(&ctx, root_pos)?;
+            let rows = data.chunks.into_iter()
+               .map(|chunk| R::decode(&mut state, &chunk.into()))
+               .collect::<Result<_, _>>()?;
+            Ok(rows)
+        }
+        None => Err(NoResultExpected::build()),
+    }
+}
+
+pub(crate) fn execute_query_single<T: StartQuery, R, A>(target: T,
+    query: &str, arguments: &A)
+    -> Result<Option<R>, Error>
+    where A: QueryArgs,
+          R: QueryResult,
+{
+    let flags = v1::CompilationFlags {
+        implicit_limit: None,
+        implicit_typenames: false,
+        implicit_typeids: false,
+        explicit_objectids: true,
+        // host app will remove everything else anyway
+        allow_capabilities: v1::Capabilities::MODIFICATIONS,
+        io_format: v1::IoFormat::Binary,
+        expected_cardinality: v1::Cardinality::AtMostOne,
+    };
+    let (query, _prepare_info) = target.prepare(flags, query)
+        .map_err(|e| e.into_err())?;
+    let desc = query.describe_data().map_err(|e| e.into_err())?;
+    let desc = CommandDataDescription::try_from(desc)?;
+    let inp_desc = desc.input()
+        .map_err(ProtocolEncodingError::with_source)?;
+
+    let mut arg_buf = BytesMut::with_capacity(8);
+    arguments.encode(&mut Encoder::new(
+        &inp_desc.as_query_arg_context(),
+        &mut arg_buf,
+    ))?;
+
+    let data = query.execute(&arg_buf).map_err(|e| e.into_err())?;
+
+    let out_desc = desc.output()
+        .map_err(ProtocolEncodingError::with_source)?;
+    match out_desc.root_pos() {
+        Some(root_pos) => {
+            let ctx = out_desc.as_queryable_context();
+            let mut state = R::prepare(&ctx, root_pos)?;
+            let bytes = data.chunks.into_iter().next();
+            if let Some(bytes) = bytes {
+                Ok(Some(R::decode(&mut state, &Bytes::from(bytes))?))
+            } else {
+                Ok(None)
+            }
+        }
+        None => Err(NoResultExpected::build()),
+    }
+}
+
+pub(crate) fn execute_query_json<T: StartQuery>(target: T,
+    query: &str, arguments: &impl QueryArgs)
+    -> Result<Json, Error>
+{
+    let flags = v1::CompilationFlags {
+        implicit_limit: None,
+        implicit_typenames: false,
+        implicit_typeids: false,
+        explicit_objectids: true,
+        // host app will remove everything else anyway
+        allow_capabilities: v1::Capabilities::MODIFICATIONS,
+        io_format: v1::IoFormat::Json,
+        expected_cardinality: v1::Cardinality::Many,
+    };
+    let (query, _prepare_info) = target.prepare(flags, query)
+        .map_err(|e| e.into_err())?;
+    let desc = query.describe_data().map_err(|e| e.into_err())?;
+    let desc = CommandDataDescription::try_from(desc)?;
+    let inp_desc = desc.input()
+        .map_err(ProtocolEncodingError::with_source)?;
+
+    let mut arg_buf = BytesMut::with_capacity(8);
+    arguments.encode(&mut Encoder::new(
+        &inp_desc.as_query_arg_context(),
+        &mut arg_buf,
+    ))?;
+
+    let data = query.execute(&arg_buf).map_err(|e| e.into_err())?;
+
+    let out_desc = desc.output()
+        .map_err(ProtocolEncodingError::with_source)?;
+    match out_desc.root_pos() {
+        Some(root_pos) => {
+            let ctx = out_desc.as_queryable_context();
+            // JSON objects are returned as strings :(
+            let mut state = String::prepare(&ctx, root_pos)?;
+            let bytes = data.chunks.into_iter().next();
+            if let Some(bytes) = bytes {
+                // we trust database to produce valid json
+                let s = String::decode(&mut state, &Bytes::from(bytes))?;
+                Ok(unsafe { Json::new_unchecked(s) })
+            } else {
+                Err(NoDataError::with_message(
+                    "query row returned zero results"))
+            }
+        }
+        None => Err(NoResultExpected::build()),
+    }
+}
+
+pub(crate) fn execute_query_single_json<T: StartQuery>(target: T,
+    query: &str, arguments: &impl QueryArgs)
+    -> Result<Option<Json>, Error>
+{
+    let flags = v1::CompilationFlags {
+        implicit_limit: None,
+        implicit_typenames: false,
+        implicit_typeids: false,
+        explicit_objectids: true,
+        // host app will remove everything else anyway
+        allow_capabilities: v1::Capabilities::MODIFICATIONS,
+        io_format: v1::IoFormat::Json,
+        expected_cardinality: v1::Cardinality::AtMostOne,
+    };
+    let (query, _prepare_info) = target.prepare(flags, query)
+        .map_err(|e| e.into_err())?;
+    let desc = query.describe_data().map_err(|e| e.into_err())?;
+    let desc = CommandDataDescription::try_from(desc)?;
+    let inp_desc = desc.input()
+        .map_err(ProtocolEncodingError::with_source)?;
+
+    let mut arg_buf = BytesMut::with_capacity(8);
+    arguments.encode(&mut Encoder::new(
+        &inp_desc.as_query_arg_context(),
+        &mut arg_buf,
+    ))?;
+
+    let data = query.execute(&arg_buf).map_err(|e| e.into_err())?;
+
+    let out_desc = desc.output()
+        .map_err(ProtocolEncodingError::with_source)?;
+    match out_desc.root_pos() {
+        Some(root_pos) => {
+            let ctx = out_desc.as_queryable_context();
+            // JSON objects are returned as strings :(
+            let mut state = String::prepare(&ctx, root_pos)?;
+            let bytes = data.chunks.into_iter().next();
+            if let Some(bytes) = bytes {
+                // we trust database to produce valid json
+                let s = String::decode(&mut state, &Bytes::from(bytes))?;
+                Ok(Some(unsafe { Json::new_unchecked(s) }))
+            } else {
+                Ok(None)
+            }
+        }
+        None => Err(NoResultExpected::build()),
+    }
+}
+
+impl TryFrom<v1::DataDescription> for CommandDataDescription {
+    type Error = Error;
+    fn try_from(src: v1::DataDescription)
+        -> Result<CommandDataDescription, Error>
+    {
+        Ok(CommandDataDescription {
+            proto: ProtocolVersion::new(src.proto.0, src.proto.1),
+            headers: HashMap::new(),
+            result_cardinality: src.result_cardinality.into(),
+            input_typedesc_id: src.input_typedesc_id.parse()
+                .map_err(ClientError::with_source)?,
+            input_typedesc: src.input_typedesc.into(),
+            output_typedesc_id: src.output_typedesc_id.parse()
+                .map_err(ClientError::with_source)?,
+            output_typedesc: src.output_typedesc.into(),
+        })
+    }
+}
+
+impl From<v1::Cardinality> for Cardinality {
+    fn from(src: v1::Cardinality) -> Cardinality {
+        match src {
+            v1::Cardinality::NoResult => Cardinality::NoResult,
+            v1::Cardinality::AtMostOne => Cardinality::AtMostOne,
+            v1::Cardinality::One => Cardinality::One,
+            v1::Cardinality::Many => Cardinality::Many,
+            v1::Cardinality::AtLeastOne => Cardinality::AtLeastOne,
+        }
+    }
+}
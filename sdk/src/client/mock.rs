@@ -0,0 +1,37 @@
+//! Client replying with results scripted by the
+//! [`MockClient`](crate::testing::MockClient)
+use crate::client::{Error, QueryResult};
+
+pub(crate) use crate::testing::{execute_query, execute_query_single};
+pub(crate) use crate::testing::{execute_query_json, execute_query_single_json};
+
+/// Type of the rows returned by the query methods
+///
+/// Implemented for every `'static` [`QueryResult`], so that rows can be
+/// scripted by the [`MockClient`](crate::testing::MockClient).
+pub trait QueryRow: QueryResult + 'static {}
+impl<T: QueryResult + 'static> QueryRow for T {}
+
+#[derive(Debug, Clone)]
+pub struct Client;
+
+#[derive(Debug)]
+pub struct Transaction;
+
+impl Client {
+    pub fn connect() -> Client {
+        Client
+    }
+}
+
+impl Transaction {
+    pub fn new(_client: &Client) -> Transaction {
+        Transaction
+    }
+    pub fn commit(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+    pub fn rollback(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -1,13 +1,11 @@
 
 use edgedb_protocol::model::Json;
-use edgedb_protocol::QueryResult;
 use edgedb_protocol::query_arg::QueryArgs;
 use edgedb_errors::{SHOULD_RETRY};
 use edgedb_errors::{NoDataError};
 
-use crate::client::v1;
-use crate::client::{Client, Error, ErrorKind};
-use crate::client::{StartQuery, execute_query, execute_query_single};
+use crate::client::{backend, Client, Error, ErrorKind, QueryRow};
+use crate::client::{execute_query, execute_query_single};
 use crate::client::{execute_query_json, execute_query_single_json};
 
 // TODO(tailhook) temporary
@@ -22,8 +20,7 @@ const MAX_ITERATIONS: u32 = 3;
 #[derive(Debug)]
 pub struct Transaction {
     iteration: u32,
-    client: Client,
-    transaction: Option<v1::Transaction>,
+    inner: backend::Transaction,
 }
 
 pub(crate) fn transaction<T, F>(cli: &Client, mut body: F)
@@ -32,24 +29,19 @@ pub(crate) fn transaction<T, F>(cli: &Client, mut body: F)
 {
     let mut tx = Transaction {
         iteration: 0,
-        client: cli.clone(),
-        transaction: None,
+        inner: backend::Transaction::new(&cli.inner),
     };
     'transaction: loop {
         let result = body(&mut tx);
         match result {
             Ok(val) => {
                 log::debug!("Comitting transaction");
-                if let Some(tx) = tx.transaction.take() {
-                    tx.commit().map_err(|e| e.into_err())?;
-                }
+                tx.inner.commit()?;
                 return Ok(val)
             }
             Err(e) => {
                 log::debug!("Rolling back transaction on error");
-                if let Some(tx) = tx.transaction.take() {
-                    tx.rollback().map_err(|e| e.into_err())?;
-                }
+                tx.inner.rollback()?;
                 for e in e.chain() {
                     if let Some(e) = e.downcast_ref::<Error>() {
                         if e.has_tag(SHOULD_RETRY) {
@@ -68,17 +60,6 @@ pub(crate) fn transaction<T, F>(cli: &Client, mut body: F)
     }
 }
 
-impl StartQuery for &'_ mut Transaction {
-    fn prepare(self, flags: v1::CompilationFlags, query: &str)
-        -> Result<(v1::Query, v1::PrepareComplete), v1::Error>
-    {
-        if self.transaction.is_none() {
-            self.transaction = Some(self.client.client.transaction()?);
-        }
-        self.transaction.as_mut().unwrap().prepare(flags, query)
-    }
-}
-
 impl Transaction {
     /// Execute a query and return a collection of results.
     ///
@@ -96,9 +77,9 @@ impl Transaction {
     pub fn query<R, A>(&mut self, query: &str, arguments: &A)
         -> Result<Vec<R>, Error>
         where A: QueryArgs,
-              R: QueryResult,
+              R: QueryRow,
     {
-        execute_query(self, query, arguments)
+        execute_query(&mut self.inner, query, arguments)
     }
 
     /// Execute a query and return a single result
@@ -120,9 +101,9 @@ impl Transaction {
     pub fn query_single<R, A>(&mut self, query: &str, arguments: &A)
         -> Result<Option<R>, Error>
         where A: QueryArgs,
-              R: QueryResult,
+              R: QueryRow,
     {
-        execute_query_single(self, query, arguments)
+        execute_query_single(&mut self.inner, query, arguments)
     }
 
     /// Execute a query and return a single result
@@ -154,7 +135,7 @@ impl Transaction {
                                              query: &str, arguments: &A)
         -> Result<R, Error>
         where A: QueryArgs,
-              R: QueryResult,
+              R: QueryRow,
     {
         self.query_single(query, arguments)?
             .ok_or_else(|| NoDataError::with_message(
@@ -165,7 +146,7 @@ impl Transaction {
     pub fn query_json(&mut self, query: &str, arguments: &impl QueryArgs)
         -> Result<Json, Error>
     {
-        execute_query_json(self, query, arguments)
+        execute_query_json(&mut self.inner, query, arguments)
     }
 
     /// Execute a query and return a single result as JSON.
@@ -178,7 +159,7 @@ impl Transaction {
                                    query: &str, arguments: &impl QueryArgs)
         -> Result<Option<Json>, Error>
     {
-        execute_query_single_json(self, query, arguments)
+        execute_query_single_json(&mut self.inner, query, arguments)
     }
 
     /// Execute a query and return a single result as JSON.
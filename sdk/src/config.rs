@@ -0,0 +1,77 @@
+//! Configuration values of the module set on the server
+//!
+//! Values are set per database and module, either in the config file of
+//! the server or at runtime. They are read at the time of the call, so there
+//! is no need to restart the module to pick up changes:
+//!
+//! ```rust,no_run
+//! use edgedb_sdk::config;
+//!
+//! # fn main() -> Result<(), config::Error> {
+//! let page_size = config::get::<u32>("page_size")?.unwrap_or(20);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Use [`on_change`] to update state derived from the values.
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use once_cell::sync::Lazy;
+
+wit_bindgen_rust::import!("../wit/edgedb_config_v1.wit");
+
+use edgedb_config_v1 as v1;
+
+type Callback = Arc<dyn Fn(&[String]) + Send + Sync>;
+
+pub(crate) static CALLBACKS: Lazy<Mutex<Vec<Callback>>> =
+    Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Value can't be parsed into the requested type
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[error("invalid value of config key {key:?}: {message}")]
+pub struct Error {
+    key: String,
+    message: String,
+}
+
+impl Error {
+    /// Key of the invalid value
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+}
+
+/// Get a raw value, returns `None` if value is not set
+pub fn get_str(key: &str) -> Option<String> {
+    v1::get(key)
+}
+
+/// Get a value parsed with [`FromStr`], returns `None` if value is not set
+pub fn get<T>(key: &str) -> Result<Option<T>, Error>
+    where T: FromStr,
+          T::Err: fmt::Display,
+{
+    match v1::get(key) {
+        Some(value) => value.parse().map(Some).map_err(|e| Error {
+            key: key.into(),
+            message: format!("{:#}", e),
+        }),
+        None => Ok(None),
+    }
+}
+
+/// Keys of all values set for the module
+pub fn keys() -> Vec<String> {
+    v1::keys()
+}
+
+/// Register a function called when values are changed
+///
+/// Function receives keys that were changed, added or removed. It's not
+/// called for values set before the module was started.
+pub fn on_change(f: impl Fn(&[String]) + Send + Sync + 'static) {
+    CALLBACKS.lock().expect("config is not poisoned").push(Arc::new(f));
+}
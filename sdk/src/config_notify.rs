@@ -0,0 +1,19 @@
+use crate::config::CALLBACKS;
+
+wit_bindgen_rust::export!("../wit/edgedb_config_notify_v1.wit");
+
+use edgedb_config_notify_v1 as v1;
+
+struct EdgedbConfigNotifyV1 {
+}
+
+impl v1::EdgedbConfigNotifyV1 for EdgedbConfigNotifyV1 {
+    fn on_change(keys: Vec<String>) {
+        // lock is not held while running, so callback can register others
+        let callbacks = CALLBACKS.lock().expect("config is not poisoned")
+            .clone();
+        for callback in callbacks {
+            callback(&keys);
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! Minimal executor for async code
+//!
+//! All host calls are synchronous, so there is no reactor: futures are
+//! polled until they complete. This allows using `async` code (e.g. an
+//! [`async` web handler](crate::web::handler)) as long as it only awaits
+//! futures that are woken by the code running in the same module.
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll, Wake, Waker};
+
+struct Flag(AtomicBool);
+
+impl Wake for Flag {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Run future to completion
+///
+/// # Panics
+///
+/// Panics if future is pending and was not woken, as nothing else can make
+/// progress in the meantime.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let flag = Arc::new(Flag(AtomicBool::new(false)));
+    let waker = Waker::from(flag.clone());
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match Pin::as_mut(&mut future).poll(&mut cx) {
+            Poll::Ready(value) => return value,
+            Poll::Pending => {
+                if !flag.0.swap(false, Ordering::SeqCst) {
+                    panic!("future is blocked on something that is not \
+                            available in WebAssembly worker");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,26 @@
+//! Support code for the code generated by hook macros
+use std::fmt;
+
+/// Converts result of the fallible init hook into the status code
+///
+/// Error is logged here, host only receives the status.
+pub fn init_result<E: fmt::Display>(name: &str, result: Result<(), E>) -> i32 {
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            log::error!("Init hook {:?} failed: {:#}", name, e);
+            1
+        }
+    }
+}
+
+/// Converts result of the test into the status code
+pub fn test_result<E: fmt::Display>(name: &str, result: Result<(), E>) -> i32 {
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            log::error!("Test {:?} failed: {:#}", name, e);
+            1
+        }
+    }
+}
@@ -0,0 +1,115 @@
+//! Outbound HTTP requests
+//!
+//! Module can only send requests to the hosts allowed for it in the server
+//! configuration, any other request fails with [`Error::NotAllowed`].
+//!
+//! ```rust,no_run
+//! use edgedb_sdk::http_client;
+//!
+//! # fn main() -> Result<(), http_client::Error> {
+//! let response = http_client::get("https://example.com/")?;
+//! log::info!("Status: {}", response.status());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Redirects are not followed, and response body is always read in full.
+use std::time::Duration;
+
+wit_bindgen_rust::import!("../wit/edgedb_http_client_v1.wit");
+
+use edgedb_http_client_v1 as v1;
+
+/// Error sending request
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// Host is not in the allowlist of the module
+    #[error("request not allowed: {0}")]
+    NotAllowed(String),
+    /// Request is malformed (e.g. invalid URI or header)
+    #[error("invalid request: {0}")]
+    InvalidRequest(String),
+    /// Request hasn't finished in time
+    #[error("request timed out")]
+    Timeout,
+    /// Response body exceeds the size limit set by the server
+    #[error("response is too large")]
+    TooLarge,
+    /// Connection or protocol error
+    #[error("request failed: {0}")]
+    Failed(String),
+}
+
+/// HTTP client
+///
+/// Client has no state except the settings, so it's cheap to create.
+#[derive(Debug, Clone, Default)]
+pub struct Client {
+    timeout: Option<Duration>,
+}
+
+impl From<v1::Error> for Error {
+    fn from(e: v1::Error) -> Error {
+        match e {
+            v1::Error::NotAllowed(msg) => Error::NotAllowed(msg),
+            v1::Error::InvalidRequest(msg) => Error::InvalidRequest(msg),
+            v1::Error::Timeout => Error::Timeout,
+            v1::Error::TooLarge => Error::TooLarge,
+            v1::Error::Failed(msg) => Error::Failed(msg),
+        }
+    }
+}
+
+impl Client {
+    /// Create a client with default settings
+    pub fn new() -> Client {
+        Client::default()
+    }
+    /// Set timeout of each request (including reading response body)
+    ///
+    /// The server has its own limit, the smaller one is used.
+    pub fn timeout(mut self, timeout: Duration) -> Client {
+        self.timeout = Some(timeout);
+        self
+    }
+    /// Send request and read the response
+    pub fn send(&self, req: http::Request<Vec<u8>>)
+        -> Result<http::Response<Vec<u8>>, Error>
+    {
+        let (parts, body) = req.into_parts();
+        let uri = parts.uri.to_string();
+        let headers = parts.headers.iter()
+            .map(|(name, value)| (name.as_str().as_bytes(), value.as_bytes()))
+            .collect::<Vec<_>>();
+        let response = v1::fetch(v1::Request {
+            method: parts.method.as_str(),
+            uri: &uri,
+            headers: &headers,
+            body: &body,
+            timeout_ms: self.timeout
+                .map(|t| t.as_millis().min(u32::MAX.into()) as u32),
+        })?;
+        let mut bld = http::Response::builder().status(response.status_code);
+        for (name, value) in response.headers {
+            bld = bld.header(name, value);
+        }
+        bld.body(response.body).map_err(|e| Error::Failed(e.to_string()))
+    }
+}
+
+/// Send `GET` request with default settings
+pub fn get(uri: &str) -> Result<http::Response<Vec<u8>>, Error> {
+    let req = http::Request::get(uri).body(Vec::new())
+        .map_err(|e| Error::InvalidRequest(e.to_string()))?;
+    Client::new().send(req)
+}
+
+/// Send `POST` request with default settings
+pub fn post(uri: &str, body: impl Into<Vec<u8>>)
+    -> Result<http::Response<Vec<u8>>, Error>
+{
+    let req = http::Request::post(uri).body(body.into())
+        .map_err(|e| Error::InvalidRequest(e.to_string()))?;
+    Client::new().send(req)
+}
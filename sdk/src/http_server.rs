@@ -1,41 +1,80 @@
-use crate::web::{self, WEB_HANDLER};
+use crate::web::{self, BodyReader, RequestMeta, WEB_HANDLER};
 
-wit_bindgen_rust::export!("../wit/edgedb_http_server_v1.wit");
+wit_bindgen_rust::export!("../wit/edgedb_http_server_v2.wit");
 
-use edgedb_http_server_v1 as v1;
+use edgedb_http_server_v2 as v2;
 
-struct EdgedbHttpServerV1 {
+struct EdgedbHttpServerV2 {
 }
 
-impl v1::EdgedbHttpServerV1 for EdgedbHttpServerV1 {
-    fn handle_request(req: v1::Request) -> v1::Response {
+fn simple_response(status_code: u16, body: &str) -> v2::Response {
+    v2::Response {
+        status_code,
+        headers: vec![],
+        body: v2::Body::Full(body.as_bytes().to_vec()),
+        has_deferred: false,
+    }
+}
+
+impl v2::EdgedbHttpServerV2 for EdgedbHttpServerV2 {
+    fn handle_http_request(req: v2::Request) -> v2::Response {
         if let Some(handler) = WEB_HANDLER.get() {
+            let reader = BodyReader::request_body();
+            let (body, body_reader) = if handler.streams_request_body() {
+                (Vec::new(), Some(reader))
+            } else {
+                match reader.read_to_end() {
+                    Ok(body) => (body, None),
+                    Err(e) => {
+                        log::debug!("Error reading request body: {:#}", e);
+                        return simple_response(400, "Bad Request");
+                    }
+                }
+            };
             let mut bld = http::Request::builder();
             bld = bld.method(&req.method[..]);
             bld = bld.uri(&req.uri);
             for (k, v) in req.headers {
                 bld = bld.header(k, v);
             }
-            let inner = bld.body(req.body).expect("can build request");
+            let inner = bld.body(body).expect("can build request");
+            let meta = RequestMeta {
+                database: req.database,
+                module_name: req.module_name,
+                mount_prefix: req.mount_prefix,
+                scheme: req.scheme,
+                host: req.host,
+                remote_addr: req.remote_addr.and_then(|a| a.parse().ok()),
+            };
 
-            let resp = handler(web::Request {
+            let resp = handler.handle(web::Request {
                 inner,
+                meta,
+                body_reader,
             });
 
-            v1::Response {
-                status_code: resp.status().as_u16(),
-                headers: resp.headers().iter().map(|(key, val)| {
+            let (parts, body) = resp.into_parts();
+            let body = match body.into_full_or_pending() {
+                Some(data) => v2::Body::Full(data),
+                None => v2::Body::Stream,
+            };
+            v2::Response {
+                status_code: parts.status.as_u16(),
+                headers: parts.headers.iter().map(|(key, val)| {
                     (key.as_str().as_bytes().to_vec(), val.as_bytes().to_vec())
                 }).collect(),
-                body: resp.into_body(),
+                body,
+                has_deferred: web::defer::has_pending(),
             }
         } else {
-            v1::Response {
-                status_code: 404,
-                // TODO(tailhook) only in debug mode
-                headers: vec![],
-                body: b"Page Not Found (Web handler is not set)".to_vec(),
-            }
+            // TODO(tailhook) only in debug mode
+            simple_response(404, "Page Not Found (Web handler is not set)")
         }
     }
+    fn write_http_body() {
+        web::body::write_pending();
+    }
+    fn run_deferred() {
+        web::defer::run_pending();
+    }
 }
@@ -0,0 +1,98 @@
+//! Requests to other modules of the same database
+//!
+//! Request is handled by the HTTP handler of the target module within the
+//! server process, as if it was sent to `/db/<database>/wasm/<module>/<path>`.
+//!
+//! ```rust,no_run
+//! use edgedb_sdk::invoke;
+//!
+//! # fn main() -> Result<(), invoke::Error> {
+//! let response = invoke::get("users", "/profile?id=42")?;
+//! log::info!("Status: {}", response.status());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! `X-Request-Id` header of the request being handled is passed to the
+//! sub-request, unless it's set explicitly. Module can't invoke itself,
+//! neither directly nor through other modules, and the depth of nested
+//! invocations is limited.
+wit_bindgen_rust::import!("../wit/edgedb_invoke_v1.wit");
+
+use edgedb_invoke_v1 as v1;
+
+/// Error invoking module
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// There is no such module or it has no HTTP handler
+    #[error("module not found: {0}")]
+    NotFound(String),
+    /// Request is malformed (e.g. path doesn't start with a slash)
+    #[error("invalid request: {0}")]
+    InvalidRequest(String),
+    /// Target module is already waiting for this request
+    #[error("invocation loop detected")]
+    LoopDetected,
+    /// Too many nested invocations
+    #[error("invocations are nested too deep")]
+    TooDeep,
+    /// Module failed to initialize or to handle the request
+    #[error("invocation failed: {0}")]
+    Failed(String),
+}
+
+impl From<v1::Error> for Error {
+    fn from(e: v1::Error) -> Error {
+        match e {
+            v1::Error::NotFound(msg) => Error::NotFound(msg),
+            v1::Error::InvalidRequest(msg) => Error::InvalidRequest(msg),
+            v1::Error::LoopDetected => Error::LoopDetected,
+            v1::Error::TooDeep => Error::TooDeep,
+            v1::Error::Failed(msg) => Error::Failed(msg),
+        }
+    }
+}
+
+/// Send request to the HTTP handler of another module
+///
+/// URI of the request must only contain path and query within the target
+/// module, e.g. `/items?limit=10`. Response body is always read in full.
+pub fn send(module_name: &str, req: http::Request<Vec<u8>>)
+    -> Result<http::Response<Vec<u8>>, Error>
+{
+    let (parts, body) = req.into_parts();
+    let path = parts.uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    let headers = parts.headers.iter()
+        .map(|(name, value)| (name.as_str().as_bytes(), value.as_bytes()))
+        .collect::<Vec<_>>();
+    let response = v1::invoke(module_name, v1::Request {
+        method: parts.method.as_str(),
+        path,
+        headers: &headers,
+        body: &body,
+    })?;
+    let mut bld = http::Response::builder().status(response.status_code);
+    for (name, value) in response.headers {
+        bld = bld.header(name, value);
+    }
+    bld.body(response.body).map_err(|e| Error::Failed(e.to_string()))
+}
+
+/// Send `GET` request to another module
+pub fn get(module_name: &str, path: &str)
+    -> Result<http::Response<Vec<u8>>, Error>
+{
+    let req = http::Request::get(path).body(Vec::new())
+        .map_err(|e| Error::InvalidRequest(e.to_string()))?;
+    send(module_name, req)
+}
+
+/// Send `POST` request to another module
+pub fn post(module_name: &str, path: &str, body: impl Into<Vec<u8>>)
+    -> Result<http::Response<Vec<u8>>, Error>
+{
+    let req = http::Request::post(path).body(body.into())
+        .map_err(|e| Error::InvalidRequest(e.to_string()))?;
+    send(module_name, req)
+}
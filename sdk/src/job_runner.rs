@@ -0,0 +1,21 @@
+use crate::jobs::JOBS;
+
+wit_bindgen_rust::export!("../wit/edgedb_job_runner_v1.wit");
+
+use edgedb_job_runner_v1 as v1;
+
+struct EdgedbJobRunnerV1 {
+}
+
+impl v1::EdgedbJobRunnerV1 for EdgedbJobRunnerV1 {
+    fn run_job(name: String, payload: String) -> Result<(), String> {
+        // lock is not held while running, so job can register other jobs
+        let func = JOBS.lock().expect("jobs are not poisoned")
+            .get(&name)
+            .cloned();
+        match func {
+            Some(func) => func(payload),
+            None => Err(format!("no job handler {:?}", name)),
+        }
+    }
+}
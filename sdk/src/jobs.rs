@@ -0,0 +1,114 @@
+//! Background jobs run by the server
+//!
+//! Jobs are stored in the database, so a job enqueued within a transaction
+//! is run only if the transaction is committed. Job type must be defined in
+//! the schema:
+//!
+//! ```edgeql
+//! type WasmJob {
+//!     required property module_name -> str;
+//!     required property name -> str;
+//!     required property payload -> str;
+//!     required property run_at -> datetime {
+//!         default := datetime_current();
+//!     };
+//!     required property attempts -> int64 {
+//!         default := 0;
+//!     };
+//!     property last_error -> str;
+//!     required property dead -> bool {
+//!         default := false;
+//!     };
+//!     index on (.run_at);
+//! }
+//! ```
+//!
+//! Use [`job`](macro@crate::job) attribute to register a handler:
+//!
+//! ```rust,no_run
+//! use edgedb_sdk::jobs;
+//!
+//! #[edgedb_sdk::job]
+//! fn send_welcome(user_id: String) -> anyhow::Result<()> {
+//!     log::info!("Sending welcome email to {}", user_id);
+//!     Ok(())
+//! }
+//!
+//! # fn signup() -> Result<(), edgedb_sdk::client::Error> {
+//! jobs::enqueue("send_welcome", "42")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Job is run by the module that enqueued it. Failed job is retried with
+//! exponential backoff, after 5 failed attempts it's kept in the database
+//! with `dead` set to `true`. Job may also run more than once, if the server
+//! is restarted while running it, so handlers should be idempotent.
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use once_cell::sync::Lazy;
+
+use crate::client::{Client, Error, Transaction, create_client};
+use crate::schedule::TaskResult;
+
+wit_bindgen_rust::import!("../wit/edgedb_jobs_v1.wit");
+
+use edgedb_jobs_v1 as v1;
+
+type JobFn = Arc<dyn Fn(String) -> Result<(), String> + Send + Sync>;
+
+pub(crate) static JOBS: Lazy<Mutex<BTreeMap<String, JobFn>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+static CLIENT: Lazy<Client> = Lazy::new(create_client);
+static MODULE_NAME: Lazy<String> = Lazy::new(v1::module_name);
+
+const INSERT_QUERY: &str = "
+    INSERT WasmJob {
+        module_name := <str>$0,
+        name := <str>$1,
+        payload := <str>$2,
+    }
+";
+
+fn arguments(name: &str, payload: &str) -> (String, String, String) {
+    (MODULE_NAME.clone(), name.to_string(), payload.to_string())
+}
+
+/// Enqueue a job to run in background as soon as possible
+///
+/// Use [`enqueue_in`] to enqueue a job within a transaction.
+pub fn enqueue(name: &str, payload: &str) -> Result<(), Error> {
+    CLIENT.query_json(INSERT_QUERY, &arguments(name, payload))?;
+    Ok(())
+}
+
+/// Enqueue a job within a transaction
+///
+/// Job is run only if the transaction is committed.
+pub fn enqueue_in(transaction: &mut Transaction, name: &str, payload: &str)
+    -> Result<(), Error>
+{
+    transaction.query_json(INSERT_QUERY, &arguments(name, payload))?;
+    Ok(())
+}
+
+/// Register a job handler
+///
+/// This is what [`job`](macro@crate::job) attribute does. Must be called
+/// from an [init hook](macro@crate::init_hook). Handler returns the same
+/// types as a [scheduled task](crate::schedule::TaskResult) does.
+///
+/// # Panics
+///
+/// Panics if a handler with the same name is already registered.
+pub fn register<F, R>(name: &str, f: F)
+    where F: Fn(String) -> R + Send + Sync + 'static,
+          R: TaskResult,
+{
+    let mut jobs = JOBS.lock().expect("jobs are not poisoned");
+    assert!(!jobs.contains_key(name),
+            "job {:?} is already registered", name);
+    jobs.insert(name.into(), Arc::new(move |payload| f(payload).into_result()));
+}
@@ -40,19 +40,45 @@
 #![warn(missing_debug_implementations, missing_docs)]
 
 mod http_server;
+mod websocket;
+mod scheduler;
+mod config_notify;
+mod log_level;
+#[cfg(feature="client")]
+mod job_runner;
 mod bug;
 #[cfg(feature="bindgen")]
 mod bindgen;
 
 #[cfg(feature="client")]
 pub mod client;
+#[cfg(feature="client")]
+pub mod jobs;
 pub mod web;
+pub mod cache;
+pub mod config;
+pub mod secrets;
+pub mod metrics;
 pub mod log;
+pub mod executor;
+pub mod http_client;
+pub mod invoke;
+pub mod schedule;
+#[doc(hidden)]
+pub mod hooks;
+#[cfg(all(feature="testing", not(target_arch="wasm32")))]
+pub mod testing;
+#[cfg(feature="tracing")]
+pub mod tracing;
 
-pub use edgedb_sdk_macros::init_hook;
+pub use edgedb_sdk_macros::{init_hook, shutdown_hook, schedule, test};
+#[cfg(feature="client")]
+pub use edgedb_sdk_macros::job;
 
 #[cfg(not(feature="host"))]
 #[export_name = "_edgedb_sdk_pre_init"]
 extern "C" fn init() {
     log::init();
+    #[cfg(feature="tracing")]
+    tracing::init();
 }
@@ -4,28 +4,30 @@
 //! important exception is that logging is expected to be controlled from host.
 //! So logger and max-level changes are allowed via this API.
 //!
+//! Max level is set by the server when the instance starts and is updated
+//! when the log level of the module is changed at runtime.
+//!
 #![cfg_attr(feature="host", allow(dead_code))]
 
+use std::any::Any;
+
 pub use log::{debug, error, info, log, log_enabled, trace, warn};
 pub use log::{Record, RecordBuilder, Metadata, MetadataBuilder};
 pub use log::{Level, LevelFilter, STATIC_MAX_LEVEL};
 pub use log::{logger, max_level};
 
-wit_bindgen_rust::import!("../wit/edgedb_log_v1.wit");
+wit_bindgen_rust::import!("../wit/edgedb_log_v2.wit");
 
-use edgedb_log_v1 as v1;
+pub(crate) use edgedb_log_v2 as v2;
 
-static mut LOGGER: HostLogger = HostLogger {
-    max_level: log::STATIC_MAX_LEVEL,
-};
+static LOGGER: HostLogger = HostLogger {};
 
 struct HostLogger {
-    max_level: log::LevelFilter,
 }
 
-impl From<log::Level> for v1::Level {
-    fn from(value: log::Level) -> v1::Level {
-        use v1::Level as T;
+impl From<log::Level> for v2::Level {
+    fn from(value: log::Level) -> v2::Level {
+        use v2::Level as T;
         use log::Level as S;
 
         match value {
@@ -38,9 +40,9 @@ impl From<log::Level> for v1::Level {
     }
 }
 
-fn convert_filter(value: Option<v1::Level>) -> log::LevelFilter {
+fn convert_filter(value: Option<v2::Level>) -> log::LevelFilter {
     use log::LevelFilter as T;
-    use v1::Level as S;
+    use v2::Level as S;
 
     match value {
         None => T::Off,
@@ -54,30 +56,55 @@ fn convert_filter(value: Option<v1::Level>) -> log::LevelFilter {
 
 #[cfg(not(feature="host"))]
 pub(crate) fn init() {
-    let level = convert_filter(v1::max_level());
-    unsafe {
-        // not sure if safe all all platforms
-        LOGGER.max_level = level;
+    log::set_logger(&LOGGER).expect("init_logging");
+    log::set_max_level(convert_filter(v2::max_level()));
+    std::panic::set_hook(Box::new(log_panic));
+}
+
+/// Sends panic message to the host log before the instance traps
+///
+/// Host doesn't reuse the instance after a trap.
+fn log_panic(info: &std::panic::PanicInfo) {
+    let message = panic_message(info.payload());
+    let location = info.location();
+    // bypass max level: panics are always logged
+    v2::log(v2::LogRecord {
+        target: "panic",
+        level: v2::Level::Error,
+        message: &format!("panicked: {}", message),
+        line: location.map(|l| l.line()),
+        file: location.map(|l| l.file()),
+        module_path: None,
+        fields: &[],
+    });
+}
 
-        log::set_logger(&LOGGER).expect("init_logging");
+/// Returns message of the panic payload, if it's a string
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&'static str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "Box<dyn Any>"
     }
-    log::set_max_level(level);
 }
 
 impl log::Log for HostLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= self.max_level
+        metadata.level() <= log::max_level()
     }
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            v1::log(v1::LogRecord {
+            v2::log(v2::LogRecord {
                 target: record.target(),
                 level: record.level().into(),
                 message: &record.args().to_string(),
                 line: record.line(),
                 file: record.file(),
                 module_path: record.module_path(),
+                fields: &[],
             });
         }
     }
@@ -0,0 +1,22 @@
+wit_bindgen_rust::export!("../wit/edgedb_log_level_v1.wit");
+
+use edgedb_log_level_v1 as v1;
+
+struct EdgedbLogLevelV1 {
+}
+
+impl v1::EdgedbLogLevelV1 for EdgedbLogLevelV1 {
+    fn set_max_level(level: Option<v1::Level>) {
+        use log::LevelFilter as T;
+        use v1::Level as S;
+
+        log::set_max_level(match level {
+            None => T::Off,
+            Some(S::Error) => T::Error,
+            Some(S::Warn) => T::Warn,
+            Some(S::Debug) => T::Debug,
+            Some(S::Info) => T::Info,
+            Some(S::Trace) => T::Trace,
+        });
+    }
+}
@@ -0,0 +1,93 @@
+//! Custom metrics
+//!
+//! Metrics are aggregated by the server and exposed in Prometheus format,
+//! with `database` and `module` labels added:
+//!
+//! ```rust,no_run
+//! use edgedb_sdk::metrics;
+//!
+//! metrics::counter!("orders_total", 1, "currency" => "EUR");
+//! metrics::gauge!("queue_length", 17);
+//! metrics::histogram!("order_amount", 99.5, "currency" => "EUR");
+//! ```
+//!
+//! Label values can be of any type implementing [`ToString`]. Keep the
+//! number of distinct label values small: server drops label sets above a
+//! limit.
+//!
+//! Names must match `[a-zA-Z_:][a-zA-Z0-9_:]*` and label names
+//! `[a-zA-Z_][a-zA-Z0-9_]*`. Metrics with invalid names are ignored by the
+//! server, as are metrics of the name that is already used by another kind.
+
+wit_bindgen_rust::import!("../wit/edgedb_metrics_v1.wit");
+
+use edgedb_metrics_v1 as v1;
+
+/// Add value to the counter
+///
+/// Negative values are ignored by the server.
+pub fn counter_increment(name: &str, labels: &[(&str, &str)], value: f64) {
+    v1::counter_increment(name, labels, value)
+}
+
+/// Set the gauge to value
+pub fn gauge_set(name: &str, labels: &[(&str, &str)], value: f64) {
+    v1::gauge_set(name, labels, value)
+}
+
+/// Record value in the histogram
+///
+/// Histogram has the default buckets of Prometheus clients (from 5ms to 10s
+/// if value is duration in seconds).
+pub fn histogram_observe(name: &str, labels: &[(&str, &str)], value: f64) {
+    v1::histogram_observe(name, labels, value)
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __metrics_counter {
+    ($name:expr, $value:expr $(, $key:expr => $label:expr)* $(,)?) => {
+        $crate::metrics::counter_increment(
+            $name,
+            &[$(($key, &::std::string::ToString::to_string(&$label)[..])),*],
+            $value as f64,
+        )
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __metrics_gauge {
+    ($name:expr, $value:expr $(, $key:expr => $label:expr)* $(,)?) => {
+        $crate::metrics::gauge_set(
+            $name,
+            &[$(($key, &::std::string::ToString::to_string(&$label)[..])),*],
+            $value as f64,
+        )
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __metrics_histogram {
+    ($name:expr, $value:expr $(, $key:expr => $label:expr)* $(,)?) => {
+        $crate::metrics::histogram_observe(
+            $name,
+            &[$(($key, &::std::string::ToString::to_string(&$label)[..])),*],
+            $value as f64,
+        )
+    };
+}
+
+/// Add value to the counter: `counter!(name, value, "label" => value, ..)`
+#[doc(inline)]
+pub use crate::__metrics_counter as counter;
+
+/// Set the gauge: `gauge!(name, value, "label" => value, ..)`
+#[doc(inline)]
+pub use crate::__metrics_gauge as gauge;
+
+/// Record value in the histogram:
+/// `histogram!(name, value, "label" => value, ..)`
+#[doc(inline)]
+pub use crate::__metrics_histogram as histogram;
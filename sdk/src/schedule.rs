@@ -0,0 +1,80 @@
+//! Tasks run periodically by the server
+//!
+//! Use [`schedule`](macro@crate::schedule) attribute to register a function:
+//!
+//! ```rust,no_run
+//! #[edgedb_sdk::schedule("*/5 * * * *")]
+//! fn cleanup() -> anyhow::Result<()> {
+//!     log::info!("Cleaning up");
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Cron expression is either a standard five-field one (`min hour day month
+//! weekday`) or has seconds (and optional year) prepended. Times are in UTC.
+//! In five-field expressions days of the week are numbered from 0 (Sunday) to
+//! 7 (Sunday again), as in standard cron, or named (`MON-FRI`).
+//!
+//! Server runs schedules of every module in the wasm directory of the
+//! database. A task is skipped if its previous run is not finished yet.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use once_cell::sync::Lazy;
+
+type TaskFn = Arc<dyn Fn() -> Result<(), String> + Send + Sync>;
+
+pub(crate) static TASKS: Lazy<Mutex<BTreeMap<String, Task>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+pub(crate) struct Task {
+    pub cron: String,
+    pub func: TaskFn,
+}
+
+/// Return value of a scheduled task
+///
+/// Errors are reported to the server and logged.
+pub trait TaskResult {
+    /// Convert into the failure message if any
+    fn into_result(self) -> Result<(), String>;
+}
+
+impl TaskResult for () {
+    fn into_result(self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl<E: fmt::Display> TaskResult for Result<(), E> {
+    fn into_result(self) -> Result<(), String> {
+        self.map_err(|e| format!("{:#}", e))
+    }
+}
+
+/// Register a function to run on schedule
+///
+/// This is what [`schedule`](macro@crate::schedule) attribute does, use it
+/// directly if the schedule is known only at runtime. Must be called from
+/// an [init hook](macro@crate::init_hook).
+///
+/// Server reads schedules declared by the attribute without running the
+/// module, while schedules registered at runtime are only picked up when
+/// the module is already running (e.g. handled a request).
+///
+/// # Panics
+///
+/// Panics if a task with the same name is already registered.
+pub fn register<F, R>(name: &str, cron: &str, f: F)
+    where F: Fn() -> R + Send + Sync + 'static,
+          R: TaskResult,
+{
+    let mut tasks = TASKS.lock().expect("scheduler is not poisoned");
+    assert!(!tasks.contains_key(name),
+            "scheduled task {:?} is already registered", name);
+    tasks.insert(name.into(), Task {
+        cron: cron.into(),
+        func: Arc::new(move || f().into_result()),
+    });
+}
@@ -0,0 +1,28 @@
+use crate::schedule::TASKS;
+
+wit_bindgen_rust::export!("../wit/edgedb_scheduler_v1.wit");
+
+use edgedb_scheduler_v1 as v1;
+
+struct EdgedbSchedulerV1 {
+}
+
+impl v1::EdgedbSchedulerV1 for EdgedbSchedulerV1 {
+    fn list_schedules() -> Vec<v1::Schedule> {
+        let tasks = TASKS.lock().expect("scheduler is not poisoned");
+        tasks.iter().map(|(name, task)| v1::Schedule {
+            name: name.clone(),
+            cron: task.cron.clone(),
+        }).collect()
+    }
+    fn run_scheduled(name: String) -> Result<(), String> {
+        // lock is not held while running, so task can register other tasks
+        let func = TASKS.lock().expect("scheduler is not poisoned")
+            .get(&name)
+            .map(|task| task.func.clone());
+        match func {
+            Some(func) => func(),
+            None => Err(format!("no scheduled task {:?}", name)),
+        }
+    }
+}
@@ -0,0 +1,39 @@
+//! Secrets of the module (e.g. API keys of third-party services)
+//!
+//! Secrets are loaded by the server from a protected file or directory, so
+//! they don't need to be embedded into the module or put into plain
+//! [configuration](crate::config).
+//!
+//! ```rust,no_run
+//! use edgedb_sdk::secrets;
+//!
+//! if let Some(key) = secrets::get("api_key") {
+//!     let header = format!("Bearer {}", key.expose());
+//! }
+//! ```
+wit_bindgen_rust::import!("../wit/edgedb_secrets_v1.wit");
+
+use edgedb_secrets_v1 as v1;
+
+/// Secret value
+///
+/// Deliberately implements neither `Debug` nor `Display`, so it can't be
+/// logged by accident. Use [`expose`](Secret::expose) to get the value.
+#[allow(missing_debug_implementations)]
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret {
+    value: String,
+}
+
+impl Secret {
+    /// Returns the value of the secret
+    pub fn expose(&self) -> &str {
+        &self.value
+    }
+}
+
+/// Get a secret, returns `None` if secret is not set for the module
+pub fn get(name: &str) -> Option<Secret> {
+    v1::get(name).map(|value| Secret { value })
+}
+
@@ -0,0 +1,271 @@
+//! Utilities for testing module code natively, without WebAssembly host
+//!
+//! With the `testing` feature enabled, [`create_client`] returns a client
+//! that replies with results scripted by [`MockClient`] when compiled for a
+//! target other than WebAssembly. Handlers can be called directly with a
+//! [synthetic request](request). Other host APIs (e.g. streaming request
+//! bodies) are not available natively.
+//!
+//! ```rust,no_run
+//! use edgedb_sdk::client::create_client;
+//! use edgedb_sdk::testing::{MockClient, request};
+//! use edgedb_sdk::web;
+//!
+//! fn handler(_req: web::Request) -> web::Response {
+//!     let value = create_client()
+//!         .query_required_single::<i64, _>("SELECT 7*8", &())
+//!         .expect("query succeeds");
+//!     web::response()
+//!         .status(web::StatusCode::OK)
+//!         .body(format!("{value}").into())
+//!         .expect("response is built")
+//! }
+//!
+//! #[test]
+//! fn test_handler() {
+//!     let _mock = MockClient::new()
+//!         .expect_rows("SELECT 7*8", vec![56_i64])
+//!         .install();
+//!     let req = http::Request::get("/").body(Vec::new()).unwrap();
+//!     let resp = handler(request(req));
+//!     assert_eq!(resp.status(), web::StatusCode::OK);
+//! }
+//! ```
+//!
+//! [`create_client`]: crate::client::create_client
+use std::any::{Any, type_name};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::thread;
+
+use edgedb_errors::{ErrorKind, ResultCardinalityMismatchError};
+use edgedb_protocol::model::Json;
+
+use crate::client::Error;
+use crate::web::{self, RequestMeta};
+
+thread_local! {
+    static EXPECTATIONS: RefCell<Option<VecDeque<Expectation>>> =
+        RefCell::new(None);
+}
+
+/// Scripted replies of the database client
+///
+/// Expectations are matched in order they are added. Query text is compared
+/// ignoring differences in whitespace, arguments are not checked. Unexpected
+/// queries panic.
+pub struct MockClient {
+    expectations: VecDeque<Expectation>,
+}
+
+/// Keeps [`MockClient`] installed for the current thread
+///
+/// When dropped, checks that all expected queries were executed.
+#[derive(Debug)]
+pub struct MockGuard {
+    _private: (),
+}
+
+struct Expectation {
+    query: String,
+    reply: Reply,
+}
+
+enum Reply {
+    Rows(Box<dyn Any>, &'static str),
+    Json(String),
+    Empty,
+    Error(Error),
+}
+
+fn normalize(query: &str) -> String {
+    query.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+impl MockClient {
+    /// Create a client without expectations
+    pub fn new() -> MockClient {
+        MockClient { expectations: VecDeque::new() }
+    }
+    /// Expect a query returning rows
+    ///
+    /// Rows must be of the same type that the query is executed with (e.g.
+    /// `i64` for `query_required_single::<i64, _>`).
+    pub fn expect_rows<R: 'static>(mut self, query: &str, rows: Vec<R>)
+        -> MockClient
+    {
+        self.expectations.push_back(Expectation {
+            query: normalize(query),
+            reply: Reply::Rows(Box::new(rows), type_name::<R>()),
+        });
+        self
+    }
+    /// Expect a query executed by one of the `*_json` methods
+    ///
+    /// For `query_json` this is an array of all rows, for `query_single_json`
+    /// a single object.
+    pub fn expect_json(mut self, query: &str, json: &str) -> MockClient {
+        self.expectations.push_back(Expectation {
+            query: normalize(query),
+            reply: Reply::Json(json.into()),
+        });
+        self
+    }
+    /// Expect a query returning an empty set
+    ///
+    /// Unlike other expectations this one matches any query method.
+    pub fn expect_empty(mut self, query: &str) -> MockClient {
+        self.expectations.push_back(Expectation {
+            query: normalize(query),
+            reply: Reply::Empty,
+        });
+        self
+    }
+    /// Expect a query returning an error
+    pub fn expect_error(mut self, query: &str, error: Error) -> MockClient {
+        self.expectations.push_back(Expectation {
+            query: normalize(query),
+            reply: Reply::Error(error),
+        });
+        self
+    }
+    /// Use this client for all queries in the current thread
+    ///
+    /// # Panics
+    ///
+    /// Panics if another client is installed.
+    pub fn install(self) -> MockGuard {
+        EXPECTATIONS.with(|cell| {
+            let mut cell = cell.borrow_mut();
+            assert!(cell.is_none(), "mock client is already installed");
+            *cell = Some(self.expectations);
+        });
+        MockGuard { _private: () }
+    }
+}
+
+impl Default for MockClient {
+    fn default() -> MockClient {
+        MockClient::new()
+    }
+}
+
+impl fmt::Debug for MockClient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MockClient")
+            .field("expectations", &self.expectations.len())
+            .finish()
+    }
+}
+
+impl Drop for MockGuard {
+    fn drop(&mut self) {
+        let left = EXPECTATIONS.with(|cell| cell.borrow_mut().take())
+            .unwrap_or_default();
+        if !left.is_empty() && !thread::panicking() {
+            let queries = left.iter().map(|e| &e.query[..])
+                .collect::<Vec<_>>();
+            panic!("expected queries were not executed: {:?}", queries);
+        }
+    }
+}
+
+fn next_reply(query: &str) -> Reply {
+    let query = normalize(query);
+    let expectation = EXPECTATIONS.with(|cell| {
+        cell.borrow_mut().as_mut()
+            .expect("mock client must be installed to run queries natively")
+            .pop_front()
+    });
+    match expectation {
+        Some(exp) if exp.query == query => exp.reply,
+        Some(exp) => {
+            panic!("unexpected query {:?}, expected {:?}", query, exp.query);
+        }
+        None => panic!("unexpected query {:?}", query),
+    }
+}
+
+pub(crate) fn execute_query<T, R: 'static, A>(_target: T, query: &str,
+                                              _arguments: &A)
+    -> Result<Vec<R>, Error>
+{
+    match next_reply(query) {
+        Reply::Rows(rows, name) => match rows.downcast::<Vec<R>>() {
+            Ok(rows) => Ok(*rows),
+            Err(_) => {
+                panic!("query {:?} is executed with type {}, \
+                        but rows of {} are expected",
+                       query, type_name::<R>(), name);
+            }
+        },
+        Reply::Json(_) => {
+            panic!("query {:?} is expected to be executed as JSON", query);
+        }
+        Reply::Empty => Ok(Vec::new()),
+        Reply::Error(e) => Err(e),
+    }
+}
+
+pub(crate) fn execute_query_single<T, R: 'static, A>(target: T, query: &str,
+                                                     arguments: &A)
+    -> Result<Option<R>, Error>
+{
+    let mut rows = execute_query(target, query, arguments)?;
+    if rows.len() > 1 {
+        return Err(ResultCardinalityMismatchError::with_message(
+            "the query has cardinality MANY \
+             which does not match the expected cardinality ONE"));
+    }
+    Ok(rows.pop())
+}
+
+fn json_reply(query: &str) -> Result<Option<Json>, Error> {
+    match next_reply(query) {
+        // mock is trusted to contain valid json
+        Reply::Json(json) => Ok(Some(unsafe { Json::new_unchecked(json) })),
+        Reply::Rows(..) => {
+            panic!("query {:?} is expected to be executed with rows",
+                   query);
+        }
+        Reply::Empty => Ok(None),
+        Reply::Error(e) => Err(e),
+    }
+}
+
+pub(crate) fn execute_query_json<T, A>(_target: T, query: &str,
+                                       _arguments: &A)
+    -> Result<Json, Error>
+{
+    match json_reply(query)? {
+        Some(json) => Ok(json),
+        None => Ok(unsafe { Json::new_unchecked("[]".into()) }),
+    }
+}
+
+pub(crate) fn execute_query_single_json<T, A>(_target: T, query: &str,
+                                              _arguments: &A)
+    -> Result<Option<Json>, Error>
+{
+    json_reply(query)
+}
+
+/// Create a synthetic request to call a web handler with
+///
+/// Request is attributed to the database `edgedb` and the module `test`,
+/// mounted at `/db/edgedb/wasm/test`.
+pub fn request(inner: http::Request<Vec<u8>>) -> web::Request {
+    web::Request {
+        inner,
+        meta: RequestMeta {
+            database: "edgedb".into(),
+            module_name: "test".into(),
+            mount_prefix: "/db/edgedb/wasm/test".into(),
+            scheme: None,
+            host: None,
+            remote_addr: None,
+        },
+        body_reader: None,
+    }
+}
@@ -0,0 +1,209 @@
+//! Forwarding of [`tracing`](https://docs.rs/tracing) events and spans to
+//! the host
+//!
+//! With the `tracing` feature enabled, [`Subscriber`] is installed as the
+//! global default before init hooks are run, so just use `tracing` macros:
+//!
+//! ```rust,ignore
+//! #[tracing::instrument]
+//! fn checkout(order_id: u64) {
+//!     tracing::info!(items = 3, "Order is ready");
+//! }
+//! ```
+//!
+//! Fields of events and entered spans are kept as structured data by the
+//! server. The verbosity is the same as of the [`log`](crate::log) module.
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tracing_core::field::{Field, Visit};
+use tracing_core::span::{self, Id};
+use tracing_core::{Event, Interest, Level, Metadata};
+
+use crate::log::v2;
+
+/// Subscriber sending events and spans to the host
+#[derive(Debug)]
+pub struct Subscriber {
+    next_id: AtomicU64,
+    /// Number of handles of each open span
+    refs: Mutex<HashMap<u64, usize>>,
+}
+
+enum Value {
+    Str(String),
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    Bool(bool),
+}
+
+#[derive(Default)]
+struct Fields {
+    message: Option<String>,
+    values: Vec<(&'static str, Value)>,
+}
+
+fn convert_level(level: &Level) -> log::Level {
+    if *level == Level::ERROR {
+        log::Level::Error
+    } else if *level == Level::WARN {
+        log::Level::Warn
+    } else if *level == Level::INFO {
+        log::Level::Info
+    } else if *level == Level::DEBUG {
+        log::Level::Debug
+    } else {
+        log::Level::Trace
+    }
+}
+
+impl Fields {
+    fn collect(record: impl FnOnce(&mut dyn Visit)) -> Fields {
+        let mut fields = Fields::default();
+        record(&mut fields);
+        fields
+    }
+    fn as_v2(&self) -> Vec<v2::Field<'_>> {
+        self.values.iter().map(|(name, value)| v2::Field {
+            name,
+            value: match value {
+                Value::Str(s) => v2::Value::Str(s),
+                Value::I64(v) => v2::Value::I64(*v),
+                Value::U64(v) => v2::Value::U64(*v),
+                Value::F64(v) => v2::Value::F64(*v),
+                Value::Bool(v) => v2::Value::Bool(*v),
+            },
+        }).collect()
+    }
+}
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = Some(format!("{:?}", value));
+        } else {
+            let value = Value::Str(format!("{:?}", value));
+            self.values.push((field.name(), value));
+        }
+    }
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = Some(value.into());
+        } else {
+            self.values.push((field.name(), Value::Str(value.into())));
+        }
+    }
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.values.push((field.name(), Value::I64(value)));
+    }
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.values.push((field.name(), Value::U64(value)));
+    }
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.values.push((field.name(), Value::F64(value)));
+    }
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.values.push((field.name(), Value::Bool(value)));
+    }
+}
+
+impl Subscriber {
+    /// Create a subscriber
+    ///
+    /// There is no need to use this unless global default subscriber is
+    /// replaced by another one.
+    pub fn new() -> Subscriber {
+        Subscriber {
+            next_id: AtomicU64::new(1),
+            refs: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Default for Subscriber {
+    fn default() -> Subscriber {
+        Subscriber::new()
+    }
+}
+
+impl tracing_core::Subscriber for Subscriber {
+    fn register_callsite(&self, _: &'static Metadata<'static>) -> Interest {
+        // max level can be changed by the host at any time
+        Interest::sometimes()
+    }
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        convert_level(metadata.level()) <= log::max_level()
+    }
+    fn new_span(&self, attrs: &span::Attributes<'_>) -> Id {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.refs.lock().expect("subscriber is not poisoned").insert(id, 1);
+        let meta = attrs.metadata();
+        let fields = Fields::collect(|v| attrs.record(v));
+        v2::span_new(v2::Span {
+            id,
+            name: meta.name(),
+            target: meta.target(),
+            level: convert_level(meta.level()).into(),
+            fields: &fields.as_v2(),
+        });
+        Id::from_u64(id)
+    }
+    fn record(&self, span: &Id, values: &span::Record<'_>) {
+        let fields = Fields::collect(|v| values.record(v));
+        v2::span_record(span.into_u64(), &fields.as_v2());
+    }
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+    fn event(&self, event: &Event<'_>) {
+        let meta = event.metadata();
+        let fields = Fields::collect(|v| event.record(v));
+        v2::log(v2::LogRecord {
+            level: convert_level(meta.level()).into(),
+            target: meta.target(),
+            module_path: meta.module_path(),
+            file: meta.file(),
+            line: meta.line(),
+            message: fields.message.as_deref().unwrap_or(""),
+            fields: &fields.as_v2(),
+        });
+    }
+    fn enter(&self, span: &Id) {
+        v2::span_enter(span.into_u64());
+    }
+    fn exit(&self, span: &Id) {
+        v2::span_exit(span.into_u64());
+    }
+    fn clone_span(&self, span: &Id) -> Id {
+        let mut refs = self.refs.lock().expect("subscriber is not poisoned");
+        if let Some(count) = refs.get_mut(&span.into_u64()) {
+            *count += 1;
+        }
+        span.clone()
+    }
+    fn try_close(&self, span: Id) -> bool {
+        let id = span.into_u64();
+        let mut refs = self.refs.lock().expect("subscriber is not poisoned");
+        match refs.get_mut(&id) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                false
+            }
+            Some(_) => {
+                refs.remove(&id);
+                drop(refs);
+                v2::span_close(id);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(not(feature="host"))]
+pub(crate) fn init() {
+    let dispatch = tracing_core::Dispatch::new(Subscriber::new());
+    // fails if module has installed its own subscriber already
+    tracing_core::dispatcher::set_global_default(dispatch).ok();
+}
@@ -0,0 +1,350 @@
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::time::Duration;
+
+wit_bindgen_rust::import!("../wit/edgedb_http_body_v1.wit");
+
+use edgedb_http_body_v1 as v1;
+
+type BoxError = Box<dyn Error + Send + Sync + 'static>;
+type StreamFn = Box<dyn FnOnce(&mut BodyWriter) -> Result<(), BoxError>>;
+
+thread_local! {
+    // Streaming function of the response that is being sent
+    static PENDING_STREAM: RefCell<Option<StreamFn>> = RefCell::new(None);
+}
+
+/// Response body
+///
+/// Body is either a byte buffer or a [streaming](Body::streaming) function.
+/// Usually it's created by converting from a buffer:
+///
+/// ```rust,no_run
+/// use edgedb_sdk::web;
+///
+/// let response = web::response()
+///     .status(web::StatusCode::OK)
+///     .body(web::Body::from("Hello"))
+///     .expect("response is built");
+/// ```
+pub struct Body {
+    inner: BodyInner,
+}
+
+enum BodyInner {
+    Full(Vec<u8>),
+    Stream(StreamFn),
+}
+
+/// Error reading request body or writing response body
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamError {
+    /// Peer has disconnected or stream is already finished
+    #[error("stream is closed")]
+    Closed,
+    /// Error reading or writing stream
+    #[error("stream failed")]
+    Failed,
+}
+
+/// Reader of the request body
+///
+/// Returned by [`Request::body_reader`](crate::web::Request::body_reader).
+/// Also implements [`std::io::Read`].
+pub struct BodyReader {
+    inner: ReaderInner,
+}
+
+enum ReaderInner {
+    Host(v1::BodyReader),
+    Buffer(io::Cursor<Vec<u8>>),
+    Done,
+}
+
+/// Writer of the streaming response body
+///
+/// Passed to the function in [`Body::streaming`]. Also implements
+/// [`std::io::Write`]. Every write is sent to the client as a separate chunk,
+/// so wrap it into [`std::io::BufWriter`] when writing a lot of small
+/// pieces.
+pub struct BodyWriter {
+    inner: v1::BodyWriter,
+}
+
+impl Body {
+    /// Empty body
+    pub fn empty() -> Body {
+        Body { inner: BodyInner::Full(Vec::new()) }
+    }
+    /// Body which is generated by a function after response headers are sent
+    ///
+    /// This allows to send large responses without keeping them in memory.
+    ///
+    /// ```rust,no_run
+    /// use std::io::Write;
+    /// use edgedb_sdk::web;
+    ///
+    /// let response = web::response()
+    ///     .status(web::StatusCode::OK)
+    ///     .header("Content-Type", "text/csv")
+    ///     .body(web::Body::streaming(|writer| {
+    ///         for i in 0..1_000_000 {
+    ///             writeln!(writer, "{},{}", i, i*i)?;
+    ///         }
+    ///         Ok::<_, std::io::Error>(())
+    ///     }))
+    ///     .expect("response is built");
+    /// ```
+    ///
+    /// Function is run while the worker is kept busy, so subsequent requests
+    /// are processed by a fresh instance of the module. Writes return
+    /// [`StreamError::Closed`] when client disconnects.
+    pub fn streaming<F, E>(f: F) -> Body
+        where F: FnOnce(&mut BodyWriter) -> Result<(), E> + 'static,
+              E: Into<BoxError>,
+    {
+        Body {
+            inner: BodyInner::Stream(Box::new(|w| f(w).map_err(Into::into))),
+        }
+    }
+    /// Returns body contents unless body is streaming
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match &self.inner {
+            BodyInner::Full(data) => Some(data),
+            BodyInner::Stream(_) => None,
+        }
+    }
+    /// Returns `true` if body is generated by a [streaming](Body::streaming)
+    /// function
+    pub fn is_streaming(&self) -> bool {
+        matches!(self.inner, BodyInner::Stream(_))
+    }
+    /// Converts body into a buffer or saves streaming function to be called
+    /// by [`write_pending`]
+    pub(crate) fn into_full_or_pending(self) -> Option<Vec<u8>> {
+        match self.inner {
+            BodyInner::Full(data) => Some(data),
+            BodyInner::Stream(f) => {
+                PENDING_STREAM.with(|p| *p.borrow_mut() = Some(f));
+                None
+            }
+        }
+    }
+}
+
+fn is_disconnect(e: &(dyn Error + 'static)) -> bool {
+    if let Some(e) = e.downcast_ref::<StreamError>() {
+        return *e == StreamError::Closed;
+    }
+    if let Some(e) = e.downcast_ref::<io::Error>() {
+        return e.kind() == io::ErrorKind::BrokenPipe;
+    }
+    false
+}
+
+/// Runs streaming function of the last response
+pub(crate) fn write_pending() {
+    let f = match PENDING_STREAM.with(|p| p.borrow_mut().take()) {
+        Some(f) => f,
+        None => {
+            log::error!("No streaming body to write");
+            return;
+        }
+    };
+    let mut writer = match v1::BodyWriter::response_body() {
+        Some(inner) => BodyWriter { inner },
+        None => {
+            log::error!("Response body writer is not available");
+            return;
+        }
+    };
+    match f(&mut writer) {
+        Ok(()) => {}
+        Err(e) if is_disconnect(&*e) => {
+            log::debug!("Client disconnected while streaming response");
+        }
+        Err(e) => log::error!("Error writing response body: {:#}", e),
+    }
+}
+
+impl Default for Body {
+    fn default() -> Body {
+        Body::empty()
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(data: Vec<u8>) -> Body {
+        Body { inner: BodyInner::Full(data) }
+    }
+}
+
+impl From<String> for Body {
+    fn from(data: String) -> Body {
+        data.into_bytes().into()
+    }
+}
+
+impl From<&'_ str> for Body {
+    fn from(data: &str) -> Body {
+        data.as_bytes().into()
+    }
+}
+
+impl From<&'_ [u8]> for Body {
+    fn from(data: &[u8]) -> Body {
+        data.to_vec().into()
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.inner {
+            BodyInner::Full(data) => {
+                f.debug_tuple("Body").field(&data.len()).finish()
+            }
+            BodyInner::Stream(_) => f.write_str("Body(<streaming>)"),
+        }
+    }
+}
+
+impl From<v1::StreamError> for StreamError {
+    fn from(e: v1::StreamError) -> StreamError {
+        match e {
+            v1::StreamError::Closed => StreamError::Closed,
+            v1::StreamError::Failed => StreamError::Failed,
+        }
+    }
+}
+
+impl From<StreamError> for io::Error {
+    fn from(e: StreamError) -> io::Error {
+        match e {
+            StreamError::Closed => io::Error::new(io::ErrorKind::BrokenPipe, e),
+            StreamError::Failed => io::Error::new(io::ErrorKind::Other, e),
+        }
+    }
+}
+
+impl BodyReader {
+    /// Reader of the body of the request being handled
+    pub(crate) fn request_body() -> BodyReader {
+        match v1::BodyReader::request_body() {
+            Some(reader) => BodyReader { inner: ReaderInner::Host(reader) },
+            None => BodyReader { inner: ReaderInner::Done },
+        }
+    }
+    pub(crate) fn from_vec(data: Vec<u8>) -> BodyReader {
+        BodyReader { inner: ReaderInner::Buffer(io::Cursor::new(data)) }
+    }
+    /// Read next chunk of at most `max_size` bytes
+    ///
+    /// Returns `None` at the end of body.
+    pub fn read_chunk(&mut self, max_size: usize)
+        -> Result<Option<Vec<u8>>, StreamError>
+    {
+        match &mut self.inner {
+            ReaderInner::Host(reader) => {
+                let max_size = max_size.try_into().unwrap_or(u32::MAX);
+                let data = reader.read(max_size)?;
+                if data.is_empty() {
+                    self.inner = ReaderInner::Done;
+                    return Ok(None);
+                }
+                Ok(Some(data))
+            }
+            ReaderInner::Buffer(cur) => {
+                let pos = cur.position() as usize;
+                let buf = cur.get_ref();
+                if pos >= buf.len() {
+                    self.inner = ReaderInner::Done;
+                    return Ok(None);
+                }
+                let end = buf.len().min(pos + max_size);
+                let data = buf[pos..end].to_vec();
+                cur.set_position(end as u64);
+                Ok(Some(data))
+            }
+            ReaderInner::Done => Ok(None),
+        }
+    }
+    /// Read the rest of the body into memory
+    pub fn read_to_end(mut self) -> Result<Vec<u8>, StreamError> {
+        if let ReaderInner::Buffer(cur) = &mut self.inner {
+            if cur.position() == 0 {
+                return Ok(mem::take(cur.get_mut()));
+            }
+        }
+        let mut result = Vec::new();
+        while let Some(chunk) = self.read_chunk(65536)? {
+            result.extend(chunk);
+        }
+        Ok(result)
+    }
+}
+
+impl io::Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let ReaderInner::Buffer(cur) = &mut self.inner {
+            return io::Read::read(cur, buf);
+        }
+        match self.read_chunk(buf.len())? {
+            Some(chunk) => {
+                buf[..chunk.len()].copy_from_slice(&chunk);
+                Ok(chunk.len())
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+impl fmt::Debug for BodyReader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.inner {
+            ReaderInner::Host(_) => "host",
+            ReaderInner::Buffer(_) => "buffer",
+            ReaderInner::Done => "done",
+        };
+        f.debug_struct("BodyReader").field("kind", &kind).finish()
+    }
+}
+
+impl BodyWriter {
+    /// Send a chunk of data to the client
+    pub fn write_chunk(&mut self, data: &[u8]) -> Result<(), StreamError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.inner.write(data)?;
+        Ok(())
+    }
+    /// Sleep for the specified time
+    ///
+    /// Returns [`StreamError::Closed`] as soon as client disconnects, so this
+    /// should be used instead of [`std::thread::sleep`] between chunks of
+    /// a long-running stream.
+    pub fn wait(&mut self, timeout: Duration) -> Result<(), StreamError> {
+        let ms = timeout.as_millis().try_into().unwrap_or(u32::MAX);
+        self.inner.wait(ms)?;
+        Ok(())
+    }
+}
+
+impl io::Write for BodyWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_chunk(buf)?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl fmt::Debug for BodyWriter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BodyWriter").finish()
+    }
+}
@@ -0,0 +1,188 @@
+//! Request and response cookies
+//!
+//! ```rust,no_run
+//! use edgedb_sdk::web;
+//! use edgedb_sdk::web::cookies::{Cookie, Cookies, Key};
+//! use once_cell::sync::Lazy;
+//!
+//! // in real application key should be loaded from configuration
+//! static KEY: Lazy<Key> = Lazy::new(Key::generate);
+//!
+//! #[web::handler]
+//! fn handler(req: web::Request) -> web::Response {
+//!     let mut cookies = Cookies::from_request(&req);
+//!     let visits = cookies.signed(&KEY).get("visits")
+//!         .and_then(|c| c.value().parse::<u64>().ok())
+//!         .unwrap_or(0) + 1;
+//!     cookies.signed_mut(&KEY).add(Cookie::new("visits", visits.to_string()));
+//!     let mut response = web::response()
+//!         .status(web::StatusCode::OK)
+//!         .body(format!("Visited {visits} times").into())
+//!         .expect("response is built");
+//!     cookies.apply(&mut response);
+//!     response
+//! }
+//! ```
+use std::ops::{Deref, DerefMut};
+
+use http::header::{self, HeaderValue};
+
+use crate::web::{Request, Response};
+
+pub use cookie::{Cookie, CookieBuilder, CookieJar, Key, SameSite};
+pub use cookie::time;
+
+/// Cookies of the request and changes to send with the response
+///
+/// Dereferences to [`CookieJar`]. Use its [`signed`](CookieJar::signed)
+/// and [`private`](CookieJar::private) methods to sign or encrypt values.
+/// Changes (added and removed cookies) are sent to the client by
+/// [`apply`](Cookies::apply).
+#[derive(Debug, Clone, Default)]
+pub struct Cookies {
+    jar: CookieJar,
+}
+
+impl Cookies {
+    /// Parse `Cookie` headers of the request
+    ///
+    /// Cookies that can't be parsed are ignored.
+    pub fn from_request(req: &Request) -> Cookies {
+        let mut jar = CookieJar::new();
+        let cookies = req.headers().get_all(header::COOKIE).iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .map(|c| c.trim())
+            .filter(|c| !c.is_empty())
+            .filter_map(|c| Cookie::parse_encoded(c.to_string()).ok());
+        for cookie in cookies {
+            jar.add_original(cookie);
+        }
+        Cookies { jar }
+    }
+    /// Add `Set-Cookie` header to the response for each changed cookie
+    pub fn apply(&self, response: &mut Response) {
+        let headers = response.headers_mut();
+        for cookie in self.jar.delta() {
+            match HeaderValue::from_str(&cookie.encoded().to_string()) {
+                Ok(value) => {
+                    headers.append(header::SET_COOKIE, value);
+                }
+                Err(e) => {
+                    log::error!("Invalid cookie {:?}: {}", cookie.name(), e);
+                }
+            }
+        }
+    }
+}
+
+impl Deref for Cookies {
+    type Target = CookieJar;
+    fn deref(&self) -> &CookieJar {
+        &self.jar
+    }
+}
+
+impl DerefMut for Cookies {
+    fn deref_mut(&mut self) -> &mut CookieJar {
+        &mut self.jar
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use http::header;
+
+    use crate::web::{self, Body, Response, StatusCode};
+    use super::{Cookie, Cookies, Key};
+
+    fn cookies(headers: &[&[u8]]) -> Cookies {
+        let mut req = http::Request::get("/db/edgedb/wasm/test");
+        for value in headers {
+            req = req.header(header::COOKIE, *value);
+        }
+        Cookies::from_request(&web::test_request(req.body(Vec::new())
+                                                 .unwrap()))
+    }
+
+    fn value<'a>(cookies: &'a Cookies, name: &str) -> Option<&'a str> {
+        cookies.get(name).map(|c| c.value())
+    }
+
+    fn set_cookies(cookies: &Cookies) -> Vec<String> {
+        let mut resp: Response = web::response()
+            .status(StatusCode::OK)
+            .body(Body::from("ok"))
+            .expect("response is built");
+        cookies.apply(&mut resp);
+        let mut values = resp.headers().get_all(header::SET_COOKIE).iter()
+            .map(|v| v.to_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        values.sort();
+        values
+    }
+
+    #[test]
+    fn single_header() {
+        let cookies = cookies(&[b"a=1; b=2"]);
+        assert_eq!(value(&cookies, "a"), Some("1"));
+        assert_eq!(value(&cookies, "b"), Some("2"));
+        assert_eq!(cookies.iter().count(), 2);
+    }
+
+    #[test]
+    fn multiple_headers() {
+        let cookies = cookies(&[b"a=1", b"b=2;c=3"]);
+        assert_eq!(value(&cookies, "a"), Some("1"));
+        assert_eq!(value(&cookies, "b"), Some("2"));
+        assert_eq!(value(&cookies, "c"), Some("3"));
+    }
+
+    #[test]
+    fn malformed() {
+        let cookies = cookies(&[b"a=1; ; garbage; =x; b=2;", b"c=\xff\xfe"]);
+        assert_eq!(value(&cookies, "a"), Some("1"));
+        assert_eq!(value(&cookies, "b"), Some("2"));
+        assert_eq!(cookies.iter().count(), 2);
+    }
+
+    #[test]
+    fn percent_encoded() {
+        let cookies = cookies(&[b"name=hello%20world%3B"]);
+        assert_eq!(value(&cookies, "name"), Some("hello world;"));
+    }
+
+    #[test]
+    fn no_headers() {
+        let cookies = cookies(&[]);
+        assert_eq!(cookies.iter().count(), 0);
+        assert!(set_cookies(&cookies).is_empty());
+    }
+
+    #[test]
+    fn apply_only_changes() {
+        let mut cookies = cookies(&[b"a=1; b=2; c=3"]);
+        cookies.add(Cookie::new("a", "10"));
+        cookies.add(Cookie::new("d", "4 5"));
+        cookies.remove(Cookie::named("b"));
+        let values = set_cookies(&cookies);
+        assert_eq!(values.len(), 3);
+        assert_eq!(values[0], "a=10");
+        assert!(values[1].starts_with("b=; "), "{}", values[1]);
+        assert!(values[1].contains("Max-Age=0"), "{}", values[1]);
+        assert_eq!(values[2], "d=4%205");
+    }
+
+    #[test]
+    fn signed() {
+        let key = Key::generate();
+        let mut original = Cookies::default();
+        original.signed_mut(&key).add(Cookie::new("user", "alice"));
+        let value = set_cookies(&original).pop().unwrap();
+
+        let cookies = cookies(&[value.as_bytes()]);
+        assert_eq!(cookies.signed(&key).get("user").unwrap().value(),
+                   "alice");
+        assert!(cookies.signed(&Key::generate()).get("user").is_none());
+    }
+}
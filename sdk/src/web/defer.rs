@@ -0,0 +1,59 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::error::Error;
+
+type BoxError = Box<dyn Error + Send + Sync + 'static>;
+type DeferFn = Box<dyn FnOnce() -> Result<(), BoxError>>;
+
+thread_local! {
+    static DEFERRED: RefCell<VecDeque<DeferFn>> = RefCell::new(VecDeque::new());
+}
+
+/// Run a function after the response is sent
+///
+/// This is useful for non-critical follow-up work, like writing audit
+/// records, that shouldn't delay the response. Functions are run in order
+/// they are deferred, by the same instance of the module, so no other
+/// requests are processed by this instance until they finish.
+///
+/// Deferred functions have limited time budget. If it's exceeded, the
+/// remaining functions are not run. Errors are logged.
+///
+/// ```rust,no_run
+/// use edgedb_sdk::{log, web};
+///
+/// #[web::handler]
+/// fn handler(req: web::Request) -> web::Response {
+///     let path = req.relative_path().to_string();
+///     web::defer(move || {
+///         log::info!("Visited {}", path);
+///         Ok::<_, std::convert::Infallible>(())
+///     });
+///     web::response()
+///         .status(web::StatusCode::OK)
+///         .body("Hello".into())
+///         .expect("response is built")
+/// }
+/// ```
+#[doc(alias = "after_response")]
+pub fn defer<F, E>(f: F)
+    where F: FnOnce() -> Result<(), E> + 'static,
+          E: Into<BoxError>,
+{
+    DEFERRED.with(|d| {
+        d.borrow_mut().push_back(Box::new(|| f().map_err(Into::into)))
+    });
+}
+
+pub(crate) fn has_pending() -> bool {
+    DEFERRED.with(|d| !d.borrow().is_empty())
+}
+
+/// Runs deferred functions, including ones deferred while running
+pub(crate) fn run_pending() {
+    while let Some(f) = DEFERRED.with(|d| d.borrow_mut().pop_front()) {
+        if let Err(e) = f() {
+            log::error!("Deferred task failed: {:#}", e);
+        }
+    }
+}
@@ -0,0 +1,361 @@
+use std::time::Duration;
+
+use http::header::{self, HeaderName, HeaderValue};
+use http::Method;
+
+use crate::web::{self, Body, Request, Response, StatusCode};
+use crate::web::middleware::{Middleware, Next};
+
+/// Cross-Origin Resource Sharing (CORS) middleware
+///
+/// Answers preflight (`OPTIONS`) requests itself and adds
+/// `Access-Control-*` headers to responses for allowed origins. Requests from
+/// origins that are not allowed are passed to the handler unchanged, so the
+/// browser rejects the response.
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use edgedb_sdk::web::middleware::Cors;
+///
+/// let cors = Cors::new()
+///     .allow_origin("https://example.com")
+///     .allow_methods(["GET", "POST"])
+///     .allow_headers(["Content-Type"])
+///     .max_age(Duration::from_secs(3600));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Cors {
+    any_origin: bool,
+    origins: Vec<HeaderValue>,
+    methods: Vec<Method>,
+    headers: Vec<HeaderName>,
+    expose_headers: Vec<HeaderName>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+fn join<T: AsRef<str>>(items: &[T]) -> HeaderValue {
+    let items = items.iter().map(|x| x.as_ref()).collect::<Vec<_>>();
+    HeaderValue::from_str(&items.join(", "))
+        .expect("method and header names are valid header values")
+}
+
+impl Cors {
+    /// Create middleware that allows no origins
+    ///
+    /// By default `GET`, `HEAD` and `POST` methods are allowed.
+    pub fn new() -> Cors {
+        Cors {
+            any_origin: false,
+            origins: Vec::new(),
+            methods: vec![Method::GET, Method::HEAD, Method::POST],
+            headers: Vec::new(),
+            expose_headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+    /// Allow requests from any origin
+    pub fn allow_any_origin(mut self) -> Cors {
+        self.any_origin = true;
+        self
+    }
+    /// Allow requests from the specified origin (e.g. `https://example.com`)
+    ///
+    /// # Panics
+    ///
+    /// Panics if origin is not a valid header value.
+    pub fn allow_origin(mut self, origin: &str) -> Cors {
+        self.origins.push(HeaderValue::from_str(origin)
+            .expect("origin is a valid header value"));
+        self
+    }
+    /// Set methods allowed for cross-origin requests
+    ///
+    /// # Panics
+    ///
+    /// Panics if method name is invalid.
+    pub fn allow_methods<'a>(mut self,
+                             methods: impl IntoIterator<Item=&'a str>)
+        -> Cors
+    {
+        self.methods = methods.into_iter()
+            .map(|m| m.parse().expect("valid method name"))
+            .collect();
+        self
+    }
+    /// Set request headers allowed for cross-origin requests
+    ///
+    /// # Panics
+    ///
+    /// Panics if header name is invalid.
+    pub fn allow_headers<'a>(mut self,
+                             headers: impl IntoIterator<Item=&'a str>)
+        -> Cors
+    {
+        self.headers = headers.into_iter()
+            .map(|h| h.parse().expect("valid header name"))
+            .collect();
+        self
+    }
+    /// Set response headers that are exposed to the browser script
+    ///
+    /// # Panics
+    ///
+    /// Panics if header name is invalid.
+    pub fn expose_headers<'a>(mut self,
+                              headers: impl IntoIterator<Item=&'a str>)
+        -> Cors
+    {
+        self.expose_headers = headers.into_iter()
+            .map(|h| h.parse().expect("valid header name"))
+            .collect();
+        self
+    }
+    /// Allow cookies and authorization headers in cross-origin requests
+    pub fn allow_credentials(mut self, value: bool) -> Cors {
+        self.credentials = value;
+        self
+    }
+    /// Set for how long the results of a preflight request may be cached
+    pub fn max_age(mut self, value: Duration) -> Cors {
+        self.max_age = Some(value);
+        self
+    }
+
+    fn is_allowed(&self, origin: &HeaderValue) -> bool {
+        self.any_origin || self.origins.iter().any(|o| o == origin)
+    }
+
+    fn add_origin_headers(&self, resp: &mut Response, origin: &HeaderValue) {
+        let headers = resp.headers_mut();
+        if self.any_origin && !self.credentials {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN,
+                           HeaderValue::from_static("*"));
+        } else {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN,
+                           origin.clone());
+            headers.append(header::VARY, HeaderValue::from_static("Origin"));
+        }
+        if self.credentials {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                           HeaderValue::from_static("true"));
+        }
+    }
+
+    fn preflight(&self, origin: &HeaderValue) -> Response {
+        if !self.is_allowed(origin) {
+            return web::response()
+                .status(StatusCode::FORBIDDEN)
+                .body(Body::empty())
+                .expect("response is built");
+        }
+        let mut resp = web::response()
+            .status(StatusCode::NO_CONTENT)
+            .header(header::ACCESS_CONTROL_ALLOW_METHODS, join(&self.methods))
+            .body(Body::empty())
+            .expect("response is built");
+        self.add_origin_headers(&mut resp, origin);
+        let headers = resp.headers_mut();
+        if !self.headers.is_empty() {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS,
+                           join(&self.headers));
+        }
+        if let Some(max_age) = self.max_age {
+            headers.insert(header::ACCESS_CONTROL_MAX_AGE,
+                           max_age.as_secs().into());
+        }
+        resp
+    }
+}
+
+impl Default for Cors {
+    fn default() -> Cors {
+        Cors::new()
+    }
+}
+
+impl Middleware for Cors {
+    fn call(&self, req: Request, next: Next<'_>) -> Response {
+        let origin = match req.headers().get(header::ORIGIN) {
+            Some(origin) => origin.clone(),
+            None => return next.run(req),
+        };
+        if req.method() == Method::OPTIONS &&
+            req.headers().contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+        {
+            return self.preflight(&origin);
+        }
+        let mut resp = next.run(req);
+        if self.is_allowed(&origin) {
+            self.add_origin_headers(&mut resp, &origin);
+            if !self.expose_headers.is_empty() {
+                resp.headers_mut().insert(
+                    header::ACCESS_CONTROL_EXPOSE_HEADERS,
+                    join(&self.expose_headers));
+            }
+        }
+        resp
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use http::header;
+    use http::Method;
+
+    use crate::web::{self, Body, Response, StatusCode};
+    use crate::web::middleware::{Middleware, Next};
+    use super::Cors;
+
+    fn handler(_req: web::Request) -> Response {
+        web::response()
+            .status(StatusCode::OK)
+            .body(Body::from("handler"))
+            .expect("response is built")
+    }
+
+    fn call(cors: &Cors, method: Method, headers: &[(&str, &str)])
+        -> Response
+    {
+        let mut req = http::Request::builder().method(method)
+            .uri("/db/edgedb/wasm/test/items");
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        let req = web::test_request(req.body(Vec::new()).unwrap());
+        cors.call(req, Next::new(&[], &handler))
+    }
+
+    fn header(resp: &Response, name: header::HeaderName) -> Option<&str> {
+        resp.headers().get(name).map(|v| v.to_str().unwrap())
+    }
+
+    fn preflight(cors: &Cors, origin: &str) -> Response {
+        call(cors, Method::OPTIONS, &[
+            ("Origin", origin),
+            ("Access-Control-Request-Method", "POST"),
+        ])
+    }
+
+    #[test]
+    fn no_origin() {
+        let cors = Cors::new().allow_any_origin();
+        let resp = call(&cors, Method::GET, &[]);
+        assert_eq!(resp.body().as_bytes(), Some(&b"handler"[..]));
+        assert!(resp.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .is_none());
+    }
+
+    #[test]
+    fn allowed_origin() {
+        let cors = Cors::new()
+            .allow_origin("https://a.example.com")
+            .expose_headers(["X-Total"]);
+        let resp = call(&cors, Method::GET,
+                        &[("Origin", "https://a.example.com")]);
+        assert_eq!(resp.body().as_bytes(), Some(&b"handler"[..]));
+        assert_eq!(header(&resp, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+                   Some("https://a.example.com"));
+        assert_eq!(header(&resp, header::VARY), Some("Origin"));
+        assert_eq!(header(&resp, header::ACCESS_CONTROL_EXPOSE_HEADERS),
+                   Some("x-total"));
+    }
+
+    #[test]
+    fn other_origin() {
+        let cors = Cors::new().allow_origin("https://a.example.com");
+        let resp = call(&cors, Method::GET,
+                        &[("Origin", "https://b.example.com")]);
+        assert_eq!(resp.body().as_bytes(), Some(&b"handler"[..]));
+        assert!(resp.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .is_none());
+    }
+
+    #[test]
+    fn origin_is_matched_exactly() {
+        let cors = Cors::new().allow_origin("https://example.com");
+        for origin in ["https://example.com.evil.org", "http://example.com",
+                       "https://example.com:8080", "null"]
+        {
+            let resp = preflight(&cors, origin);
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN, "{}", origin);
+        }
+    }
+
+    #[test]
+    fn preflight_allowed() {
+        let cors = Cors::new()
+            .allow_origin("https://example.com")
+            .allow_methods(["GET", "POST", "DELETE"])
+            .allow_headers(["Content-Type", "X-Token"])
+            .max_age(Duration::from_secs(600));
+        let resp = preflight(&cors, "https://example.com");
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(resp.body().as_bytes(), Some(&b""[..]));
+        assert_eq!(header(&resp, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+                   Some("https://example.com"));
+        assert_eq!(header(&resp, header::ACCESS_CONTROL_ALLOW_METHODS),
+                   Some("GET, POST, DELETE"));
+        assert_eq!(header(&resp, header::ACCESS_CONTROL_ALLOW_HEADERS),
+                   Some("content-type, x-token"));
+        assert_eq!(header(&resp, header::ACCESS_CONTROL_MAX_AGE),
+                   Some("600"));
+    }
+
+    #[test]
+    fn preflight_default_methods() {
+        let cors = Cors::new().allow_any_origin();
+        let resp = preflight(&cors, "https://example.com");
+        assert_eq!(header(&resp, header::ACCESS_CONTROL_ALLOW_METHODS),
+                   Some("GET, HEAD, POST"));
+        assert!(resp.headers().get(header::ACCESS_CONTROL_ALLOW_HEADERS)
+                .is_none());
+        assert!(resp.headers().get(header::ACCESS_CONTROL_MAX_AGE)
+                .is_none());
+    }
+
+    #[test]
+    fn preflight_denied() {
+        let cors = Cors::new().allow_origin("https://example.com");
+        let resp = preflight(&cors, "https://other.com");
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert!(resp.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .is_none());
+    }
+
+    #[test]
+    fn options_without_request_method() {
+        let cors = Cors::new().allow_any_origin();
+        let resp = call(&cors, Method::OPTIONS,
+                        &[("Origin", "https://example.com")]);
+        assert_eq!(resp.body().as_bytes(), Some(&b"handler"[..]));
+    }
+
+    #[test]
+    fn any_origin() {
+        let cors = Cors::new().allow_any_origin();
+        let resp = call(&cors, Method::GET,
+                        &[("Origin", "https://example.com")]);
+        assert_eq!(header(&resp, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+                   Some("*"));
+        assert!(resp.headers().get(header::VARY).is_none());
+        assert!(resp.headers()
+                .get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+    }
+
+    #[test]
+    fn any_origin_with_credentials() {
+        let cors = Cors::new().allow_any_origin().allow_credentials(true);
+        let resp = call(&cors, Method::GET,
+                        &[("Origin", "https://example.com")]);
+        // `*` is not allowed with credentials
+        assert_eq!(header(&resp, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+                   Some("https://example.com"));
+        assert_eq!(header(&resp, header::VARY), Some("Origin"));
+        assert_eq!(header(&resp, header::ACCESS_CONTROL_ALLOW_CREDENTIALS),
+                   Some("true"));
+    }
+}
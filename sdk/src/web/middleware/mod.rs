@@ -0,0 +1,158 @@
+//! Middleware for web handlers
+//!
+//! Middleware wraps a web handler and can inspect or modify both the request
+//! and the response, or return a response without calling the handler at all.
+//! Use [`Router::layer`](crate::web::Router::layer) to add middleware.
+//!
+//! # Writing Middleware
+//!
+//! Any function with a suitable signature is a middleware:
+//!
+//! ```rust,no_run
+//! use edgedb_sdk::web;
+//!
+//! fn require_token(req: web::Request, next: web::Next) -> web::Response {
+//!     if req.headers().get("Authorization").is_none() {
+//!         return web::response()
+//!             .status(web::StatusCode::UNAUTHORIZED)
+//!             .body("Unauthorized".into())
+//!             .expect("response is built");
+//!     }
+//!     next.run(req)
+//! }
+//!
+//! # fn handler(_req: web::Request) -> web::Response { todo!() }
+//! let router = web::Router::new(handler).layer(require_token);
+//! ```
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Instant;
+
+use crate::log;
+use crate::web::{self, Request, Response, StatusCode};
+
+mod cors;
+
+pub use cors::Cors;
+
+/// A layer around the web handler
+pub trait Middleware: Send + Sync + 'static {
+    /// Process the request
+    ///
+    /// Call [`next.run(req)`](Next::run) to pass request further down the
+    /// chain, or return a response directly to short-circuit.
+    fn call(&self, req: Request, next: Next<'_>) -> Response;
+}
+
+/// The rest of the middleware chain, including the handler itself
+pub struct Next<'a> {
+    layers: &'a [Box<dyn Middleware>],
+    handler: &'a (dyn Fn(Request) -> Response + Send + Sync),
+}
+
+/// Logs every request with its response status and processing time
+///
+/// Messages are sent through [`edgedb_sdk::log`](crate::log), at the `info`
+/// level by default.
+#[derive(Debug, Clone)]
+pub struct LogRequests {
+    level: log::Level,
+}
+
+/// Converts panics in the handler into `500 Internal Server Error` responses
+///
+/// Panic message is logged at the `error` level.
+///
+/// Note: WebAssembly targets are usually built with `panic=abort`, in which
+/// case panic can't be caught and is reported to the host instead. This
+/// middleware takes effect only when code is built with unwinding enabled
+/// (for example, when handlers are tested natively).
+#[derive(Debug, Clone, Copy)]
+pub struct CatchPanic;
+
+impl<F> Middleware for F
+    where F: Fn(Request, Next<'_>) -> Response + Send + Sync + 'static,
+{
+    fn call(&self, req: Request, next: Next<'_>) -> Response {
+        self(req, next)
+    }
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(layers: &'a [Box<dyn Middleware>],
+                      handler: &'a (dyn Fn(Request) -> Response + Send + Sync))
+        -> Next<'a>
+    {
+        Next { layers, handler }
+    }
+
+    /// Run the rest of the chain
+    pub fn run(self, req: Request) -> Response {
+        match self.layers.split_first() {
+            Some((layer, rest)) => {
+                layer.call(req, Next { layers: rest, handler: self.handler })
+            }
+            None => (self.handler)(req),
+        }
+    }
+}
+
+impl fmt::Debug for Next<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Next")
+            .field("layers", &self.layers.len())
+            .finish()
+    }
+}
+
+impl LogRequests {
+    /// Create middleware that logs at the `info` level
+    pub fn new() -> LogRequests {
+        LogRequests {
+            level: log::Level::Info,
+        }
+    }
+    /// Set log level used for messages
+    pub fn level(mut self, level: log::Level) -> LogRequests {
+        self.level = level;
+        self
+    }
+}
+
+impl Default for LogRequests {
+    fn default() -> LogRequests {
+        LogRequests::new()
+    }
+}
+
+impl Middleware for LogRequests {
+    fn call(&self, req: Request, next: Next<'_>) -> Response {
+        if !log::log_enabled!(self.level) {
+            return next.run(req);
+        }
+        let method = req.method().clone();
+        let uri = req.uri().clone();
+        let start = Instant::now();
+        let resp = next.run(req);
+        log::log!(self.level, "{} {} -> {} in {:?}",
+                  method, uri, resp.status().as_u16(), start.elapsed());
+        resp
+    }
+}
+
+impl Middleware for CatchPanic {
+    fn call(&self, req: Request, next: Next<'_>) -> Response {
+        match panic::catch_unwind(AssertUnwindSafe(|| next.run(req))) {
+            Ok(resp) => resp,
+            Err(payload) => {
+                log::error!("Web handler panicked: {}",
+                            log::panic_message(&*payload));
+                web::response()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .header("Content-Type", "text/plain")
+                    .body("Internal Server Error".into())
+                    .expect("response is built")
+            }
+        }
+    }
+}
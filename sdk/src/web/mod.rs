@@ -18,6 +18,20 @@
 //! }
 //! ```
 //!
+//! Handler may also be an `async fn` (see [`executor`](crate::executor) for
+//! limitations) and may return a `Result` (see [`IntoResponse`]):
+//!
+//! ```rust,no_run
+//! use edgedb_sdk::web;
+//! #[web::handler]
+//! async fn web_handler(req: web::Request) -> anyhow::Result<web::Response> {
+//!     let name = std::str::from_utf8(req.body())?;
+//!     Ok(web::response()
+//!         .status(web::StatusCode::OK)
+//!         .body(format!("Hello {name}").into())?)
+//! }
+//! ```
+//!
 //! # Programmatically Register Web Handler
 //!
 //! It's sometimes useful to do that programmatically. This is usually done in
@@ -35,34 +49,162 @@
 //!     todo!();
 //! }
 //! ```
+//!
+//! # Middleware
+//!
+//! Cross-cutting concerns (CORS, logging, authentication checks) can be
+//! implemented as a [`Middleware`] and layered around the handler using
+//! [`Router`]:
+//!
+//! ```rust,no_run
+//! use edgedb_sdk::{init_hook, web};
+//! use edgedb_sdk::web::middleware::{CatchPanic, Cors, LogRequests};
+//!
+//! #[init_hook]
+//! fn init() {
+//!     web::register_router(
+//!         web::Router::new(web_handler)
+//!             .layer(LogRequests::new())
+//!             .layer(CatchPanic)
+//!             .layer(Cors::new().allow_origin("https://example.com"))
+//!     );
+//! }
+//!
+//! fn web_handler(req: web::Request) -> web::Response {
+//!     todo!();
+//! }
+//! ```
+//!
+//! # Streaming
+//!
+//! Large responses can be generated while being sent using
+//! [`Body::streaming`]. Live updates can be pushed to the browser using
+//! [server-sent events](sse).
+//!
+//! # Cookies and Sessions
+//!
+//! See [`cookies`] module. Sessions stored in the database are available
+//! with the `sessions` feature enabled.
+//!
+//! # Static Files
+//!
+//! Files can be embedded into the module using [`static_dir!`] and served
+//! with caching headers, see [`StaticDir`].
+//!
+//! # WebSockets
+//!
+//! See [`websocket`] module.
+use std::fmt;
+use std::mem;
+use std::net::SocketAddr;
+
 use once_cell::sync::OnceCell;
 
+pub mod cookies;
+pub mod middleware;
+pub(crate) mod body;
+pub(crate) mod defer;
+pub mod sse;
+pub mod static_files;
+#[cfg(feature="sessions")]
+pub mod session;
+pub mod websocket;
+mod router;
+
 pub use edgedb_sdk_macros::web_handler as handler;
+pub use edgedb_sdk_macros::static_dir;
 pub use http::StatusCode;
+pub use body::{Body, BodyReader, BodyWriter, StreamError};
+pub use defer::defer;
+pub use middleware::{Middleware, Next};
+pub use router::Router;
+pub use static_files::StaticDir;
 
 /// Re-exported type from [`http`](http::Response) crate
-pub type Response = http::Response<Vec<u8>>;
+///
+/// See [`Body`] for ways to construct the response body.
+pub type Response = http::Response<Body>;
 
 /// Web Request
 ///
 /// Currently it dereferences to [`http::Request`] so see its documentation
 /// for more info.
+///
+/// Note: [`uri()`](http::Request::uri) contains full path of the request,
+/// including the [mount prefix](Request::mount_prefix). Use
+/// [`relative_path()`](Request::relative_path) to get a path relative to the
+/// module.
 #[derive(Debug)]
 pub struct Request {
     pub(crate) inner: http::Request<Vec<u8>>,
+    pub(crate) meta: RequestMeta,
+    pub(crate) body_reader: Option<BodyReader>,
+}
+
+#[derive(Debug)]
+pub(crate) struct RequestMeta {
+    pub database: String,
+    pub module_name: String,
+    pub mount_prefix: String,
+    pub scheme: Option<String>,
+    pub host: Option<String>,
+    pub remote_addr: Option<SocketAddr>,
 }
 
 type WebHandler = fn(Request) -> Response;
-pub(crate) static WEB_HANDLER: OnceCell<WebHandler> = OnceCell::new();
+pub(crate) static WEB_HANDLER: OnceCell<Router> = OnceCell::new();
 
 /// Register a function as a web handler
 ///
+/// This is a shortcut for registering a [`Router`] without any middleware.
+///
 /// # Panics
 ///
 /// Panics if called more than once (including implicitly by [`handler`]
 /// macro).
 pub fn register_handler(f: WebHandler) {
-    WEB_HANDLER.set(f).expect("only one handler is expected");
+    register_router(Router::new(f));
+}
+
+/// Register a router (a handler with middleware layers) as a web handler
+///
+/// # Panics
+///
+/// Panics if called more than once, or if [`register_handler`] (including
+/// implicitly by [`handler`] macro) was called before.
+pub fn register_router(router: Router) {
+    WEB_HANDLER.set(router).expect("only one handler is expected");
+}
+
+/// Return value of a web handler
+///
+/// Implemented for [`Response`] and `Result<Response, E>`. Errors are
+/// logged and `500 Internal Server Error` is sent to the client.
+pub trait IntoResponse {
+    /// Convert value into a response
+    fn into_response(self) -> Response;
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> Response {
+        self
+    }
+}
+
+impl<E: fmt::Display> IntoResponse for Result<Response, E> {
+    fn into_response(self) -> Response {
+        match self {
+            Ok(response) => response,
+            Err(e) => {
+                log::error!("Error handling request: {:#}", e);
+                response()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .header("Content-Type", "text/plain")
+                    .body(Body::from("Internal Server Error"))
+                    .expect("response is built")
+            }
+        }
+    }
 }
 
 /// Create a response builder
@@ -72,6 +214,71 @@ pub fn response() -> http::response::Builder {
     http::Response::builder()
 }
 
+impl Request {
+    /// Name of the database this module is run for
+    pub fn database(&self) -> &str {
+        &self.meta.database
+    }
+    /// Name of the WebAssembly module that handles this request
+    ///
+    /// This is the name of the `.wasm` file without extension.
+    pub fn module_name(&self) -> &str {
+        &self.meta.module_name
+    }
+    /// Path prefix the module is mounted at
+    ///
+    /// For example `/db/edgedb/wasm/greeting`. This can be used to build
+    /// absolute links to other pages of the module.
+    pub fn mount_prefix(&self) -> &str {
+        &self.meta.mount_prefix
+    }
+    /// Path of the request relative to the
+    /// [mount prefix](Request::mount_prefix)
+    ///
+    /// Always starts with slash, i.e. `/` is returned for the request to the
+    /// mount prefix itself.
+    pub fn relative_path(&self) -> &str {
+        let path = self.inner.uri().path();
+        match path.strip_prefix(&self.meta.mount_prefix[..]) {
+            Some("") => "/",
+            Some(rel) if rel.starts_with('/') => rel,
+            _ => path,
+        }
+    }
+    /// Scheme of the original request (`http` or `https`), if known
+    pub fn scheme(&self) -> Option<&str> {
+        self.meta.scheme.as_deref()
+    }
+    /// Host of the original request, if known
+    ///
+    /// This is taken either from the request URL or from the `Host` header.
+    pub fn host(&self) -> Option<&str> {
+        self.meta.host.as_deref()
+    }
+    /// Address of the peer that sent the request, if known
+    ///
+    /// Note: if the server is run behind a proxy this is the address of the
+    /// proxy.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.meta.remote_addr
+    }
+    /// Returns a reader of the request body
+    ///
+    /// If the router is configured to
+    /// [stream request body](Router::stream_request_body), the body is read
+    /// from the host while the reader is consumed, and
+    /// [`body()`](http::Request::body) is always empty. Otherwise the body is
+    /// already read into memory and reader moves it out of the request.
+    ///
+    /// Subsequent calls return an empty reader.
+    pub fn body_reader(&mut self) -> BodyReader {
+        match self.body_reader.take() {
+            Some(reader) => reader,
+            None => BodyReader::from_vec(mem::take(self.inner.body_mut())),
+        }
+    }
+}
+
 impl AsRef<http::Request<Vec<u8>>> for Request {
     fn as_ref(&self) -> &http::Request<Vec<u8>> {
         &self.inner
@@ -90,3 +297,20 @@ impl Into<http::Request<Vec<u8>>> for Request {
         self.inner
     }
 }
+
+/// Request to `/db/edgedb/wasm/test` for unit tests
+#[cfg(test)]
+pub(crate) fn test_request(inner: http::Request<Vec<u8>>) -> Request {
+    Request {
+        inner,
+        meta: RequestMeta {
+            database: "edgedb".into(),
+            module_name: "test".into(),
+            mount_prefix: "/db/edgedb/wasm/test".into(),
+            scheme: None,
+            host: None,
+            remote_addr: None,
+        },
+        body_reader: None,
+    }
+}
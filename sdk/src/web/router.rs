@@ -0,0 +1,68 @@
+use std::fmt;
+
+use crate::web::{Request, Response, Middleware, Next};
+
+type BoxHandler = Box<dyn Fn(Request) -> Response + Send + Sync + 'static>;
+
+/// A web handler wrapped into a chain of [middleware](Middleware)
+///
+/// Use [`register_router`](crate::web::register_router) to make it the web
+/// handler of the WebAssembly module.
+///
+/// Layers are run in the order they are added, i.e. the first added layer
+/// receives the request first and sees the response last.
+pub struct Router {
+    handler: BoxHandler,
+    layers: Vec<Box<dyn Middleware>>,
+    stream_request_body: bool,
+}
+
+impl Router {
+    /// Create a router with a handler and no middleware
+    pub fn new<F>(handler: F) -> Router
+        where F: Fn(Request) -> Response + Send + Sync + 'static,
+    {
+        Router {
+            handler: Box::new(handler),
+            layers: Vec::new(),
+            stream_request_body: false,
+        }
+    }
+
+    /// Add a middleware layer
+    ///
+    /// The layer is put inside all previously added layers.
+    pub fn layer(mut self, middleware: impl Middleware) -> Router {
+        self.layers.push(Box::new(middleware));
+        self
+    }
+
+    /// Do not read request body before calling the handler
+    ///
+    /// By default request body is read into memory before the handler is
+    /// called. With this option it must be read using
+    /// [`Request::body_reader`], which allows to process large uploads
+    /// without buffering them.
+    pub fn stream_request_body(mut self) -> Router {
+        self.stream_request_body = true;
+        self
+    }
+
+    pub(crate) fn streams_request_body(&self) -> bool {
+        self.stream_request_body
+    }
+
+    /// Process the request through the middleware chain and the handler
+    pub fn handle(&self, request: Request) -> Response {
+        Next::new(&self.layers, &*self.handler).run(request)
+    }
+}
+
+impl fmt::Debug for Router {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Router")
+            .field("layers", &self.layers.len())
+            .field("stream_request_body", &self.stream_request_body)
+            .finish()
+    }
+}
@@ -0,0 +1,638 @@
+//! Sessions stored in the database
+//!
+//! Session data is a JSON object stored in the database. Browser only
+//! receives an encrypted cookie with a random session key. Session type must
+//! be defined in the schema:
+//!
+//! ```edgeql
+//! type WebSession {
+//!     required property session_key -> str {
+//!         constraint exclusive;
+//!     };
+//!     required property data -> json;
+//!     required property expires_at -> datetime;
+//!     required property rotated_at -> datetime;
+//! }
+//! ```
+//!
+//! Expired sessions are ignored but not deleted from the database.
+//!
+//! ```rust,no_run
+//! use edgedb_sdk::{init_hook, web};
+//! use edgedb_sdk::client::create_client;
+//! use edgedb_sdk::web::cookies::Key;
+//! use edgedb_sdk::web::session::{Session, SessionData};
+//!
+//! #[init_hook]
+//! fn init() {
+//!     // in real application key should be loaded from configuration
+//!     let key = Key::generate();
+//!     web::register_router(
+//!         web::Router::new(web_handler)
+//!             .layer(Session::new(create_client(), key))
+//!     );
+//! }
+//!
+//! fn web_handler(req: web::Request) -> web::Response {
+//!     let session = SessionData::from_request(&req)
+//!         .expect("session middleware is enabled");
+//!     let visits = session.get("visits")
+//!         .and_then(|v| v.as_u64())
+//!         .unwrap_or(0) + 1;
+//!     session.insert("visits", visits);
+//!     web::response()
+//!         .status(web::StatusCode::OK)
+//!         .body(format!("Visited {visits} times").into())
+//!         .expect("response is built")
+//! }
+//! ```
+use std::fmt;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::{Map, Value};
+
+use crate::client::Client;
+use crate::web::{self, Body, Request, Response, StatusCode};
+use crate::web::cookies::{Cookie, Cookies, Key, SameSite, time};
+use crate::web::middleware::{Middleware, Next};
+
+/// Middleware that loads and saves [`SessionData`]
+///
+/// Session key is rotated periodically (every hour by default) and when
+/// [`SessionData::renew`] is called. Session expires if it isn't modified
+/// or rotated for the [`ttl`](Session::ttl) (one day by default).
+pub struct Session {
+    client: Client,
+    key: Key,
+    cookie_name: String,
+    type_name: String,
+    ttl: Duration,
+    rotate_after: Duration,
+    secure: bool,
+}
+
+/// Data of the current session
+///
+/// This is a handle, cloning it doesn't clone the data. Changes are saved
+/// after the handler returns.
+#[derive(Debug, Clone)]
+pub struct SessionData {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    data: Map<String, Value>,
+    changed: bool,
+    renew: bool,
+    destroy: bool,
+}
+
+struct Loaded {
+    data: Map<String, Value>,
+    rotate: bool,
+}
+
+fn is_valid_type_name(name: &str) -> bool {
+    !name.is_empty() && name.split("::").all(|part| {
+        let mut chars = part.chars();
+        matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+fn generate_key() -> anyhow::Result<String> {
+    let mut buf = [0u8; 32];
+    getrandom::getrandom(&mut buf)?;
+    Ok(buf.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+impl Session {
+    /// Create session middleware
+    ///
+    /// `key` is used to encrypt session cookie, so it must be the same for
+    /// all instances of the module.
+    pub fn new(client: Client, key: Key) -> Session {
+        Session {
+            client,
+            key,
+            cookie_name: "session".into(),
+            type_name: "default::WebSession".into(),
+            ttl: Duration::from_secs(86400),
+            rotate_after: Duration::from_secs(3600),
+            secure: true,
+        }
+    }
+    /// Set name of the session cookie (default is `session`)
+    pub fn cookie_name(mut self, name: &str) -> Session {
+        self.cookie_name = name.into();
+        self
+    }
+    /// Set name of the database type to store sessions in
+    ///
+    /// Default is `default::WebSession`. See [module docs](self) for the
+    /// required schema.
+    ///
+    /// # Panics
+    ///
+    /// Panics if name is not a valid (optionally module-qualified) type name.
+    pub fn type_name(mut self, name: &str) -> Session {
+        assert!(is_valid_type_name(name), "invalid type name {:?}", name);
+        self.type_name = name.into();
+        self
+    }
+    /// Set time after which unmodified session expires
+    pub fn ttl(mut self, ttl: Duration) -> Session {
+        self.ttl = ttl;
+        self
+    }
+    /// Set interval of session key rotation
+    pub fn rotate_after(mut self, interval: Duration) -> Session {
+        self.rotate_after = interval;
+        self
+    }
+    /// Set whether cookie is only sent over HTTPS (default is `true`)
+    pub fn secure(mut self, value: bool) -> Session {
+        self.secure = value;
+        self
+    }
+
+    fn load(&self, key: &str) -> anyhow::Result<Option<Loaded>> {
+        let query = format!("
+            SELECT {} {{
+                data,
+                rotate := .rotated_at < datetime_current()
+                          - to_duration(seconds := <float64>$1),
+            }}
+            FILTER .session_key = <str>$0
+               AND .expires_at > datetime_current()
+            LIMIT 1
+        ", self.type_name);
+        let args = (key.to_string(), self.rotate_after.as_secs_f64());
+        let json = match self.client.query_single_json(&query, &args)? {
+            Some(json) => json,
+            None => return Ok(None),
+        };
+        let mut row: Map<String, Value> = serde_json::from_str(&json)?;
+        let data = match row.remove("data") {
+            Some(Value::Object(data)) => data,
+            _ => Map::new(),
+        };
+        let rotate = row.get("rotate").and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        Ok(Some(Loaded { data, rotate }))
+    }
+
+    fn insert(&self, key: &str, data: &Map<String, Value>)
+        -> anyhow::Result<()>
+    {
+        let query = format!("
+            INSERT {} {{
+                session_key := <str>$0,
+                data := to_json(<str>$1),
+                expires_at := datetime_current()
+                              + to_duration(seconds := <float64>$2),
+                rotated_at := datetime_current(),
+            }}
+        ", self.type_name);
+        let args = (
+            key.to_string(),
+            serde_json::to_string(data)?,
+            self.ttl.as_secs_f64(),
+        );
+        self.client.query_json(&query, &args)?;
+        Ok(())
+    }
+
+    fn update(&self, old_key: &str, new_key: &str,
+              data: &Map<String, Value>)
+        -> anyhow::Result<()>
+    {
+        let rotated_at = if old_key == new_key {
+            ".rotated_at"
+        } else {
+            "datetime_current()"
+        };
+        let query = format!("
+            UPDATE {} FILTER .session_key = <str>$0
+            SET {{
+                session_key := <str>$1,
+                data := to_json(<str>$2),
+                expires_at := datetime_current()
+                              + to_duration(seconds := <float64>$3),
+                rotated_at := {},
+            }}
+        ", self.type_name, rotated_at);
+        let args = (
+            old_key.to_string(),
+            new_key.to_string(),
+            serde_json::to_string(data)?,
+            self.ttl.as_secs_f64(),
+        );
+        self.client.query_json(&query, &args)?;
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> anyhow::Result<()> {
+        let query = format!("
+            DELETE {} FILTER .session_key = <str>$0
+        ", self.type_name);
+        self.client.query_json(&query, &(key.to_string(),))?;
+        Ok(())
+    }
+
+    fn cookie(&self, value: String, path: &str) -> Cookie<'static> {
+        Cookie::build(self.cookie_name.clone(), value)
+            .path(path.to_string())
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(SameSite::Lax)
+            .max_age(time::Duration::seconds(self.ttl.as_secs() as i64))
+            .finish()
+    }
+
+    fn save(&self, cookies: &mut Cookies, path: &str,
+            old_key: Option<&str>, loaded: Option<Loaded>, state: State)
+        -> anyhow::Result<()>
+    {
+        let mut jar = cookies.private_mut(&self.key);
+        let (old_key, rotate) = match (old_key, loaded) {
+            (Some(key), Some(loaded)) => (key, loaded.rotate),
+            (old_key, None) => {
+                if old_key.is_some() {
+                    // stale cookie of expired or destroyed session
+                    jar.remove(self.cookie(String::new(), path));
+                }
+                if !state.destroy && !state.data.is_empty() {
+                    let key = generate_key()?;
+                    self.insert(&key, &state.data)?;
+                    jar.add(self.cookie(key, path));
+                }
+                return Ok(());
+            }
+            (None, Some(_)) => unreachable!("session loaded without key"),
+        };
+        if state.destroy {
+            self.delete(old_key)?;
+            jar.remove(self.cookie(String::new(), path));
+        } else if rotate || state.renew {
+            let key = generate_key()?;
+            self.update(old_key, &key, &state.data)?;
+            jar.add(self.cookie(key, path));
+        } else if state.changed {
+            self.update(old_key, old_key, &state.data)?;
+            // refresh cookie expiration too
+            jar.add(self.cookie(old_key.to_string(), path));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("cookie_name", &self.cookie_name)
+            .field("type_name", &self.type_name)
+            .field("ttl", &self.ttl)
+            .field("rotate_after", &self.rotate_after)
+            .field("secure", &self.secure)
+            .finish()
+    }
+}
+
+impl Middleware for Session {
+    fn call(&self, mut req: Request, next: Next<'_>) -> Response {
+        let mut cookies = Cookies::from_request(&req);
+        let old_key = cookies.private(&self.key).get(&self.cookie_name)
+            .map(|c| c.value().to_string());
+        let loaded = match old_key.as_deref().map(|key| self.load(key)) {
+            Some(Ok(loaded)) => loaded,
+            Some(Err(e)) => {
+                log::error!("Error loading session: {:#}", e);
+                return web::response()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::from("Internal Server Error"))
+                    .expect("response is built");
+            }
+            None => None,
+        };
+        let session = SessionData {
+            state: Arc::new(Mutex::new(State {
+                data: loaded.as_ref().map(|l| l.data.clone())
+                    .unwrap_or_default(),
+                .. State::default()
+            })),
+        };
+        let path = req.mount_prefix().to_string();
+        req.inner.extensions_mut().insert(session.clone());
+
+        let mut resp = next.run(req);
+
+        let state = mem::take(&mut *session.lock());
+        let result = self.save(&mut cookies, &path,
+                               old_key.as_deref(), loaded, state);
+        if let Err(e) = result {
+            log::error!("Error saving session: {:#}", e);
+        }
+        cookies.apply(&mut resp);
+        resp
+    }
+}
+
+impl SessionData {
+    /// Returns session of the request
+    ///
+    /// Returns `None` if [`Session`] middleware is not enabled.
+    pub fn from_request(req: &Request) -> Option<SessionData> {
+        req.extensions().get::<SessionData>().cloned()
+    }
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        // data is still consistent if handler panicked
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    /// Get value by key
+    pub fn get(&self, key: &str) -> Option<Value> {
+        self.lock().data.get(key).cloned()
+    }
+    /// Set value of the key
+    pub fn insert(&self, key: &str, value: impl Into<Value>) {
+        let mut state = self.lock();
+        state.data.insert(key.into(), value.into());
+        state.changed = true;
+    }
+    /// Remove value, returning it if it was set
+    pub fn remove(&self, key: &str) -> Option<Value> {
+        let mut state = self.lock();
+        let value = state.data.remove(key);
+        state.changed |= value.is_some();
+        value
+    }
+    /// Replace session key with a new one keeping the data
+    ///
+    /// This should be called when user logs in, to prevent session fixation.
+    pub fn renew(&self) {
+        self.lock().renew = true;
+    }
+    /// Delete session from the database and remove the cookie
+    pub fn destroy(&self) {
+        let mut state = self.lock();
+        state.data.clear();
+        state.destroy = true;
+    }
+}
+
+#[cfg(all(test, feature="testing", not(target_arch="wasm32")))]
+mod test {
+    use std::time::Duration;
+
+    use http::header;
+    use serde_json::json;
+
+    use crate::client::create_client;
+    use crate::client::errors::{ClientError, ErrorKind};
+    use crate::testing::MockClient;
+    use crate::web::{self, Body, Response, StatusCode};
+    use crate::web::cookies::{Cookie, Cookies, Key};
+    use crate::web::middleware::{Middleware, Next};
+    use super::{Session, SessionData};
+
+    const LOAD: &str = "
+        SELECT default::WebSession {
+            data,
+            rotate := .rotated_at < datetime_current()
+                      - to_duration(seconds := <float64>$1),
+        }
+        FILTER .session_key = <str>$0
+           AND .expires_at > datetime_current()
+        LIMIT 1
+    ";
+    const INSERT: &str = "
+        INSERT default::WebSession {
+            session_key := <str>$0,
+            data := to_json(<str>$1),
+            expires_at := datetime_current()
+                          + to_duration(seconds := <float64>$2),
+            rotated_at := datetime_current(),
+        }
+    ";
+    const UPDATE: &str = "
+        UPDATE default::WebSession FILTER .session_key = <str>$0
+        SET {
+            session_key := <str>$1,
+            data := to_json(<str>$2),
+            expires_at := datetime_current()
+                          + to_duration(seconds := <float64>$3),
+            rotated_at := .rotated_at,
+        }
+    ";
+    const ROTATE: &str = "
+        UPDATE default::WebSession FILTER .session_key = <str>$0
+        SET {
+            session_key := <str>$1,
+            data := to_json(<str>$2),
+            expires_at := datetime_current()
+                          + to_duration(seconds := <float64>$3),
+            rotated_at := datetime_current(),
+        }
+    ";
+    const DELETE: &str = "
+        DELETE default::WebSession FILTER .session_key = <str>$0
+    ";
+
+    fn session(key: &Key) -> Session {
+        Session::new(create_client(), key.clone())
+            .rotate_after(Duration::from_secs(60))
+    }
+
+    /// Value of the `Cookie` header with the encrypted session key
+    fn cookie(key: &Key, session_key: &str) -> String {
+        let mut cookies = Cookies::default();
+        cookies.private_mut(key)
+            .add(Cookie::new("session", session_key.to_string()));
+        cookies.delta().next().unwrap().encoded().to_string()
+    }
+
+    /// Calls handler through the session middleware
+    ///
+    /// Returns the response and session key set by `Set-Cookie`, empty
+    /// string if the cookie is removed.
+    fn call(key: &Key, cookie: Option<&str>,
+            handler: &(dyn Fn(web::Request) -> Response + Send + Sync))
+        -> (Response, Option<String>)
+    {
+        let mut req = http::Request::get("/db/edgedb/wasm/test/page");
+        if let Some(cookie) = cookie {
+            req = req.header(header::COOKIE, cookie);
+        }
+        let req = web::test_request(req.body(Vec::new()).unwrap());
+        let resp = session(key).call(req, Next::new(&[], handler));
+        let mut set_cookie = resp.headers().get_all(header::SET_COOKIE)
+            .iter().collect::<Vec<_>>();
+        assert!(set_cookie.len() <= 1, "{:?}", set_cookie);
+        let session_key = set_cookie.pop().map(|value| {
+            let value = value.to_str().unwrap();
+            let mut jar = Cookies::default();
+            jar.add_original(Cookie::parse_encoded(value.to_string())
+                             .unwrap().into_owned());
+            assert!(value.contains("HttpOnly"), "{}", value);
+            assert!(value.contains("Path=/db/edgedb/wasm/test"), "{}", value);
+            match jar.private(key).get("session") {
+                Some(c) => c.value().to_string(),
+                None => {
+                    assert!(value.starts_with("session=;"), "{}", value);
+                    String::new()
+                }
+            }
+        });
+        (resp, session_key)
+    }
+
+    fn ok() -> Response {
+        web::response()
+            .status(StatusCode::OK)
+            .body(Body::from("ok"))
+            .expect("response is built")
+    }
+
+    #[test]
+    fn no_middleware() {
+        let req = web::test_request(
+            http::Request::get("/").body(Vec::new()).unwrap());
+        assert!(SessionData::from_request(&req).is_none());
+    }
+
+    #[test]
+    fn empty_session_is_not_saved() {
+        let key = Key::generate();
+        let _mock = MockClient::new().install();
+        let (resp, cookie) = call(&key, None, &|req| {
+            let session = SessionData::from_request(&req).unwrap();
+            assert_eq!(session.get("user"), None);
+            ok()
+        });
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(cookie, None);
+    }
+
+    #[test]
+    fn new_session() {
+        let key = Key::generate();
+        let _mock = MockClient::new().expect_json(INSERT, "[]").install();
+        let (_, cookie) = call(&key, None, &|req| {
+            SessionData::from_request(&req).unwrap().insert("user", 1);
+            ok()
+        });
+        let cookie = cookie.unwrap();
+        assert_eq!(cookie.len(), 64);
+        assert!(cookie.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+
+    #[test]
+    fn load_unchanged() {
+        let key = Key::generate();
+        let _mock = MockClient::new()
+            .expect_json(LOAD, r#"{"data": {"user": 1}, "rotate": false}"#)
+            .install();
+        let (_, cookie) = call(&key, Some(&cookie(&key, "k1")), &|req| {
+            let session = SessionData::from_request(&req).unwrap();
+            assert_eq!(session.get("user"), Some(json!(1)));
+            ok()
+        });
+        assert_eq!(cookie, None);
+    }
+
+    #[test]
+    fn update() {
+        let key = Key::generate();
+        let _mock = MockClient::new()
+            .expect_json(LOAD, r#"{"data": {"user": 1}, "rotate": false}"#)
+            .expect_json(UPDATE, "[]")
+            .install();
+        let (_, cookie) = call(&key, Some(&cookie(&key, "k1")), &|req| {
+            let session = SessionData::from_request(&req).unwrap();
+            assert_eq!(session.remove("user"), Some(json!(1)));
+            ok()
+        });
+        // expiration of the same key is refreshed
+        assert_eq!(cookie.as_deref(), Some("k1"));
+    }
+
+    #[test]
+    fn rotate() {
+        let key = Key::generate();
+        let _mock = MockClient::new()
+            .expect_json(LOAD, r#"{"data": {"user": 1}, "rotate": true}"#)
+            .expect_json(ROTATE, "[]")
+            .install();
+        let (_, cookie) = call(&key, Some(&cookie(&key, "k1")), &|_| ok());
+        let cookie = cookie.unwrap();
+        assert_ne!(cookie, "k1");
+        assert_eq!(cookie.len(), 64);
+    }
+
+    #[test]
+    fn renew() {
+        let key = Key::generate();
+        let _mock = MockClient::new()
+            .expect_json(LOAD, r#"{"data": {}, "rotate": false}"#)
+            .expect_json(ROTATE, "[]")
+            .install();
+        let (_, cookie) = call(&key, Some(&cookie(&key, "k1")), &|req| {
+            SessionData::from_request(&req).unwrap().renew();
+            ok()
+        });
+        assert_ne!(cookie.unwrap(), "k1");
+    }
+
+    #[test]
+    fn destroy() {
+        let key = Key::generate();
+        let _mock = MockClient::new()
+            .expect_json(LOAD, r#"{"data": {"user": 1}, "rotate": true}"#)
+            .expect_json(DELETE, "[]")
+            .install();
+        let (_, cookie) = call(&key, Some(&cookie(&key, "k1")), &|req| {
+            SessionData::from_request(&req).unwrap().destroy();
+            ok()
+        });
+        assert_eq!(cookie.as_deref(), Some(""));
+    }
+
+    #[test]
+    fn expired() {
+        let key = Key::generate();
+        let _mock = MockClient::new()
+            .expect_empty(LOAD)
+            .install();
+        let (_, cookie) = call(&key, Some(&cookie(&key, "k1")), &|req| {
+            let session = SessionData::from_request(&req).unwrap();
+            assert_eq!(session.get("user"), None);
+            ok()
+        });
+        assert_eq!(cookie.as_deref(), Some(""));
+    }
+
+    #[test]
+    fn forged_cookie() {
+        let key = Key::generate();
+        // cookie encrypted with another key is not loaded
+        let _mock = MockClient::new().install();
+        let forged = cookie(&Key::generate(), "k1");
+        let (_, cookie) = call(&key, Some(&forged), &|_| ok());
+        assert_eq!(cookie, None);
+    }
+
+    #[test]
+    fn load_error() {
+        let key = Key::generate();
+        let _mock = MockClient::new()
+            .expect_error(LOAD, ClientError::with_message("failed"))
+            .install();
+        let (resp, cookie) = call(&key, Some(&cookie(&key, "k1")),
+                                  &|_| panic!("handler is called"));
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(cookie, None);
+    }
+}
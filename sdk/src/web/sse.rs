@@ -0,0 +1,162 @@
+//! Server-sent events
+//!
+//! Event stream is a [streaming](crate::web::Body::streaming) response with
+//! the `text/event-stream` content type. Worker keeps sending events until
+//! the function returns or the client disconnects:
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//! use edgedb_sdk::web::{self, sse};
+//!
+//! #[web::handler]
+//! fn handler(_req: web::Request) -> web::Response {
+//!     sse::response(|stream| -> Result<(), web::StreamError> {
+//!         let mut counter = 0;
+//!         loop {
+//!             // usually a database query goes here
+//!             counter += 1;
+//!             stream.send(&sse::Event::new(counter.to_string()))?;
+//!             // returns error when client disconnects
+//!             stream.sleep(Duration::from_secs(5))?;
+//!         }
+//!     })
+//! }
+//! ```
+use std::fmt::Write;
+use std::time::Duration;
+
+use http::header;
+
+use crate::web::{self, Body, BodyWriter, Response, StatusCode, StreamError};
+
+/// Interval between keep-alive comments sent by [`EventStream::sleep`]
+///
+/// This is also how fast disconnect is detected when the host can't notice
+/// it without writing to a connection.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Single event of the event stream
+///
+/// ```rust
+/// use edgedb_sdk::web::sse::Event;
+///
+/// let event = Event::new("{\"count\": 1}")
+///     .event("update")
+///     .id("1");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Event {
+    data: String,
+    event: Option<String>,
+    id: Option<String>,
+    retry: Option<Duration>,
+}
+
+/// Writer of the events passed to the function in [`response`]
+#[derive(Debug)]
+pub struct EventStream<'a> {
+    writer: &'a mut BodyWriter,
+}
+
+fn single_line(value: &str) -> String {
+    value.replace(&['\r', '\n'][..], " ")
+}
+
+impl Event {
+    /// Create event with the specified data
+    ///
+    /// Multi-line data is sent as multiple `data:` lines and is joined back
+    /// by the browser.
+    pub fn new(data: impl Into<String>) -> Event {
+        Event {
+            data: data.into(),
+            .. Event::default()
+        }
+    }
+    /// Set event type (`message` is used by the browser if not set)
+    pub fn event(mut self, event: &str) -> Event {
+        self.event = Some(single_line(event));
+        self
+    }
+    /// Set event id, which is sent back by the browser on reconnect in
+    /// `Last-Event-ID` header
+    pub fn id(mut self, id: &str) -> Event {
+        self.id = Some(single_line(id));
+        self
+    }
+    /// Set time the browser waits before reconnecting
+    pub fn retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+    fn serialize(&self) -> String {
+        let mut buf = String::with_capacity(self.data.len() + 16);
+        if let Some(event) = &self.event {
+            writeln!(buf, "event: {}", event).unwrap();
+        }
+        if let Some(id) = &self.id {
+            writeln!(buf, "id: {}", id).unwrap();
+        }
+        if let Some(retry) = self.retry {
+            writeln!(buf, "retry: {}", retry.as_millis()).unwrap();
+        }
+        for line in self.data.lines() {
+            writeln!(buf, "data: {}", line).unwrap();
+        }
+        if self.data.is_empty() {
+            buf.push_str("data:\n");
+        }
+        buf.push('\n');
+        buf
+    }
+}
+
+impl EventStream<'_> {
+    /// Send an event to the client
+    pub fn send(&mut self, event: &Event) -> Result<(), StreamError> {
+        self.writer.write_chunk(event.serialize().as_bytes())
+    }
+    /// Send a comment, which is ignored by the browser
+    pub fn comment(&mut self, text: &str) -> Result<(), StreamError> {
+        let mut buf = String::with_capacity(text.len() + 3);
+        for line in text.lines() {
+            writeln!(buf, ":{}", line).unwrap();
+        }
+        if text.is_empty() {
+            buf.push_str(":\n");
+        }
+        buf.push('\n');
+        self.writer.write_chunk(buf.as_bytes())
+    }
+    /// Sleep for the specified time
+    ///
+    /// Sends keep-alive comments during long sleeps. Returns
+    /// [`StreamError::Closed`] when client disconnects.
+    pub fn sleep(&mut self, time: Duration) -> Result<(), StreamError> {
+        let mut left = time;
+        while left > KEEP_ALIVE {
+            self.writer.wait(KEEP_ALIVE)?;
+            self.comment("")?;
+            left -= KEEP_ALIVE;
+        }
+        self.writer.wait(left)
+    }
+}
+
+/// Create an event stream response
+///
+/// Function `f` is called after response headers are sent. See
+/// [module documentation](self) for an example.
+pub fn response<F, E>(f: F) -> Response
+    where F: FnOnce(&mut EventStream) -> Result<(), E> + 'static,
+          E: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+{
+    web::response()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(Body::streaming(move |writer| {
+            f(&mut EventStream { writer })
+        }))
+        .expect("response is built")
+}
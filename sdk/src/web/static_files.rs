@@ -0,0 +1,347 @@
+//! Static files embedded into the WebAssembly module
+//!
+//! Use [`static_dir!`](crate::web::static_dir) to embed a directory and add
+//! it as a [middleware](crate::web::Middleware) to the router:
+//!
+//! ```rust,ignore
+//! use edgedb_sdk::{init_hook, web};
+//!
+//! #[init_hook]
+//! fn init() {
+//!     web::register_router(
+//!         web::Router::new(web_handler)
+//!             .layer(web::static_dir!("assets/").prefix("/static"))
+//!     );
+//! }
+//!
+//! fn web_handler(req: web::Request) -> web::Response {
+//!     todo!();
+//! }
+//! ```
+use std::fmt;
+
+use http::header::{self, HeaderValue};
+use http::Method;
+
+use crate::web::{self, Body, Request, Response, StatusCode};
+use crate::web::middleware::{Middleware, Next};
+
+/// Directory of files embedded by [`static_dir!`](crate::web::static_dir)
+///
+/// Files are served with `Content-Type` detected by file extension and an
+/// `ETag`, so browsers can revalidate cached files and receive
+/// `304 Not Modified`. Precompressed variants are sent to clients that
+/// accept the encoding.
+///
+/// Works as a [middleware](crate::web::Middleware): requests that don't match
+/// any file are passed to the next handler. Requests to a directory are
+/// served with the `index.html` of that directory.
+#[derive(Debug, Clone)]
+pub struct StaticDir {
+    files: &'static [StaticFile],
+    prefix: String,
+    cache_control: HeaderValue,
+}
+
+#[doc(hidden)]
+pub struct StaticFile {
+    pub path: &'static str,
+    pub content_type: &'static str,
+    pub etag: &'static str,
+    pub data: &'static [u8],
+    pub gzip: Option<&'static [u8]>,
+    pub brotli: Option<&'static [u8]>,
+}
+
+fn accepts_encoding(req: &Request, encoding: &str) -> bool {
+    req.headers().get_all(header::ACCEPT_ENCODING).iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|item| {
+            let mut parts = item.split(';').map(|p| p.trim());
+            parts.next() == Some(encoding) &&
+                !parts.any(|p| p == "q=0" || p == "q=0.0" || p == "q=0.00")
+        })
+}
+
+fn etag_matches(req: &Request, etag: &str) -> bool {
+    req.headers().get_all(header::IF_NONE_MATCH).iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|v| v.trim())
+        .any(|v| v == "*" || v.strip_prefix("W/").unwrap_or(v) == etag)
+}
+
+impl StaticDir {
+    #[doc(hidden)]
+    pub fn new(files: &'static [StaticFile]) -> StaticDir {
+        StaticDir {
+            files,
+            prefix: String::new(),
+            cache_control: HeaderValue::from_static("no-cache"),
+        }
+    }
+    /// Serve files under the specified path (relative to the mount prefix)
+    ///
+    /// For example, with prefix `/static` file `assets/app.css` is served at
+    /// `/db/<database>/wasm/<module>/static/app.css`.
+    pub fn prefix(mut self, prefix: &str) -> StaticDir {
+        self.prefix = prefix.trim_end_matches('/').to_string();
+        self
+    }
+    /// Set `Cache-Control` header of the responses
+    ///
+    /// Default is `no-cache`, which makes browser revalidate file using
+    /// `ETag` on every use.
+    ///
+    /// # Panics
+    ///
+    /// Panics if value is not a valid header value.
+    pub fn cache_control(mut self, value: &str) -> StaticDir {
+        self.cache_control = HeaderValue::from_str(value)
+            .expect("cache control is a valid header value");
+        self
+    }
+    /// Find file by path relative to the embedded directory
+    fn find(&self, path: &str) -> Option<&'static StaticFile> {
+        self.files.binary_search_by(|f| f.path.cmp(path)).ok()
+            .map(|idx| &self.files[idx])
+    }
+    fn lookup(&self, req: &Request) -> Option<&'static StaticFile> {
+        let path = req.relative_path().strip_prefix(&self.prefix[..])?;
+        if !path.is_empty() && !path.starts_with('/') {
+            return None;
+        }
+        let path = path.trim_start_matches('/');
+        if path.is_empty() || path.ends_with('/') {
+            return self.find(&format!("{}index.html", path));
+        }
+        self.find(path)
+    }
+    /// Returns response for the file if request matches one
+    ///
+    /// This can be used to serve files from a handler directly instead of
+    /// using the directory as a middleware.
+    pub fn serve(&self, req: &Request) -> Option<Response> {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            return None;
+        }
+        let file = self.lookup(req)?;
+        let (encoding, data) = match (file.brotli, file.gzip) {
+            (Some(data), _) if accepts_encoding(req, "br") => {
+                (Some("br"), data)
+            }
+            (_, Some(data)) if accepts_encoding(req, "gzip") => {
+                (Some("gzip"), data)
+            }
+            _ => (None, file.data),
+        };
+        // each encoding is a different representation, so has own etag
+        let etag = match encoding {
+            Some(enc) => {
+                let hash = file.etag.trim_end_matches('"');
+                format!("{}-{}\"", hash, enc)
+            }
+            None => file.etag.to_string(),
+        };
+        let mut resp = web::response()
+            .header(header::ETAG, &etag)
+            .header(header::CACHE_CONTROL, self.cache_control.clone());
+        if file.gzip.is_some() || file.brotli.is_some() {
+            resp = resp.header(header::VARY, "Accept-Encoding");
+        }
+        if etag_matches(req, &etag) {
+            return Some(resp
+                .status(StatusCode::NOT_MODIFIED)
+                .body(Body::empty())
+                .expect("response is built"));
+        }
+        resp = resp
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, file.content_type);
+        if let Some(encoding) = encoding {
+            resp = resp.header(header::CONTENT_ENCODING, encoding);
+        }
+        let body = if req.method() == Method::HEAD {
+            Body::empty()
+        } else {
+            data.into()
+        };
+        Some(resp.body(body).expect("response is built"))
+    }
+}
+
+impl fmt::Debug for StaticFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StaticFile")
+            .field("path", &self.path)
+            .field("content_type", &self.content_type)
+            .field("size", &self.data.len())
+            .field("gzip", &self.gzip.is_some())
+            .field("brotli", &self.brotli.is_some())
+            .finish()
+    }
+}
+
+impl Middleware for StaticDir {
+    fn call(&self, req: Request, next: Next<'_>) -> Response {
+        match self.serve(&req) {
+            Some(resp) => resp,
+            None => next.run(req),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use http::header;
+    use http::Method;
+
+    use crate::web::{self, Response, StatusCode};
+    use super::{StaticDir, StaticFile};
+
+    static FILES: &[StaticFile] = &[
+        StaticFile {
+            path: "app.css",
+            content_type: "text/css",
+            etag: "\"abc\"",
+            data: b"body {}",
+            gzip: Some(b"gzipped"),
+            brotli: Some(b"brotli"),
+        },
+        StaticFile {
+            path: "index.html",
+            content_type: "text/html",
+            etag: "\"def\"",
+            data: b"<html>",
+            gzip: None,
+            brotli: None,
+        },
+        StaticFile {
+            path: "sub/index.html",
+            content_type: "text/html",
+            etag: "\"ghi\"",
+            data: b"<sub>",
+            gzip: None,
+            brotli: None,
+        },
+    ];
+
+    fn serve(dir: &StaticDir, method: Method, path: &str,
+             headers: &[(&str, &str)])
+        -> Option<Response>
+    {
+        let mut req = http::Request::builder().method(method)
+            .uri(format!("/db/edgedb/wasm/test{}", path));
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        dir.serve(&web::test_request(req.body(Vec::new()).unwrap()))
+    }
+
+    fn get(path: &str, headers: &[(&str, &str)]) -> Option<Response> {
+        serve(&StaticDir::new(FILES), Method::GET, path, headers)
+    }
+
+    fn header(resp: &Response, name: header::HeaderName) -> Option<&str> {
+        resp.headers().get(name).map(|v| v.to_str().unwrap())
+    }
+
+    #[test]
+    fn lookup() {
+        let resp = get("/app.css", &[]).unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(header(&resp, header::CONTENT_TYPE), Some("text/css"));
+        assert_eq!(resp.body().as_bytes(), Some(&b"body {}"[..]));
+        assert_eq!(get("/", &[]).unwrap().body().as_bytes(),
+                   Some(&b"<html>"[..]));
+        assert_eq!(get("/sub/", &[]).unwrap().body().as_bytes(),
+                   Some(&b"<sub>"[..]));
+        assert!(get("/sub", &[]).is_none());
+        assert!(get("/missing.js", &[]).is_none());
+    }
+
+    #[test]
+    fn prefix() {
+        let dir = StaticDir::new(FILES).prefix("/static/");
+        assert!(serve(&dir, Method::GET, "/static/app.css", &[]).is_some());
+        assert!(serve(&dir, Method::GET, "/static", &[]).is_some());
+        assert!(serve(&dir, Method::GET, "/staticapp.css", &[]).is_none());
+        assert!(serve(&dir, Method::GET, "/app.css", &[]).is_none());
+    }
+
+    #[test]
+    fn methods() {
+        let dir = StaticDir::new(FILES);
+        let resp = serve(&dir, Method::HEAD, "/app.css", &[]).unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.body().as_bytes(), Some(&b""[..]));
+        assert!(serve(&dir, Method::POST, "/app.css", &[]).is_none());
+    }
+
+    #[test]
+    fn etag() {
+        let resp = get("/app.css", &[]).unwrap();
+        assert_eq!(header(&resp, header::ETAG), Some("\"abc\""));
+        assert_eq!(header(&resp, header::CACHE_CONTROL), Some("no-cache"));
+        for value in ["\"abc\"", "W/\"abc\"", "\"xyz\", \"abc\"", "*"] {
+            let resp = get("/app.css", &[("If-None-Match", value)]).unwrap();
+            assert_eq!(resp.status(), StatusCode::NOT_MODIFIED, "{}", value);
+            assert_eq!(resp.body().as_bytes(), Some(&b""[..]));
+        }
+        let resp = get("/app.css", &[("If-None-Match", "\"xyz\"")]).unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn encoding() {
+        let resp = get("/app.css", &[("Accept-Encoding", "gzip, br")])
+            .unwrap();
+        assert_eq!(header(&resp, header::CONTENT_ENCODING), Some("br"));
+        assert_eq!(header(&resp, header::ETAG), Some("\"abc-br\""));
+        assert_eq!(header(&resp, header::VARY), Some("Accept-Encoding"));
+        assert_eq!(resp.body().as_bytes(), Some(&b"brotli"[..]));
+
+        let resp = get("/app.css", &[("Accept-Encoding", "gzip;q=0.8")])
+            .unwrap();
+        assert_eq!(header(&resp, header::CONTENT_ENCODING), Some("gzip"));
+        assert_eq!(header(&resp, header::ETAG), Some("\"abc-gzip\""));
+        assert_eq!(resp.body().as_bytes(), Some(&b"gzipped"[..]));
+
+        let resp = get("/app.css", &[("Accept-Encoding", "br;q=0, gzip")])
+            .unwrap();
+        assert_eq!(header(&resp, header::CONTENT_ENCODING), Some("gzip"));
+
+        let resp = get("/app.css", &[("Accept-Encoding", "identity")])
+            .unwrap();
+        assert_eq!(header(&resp, header::CONTENT_ENCODING), None);
+        assert_eq!(header(&resp, header::VARY), Some("Accept-Encoding"));
+        assert_eq!(resp.body().as_bytes(), Some(&b"body {}"[..]));
+    }
+
+    #[test]
+    fn encoding_etag() {
+        let headers = [
+            ("Accept-Encoding", "gzip"),
+            ("If-None-Match", "\"abc\""),
+        ];
+        // plain etag doesn't match the compressed representation
+        let resp = get("/app.css", &headers).unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let headers = [
+            ("Accept-Encoding", "gzip"),
+            ("If-None-Match", "\"abc-gzip\""),
+        ];
+        let resp = get("/app.css", &headers).unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[test]
+    fn uncompressed_file() {
+        let resp = get("/index.html", &[("Accept-Encoding", "br, gzip")])
+            .unwrap();
+        assert_eq!(header(&resp, header::CONTENT_ENCODING), None);
+        assert_eq!(header(&resp, header::VARY), None);
+        assert_eq!(header(&resp, header::ETAG), Some("\"def\""));
+    }
+}
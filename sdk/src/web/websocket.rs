@@ -0,0 +1,132 @@
+//! WebSocket connections
+//!
+//! Register a [`Handler`] to accept WebSocket connections at the mount
+//! prefix of the module (or any path under it):
+//!
+//! ```rust,no_run
+//! use edgedb_sdk::{init_hook, web};
+//! use edgedb_sdk::web::websocket::{self, Connection, Message};
+//!
+//! struct Echo;
+//!
+//! impl websocket::Handler for Echo {
+//!     fn on_message(&self, conn: Connection, message: Message) {
+//!         if let Err(e) = conn.send(&message) {
+//!             log::debug!("Cannot send message: {}", e);
+//!         }
+//!     }
+//! }
+//!
+//! #[init_hook]
+//! fn init() {
+//!     websocket::register_handler(Echo);
+//! }
+//! ```
+//!
+//! Every callback is a separate call into the module, so the module handles
+//! other requests and connections between messages. Only the HTTP (test)
+//! mode of the server supports WebSockets for now.
+use once_cell::sync::OnceCell;
+
+use crate::web::{Request, StreamError};
+
+wit_bindgen_rust::import!("../wit/edgedb_websocket_connection_v1.wit");
+
+use edgedb_websocket_connection_v1 as v1;
+
+pub(crate) static HANDLER: OnceCell<Box<dyn Handler>> = OnceCell::new();
+
+/// Message received or sent over the connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// Text message
+    Text(String),
+    /// Binary message
+    Binary(Vec<u8>),
+}
+
+/// Handle of the open WebSocket connection
+///
+/// Connection ids are unique within the module instance only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Connection {
+    id: u64,
+}
+
+/// Handler of WebSocket connections
+pub trait Handler: Send + Sync + 'static {
+    /// Called on the upgrade request, connection is rejected with
+    /// `403 Forbidden` if `false` is returned
+    ///
+    /// Messages sent from this method are delivered right after handshake.
+    /// By default all connections are accepted.
+    fn on_open(&self, conn: Connection, req: &Request) -> bool {
+        let _ = (conn, req);
+        true
+    }
+    /// Called for each message received
+    fn on_message(&self, conn: Connection, message: Message);
+    /// Called when connection is closed by either side
+    ///
+    /// `code` is `None` if peer has disconnected without closing handshake.
+    fn on_close(&self, conn: Connection, code: Option<u16>, reason: &str) {
+        let _ = (conn, code, reason);
+    }
+}
+
+/// Register WebSocket handler
+///
+/// # Panics
+///
+/// Panics if called more than once.
+pub fn register_handler(handler: impl Handler) {
+    if HANDLER.set(Box::new(handler)).is_err() {
+        panic!("only one websocket handler is expected");
+    }
+}
+
+impl From<v1::SendError> for StreamError {
+    fn from(e: v1::SendError) -> StreamError {
+        match e {
+            v1::SendError::Closed => StreamError::Closed,
+        }
+    }
+}
+
+impl Connection {
+    pub(crate) fn new(id: u64) -> Connection {
+        Connection { id }
+    }
+    /// Unique id of the connection
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+    /// Send a message
+    ///
+    /// Returns [`StreamError::Closed`] if connection is already closed.
+    pub fn send(&self, message: &Message) -> Result<(), StreamError> {
+        let message = match message {
+            Message::Text(text) => v1::Message::Text(text),
+            Message::Binary(data) => v1::Message::Binary(data),
+        };
+        v1::send(self.id, message)?;
+        Ok(())
+    }
+    /// Send a text message
+    pub fn send_text(&self, text: &str) -> Result<(), StreamError> {
+        v1::send(self.id, v1::Message::Text(text))?;
+        Ok(())
+    }
+    /// Send a binary message
+    pub fn send_binary(&self, data: &[u8]) -> Result<(), StreamError> {
+        v1::send(self.id, v1::Message::Binary(data))?;
+        Ok(())
+    }
+    /// Start closing handshake
+    ///
+    /// [`Handler::on_close`] is called when handshake is finished.
+    pub fn close(&self, code: u16, reason: &str) -> Result<(), StreamError> {
+        v1::close(self.id, code, reason)?;
+        Ok(())
+    }
+}
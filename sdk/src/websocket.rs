@@ -0,0 +1,52 @@
+use crate::web::{self, RequestMeta};
+use crate::web::websocket::{Connection, Message, HANDLER};
+
+wit_bindgen_rust::export!("../wit/edgedb_websocket_v1.wit");
+
+use edgedb_websocket_v1 as v1;
+
+struct EdgedbWebsocketV1 {
+}
+
+impl v1::EdgedbWebsocketV1 for EdgedbWebsocketV1 {
+    fn on_open(connection: u64, req: v1::Request) -> bool {
+        let handler = if let Some(handler) = HANDLER.get() {
+            handler
+        } else {
+            // TODO(tailhook) only in debug mode
+            log::warn!("WebSocket connection rejected: handler is not set");
+            return false;
+        };
+        let mut bld = http::Request::builder();
+        bld = bld.method(&req.method[..]);
+        bld = bld.uri(&req.uri);
+        for (k, v) in req.headers {
+            bld = bld.header(k, v);
+        }
+        let inner = bld.body(Vec::new()).expect("can build request");
+        let meta = RequestMeta {
+            database: req.database,
+            module_name: req.module_name,
+            mount_prefix: req.mount_prefix,
+            scheme: req.scheme,
+            host: req.host,
+            remote_addr: req.remote_addr.and_then(|a| a.parse().ok()),
+        };
+        let request = web::Request { inner, meta, body_reader: None };
+        handler.on_open(Connection::new(connection), &request)
+    }
+    fn on_message(connection: u64, message: v1::Message) {
+        let message = match message {
+            v1::Message::Text(text) => Message::Text(text),
+            v1::Message::Binary(data) => Message::Binary(data),
+        };
+        if let Some(handler) = HANDLER.get() {
+            handler.on_message(Connection::new(connection), message);
+        }
+    }
+    fn on_close(connection: u64, code: Option<u16>, reason: String) {
+        if let Some(handler) = HANDLER.get() {
+            handler.on_close(Connection::new(connection), code, &reason);
+        }
+    }
+}
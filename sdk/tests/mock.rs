@@ -0,0 +1,90 @@
+use edgedb_sdk::client::create_client;
+use edgedb_sdk::client::errors::{ErrorKind, ClientError, NoDataError};
+use edgedb_sdk::testing::{MockClient, request};
+use edgedb_sdk::web;
+
+fn handler(req: web::Request) -> web::Response {
+    let id = req.relative_path().trim_start_matches('/').to_string();
+    let result = create_client().query_required_single::<String, _>(
+        "SELECT User { name } FILTER .id = <uuid>$0", &(id,));
+    match result {
+        Ok(name) => web::response()
+            .status(web::StatusCode::OK)
+            .body(name.into())
+            .expect("response is built"),
+        Err(e) if e.is::<NoDataError>() => web::response()
+            .status(web::StatusCode::NOT_FOUND)
+            .body("not found".into())
+            .expect("response is built"),
+        Err(e) => web::response()
+            .status(web::StatusCode::INTERNAL_SERVER_ERROR)
+            .body(e.to_string().into())
+            .expect("response is built"),
+    }
+}
+
+fn get(path: &str) -> web::Response {
+    let uri = format!("/db/edgedb/wasm/test{}", path);
+    handler(request(http::Request::get(uri).body(Vec::new()).unwrap()))
+}
+
+#[test]
+fn rows() {
+    let _mock = MockClient::new()
+        .expect_rows("SELECT User { name }  FILTER .id = <uuid>$0",
+                     vec![String::from("alice")])
+        .install();
+    let resp = get("/42");
+    assert_eq!(resp.status(), web::StatusCode::OK);
+    assert_eq!(resp.body().as_bytes(), Some(&b"alice"[..]));
+}
+
+#[test]
+fn empty() {
+    let _mock = MockClient::new()
+        .expect_empty("SELECT User { name } FILTER .id = <uuid>$0")
+        .install();
+    assert_eq!(get("/42").status(), web::StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn error() {
+    let _mock = MockClient::new()
+        .expect_error("SELECT User { name } FILTER .id = <uuid>$0",
+                      ClientError::with_message("failed"))
+        .install();
+    assert_eq!(get("/42").status(), web::StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[test]
+fn json_in_transaction() {
+    let _mock = MockClient::new()
+        .expect_json("SELECT Counter { value }", r#"[{"value": 1}]"#)
+        .expect_json("SELECT 1", "1")
+        .install();
+    let client = create_client();
+    let (all, one) = client.transaction(|tx| {
+        let all = tx.query_json("SELECT Counter { value }", &())?;
+        let one = tx.query_required_single_json("SELECT 1", &())?;
+        Ok((all, one))
+    }).unwrap();
+    assert_eq!(&*all, r#"[{"value": 1}]"#);
+    assert_eq!(&*one, "1");
+}
+
+#[test]
+fn request_meta() {
+    let req = request(http::Request::get("/db/edgedb/wasm/test/x?y=1")
+                      .body(Vec::new()).unwrap());
+    assert_eq!(req.database(), "edgedb");
+    assert_eq!(req.module_name(), "test");
+    assert_eq!(req.relative_path(), "/x");
+}
+
+#[test]
+#[should_panic(expected = "expected queries were not executed")]
+fn not_executed() {
+    let _mock = MockClient::new()
+        .expect_empty("SELECT 1")
+        .install();
+}
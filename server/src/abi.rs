@@ -1,3 +1,19 @@
 pub mod log_v1;
+pub mod log_v2;
+pub mod log_level_v1;
 pub mod http_server_v1;
+pub mod http_server_v2;
+pub mod http_body_v1;
 pub mod client_v1;
+pub mod websocket_v1;
+pub mod websocket_connection_v1;
+pub mod scheduler_v1;
+pub mod http_client_v1;
+pub mod cache_v1;
+pub mod config_v1;
+pub mod config_notify_v1;
+pub mod secrets_v1;
+pub mod metrics_v1;
+pub mod jobs_v1;
+pub mod job_runner_v1;
+pub mod invoke_v1;
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use lru::LruCache;
+
+use crate::worker::{self, Name};
+
+wit_bindgen_wasmtime::export!("../wit/edgedb_cache_v1.wit");
+
+use edgedb_cache_v1 as v1;
+
+pub use edgedb_cache_v1::add_to_linker;
+
+/// Maximum size of entries cached for a single database
+pub const MAX_SIZE_PER_DATABASE: usize = 64 << 20;
+/// Approximate memory used by an entry besides its key and value
+///
+/// Accounts for the LRU list node, the hash table slot and allocation
+/// headers, so that many tiny entries can't exceed the limit.
+const ENTRY_OVERHEAD: usize = 128;
+
+/// Caches of all databases of the tenant
+///
+/// This is stored in the tenant rather than in a worker, so that cache is
+/// shared between instances and survives reloads.
+#[derive(Debug, Clone, Default)]
+pub struct Caches(Arc<Mutex<HashMap<String, Namespace>>>);
+
+#[derive(Debug)]
+struct Namespace {
+    entries: LruCache<String, Entry>,
+    size: usize,
+    max_size: usize,
+}
+
+#[derive(Debug)]
+struct Entry {
+    value: Vec<u8>,
+    expires: Option<Instant>,
+}
+
+pub struct State {
+    caches: Caches,
+    name: Arc<Name>,
+}
+
+fn entry_size(key: &str, value: &[u8]) -> usize {
+    ENTRY_OVERHEAD + key.len() + value.len()
+}
+
+impl Entry {
+    fn is_expired(&self) -> bool {
+        self.expires.map(|e| e <= Instant::now()).unwrap_or(false)
+    }
+}
+
+impl Namespace {
+    fn new(max_size: usize) -> Namespace {
+        Namespace {
+            entries: LruCache::unbounded(),
+            size: 0,
+            max_size,
+        }
+    }
+    fn get(&mut self, key: &str) -> Option<Vec<u8>> {
+        match self.entries.get(key) {
+            Some(entry) if entry.is_expired() => {}
+            Some(entry) => return Some(entry.value.clone()),
+            None => return None,
+        }
+        self.remove(key);
+        None
+    }
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.entries.pop(key)?;
+        self.size -= entry_size(key, &entry.value);
+        Some(entry)
+    }
+    fn insert(&mut self, key: String, entry: Entry) -> Result<(), v1::Error> {
+        let size = entry_size(&key, &entry.value);
+        if size > self.max_size {
+            return Err(v1::Error::TooLarge);
+        }
+        self.remove(&key);
+        self.size += size;
+        self.entries.put(key, entry);
+        while self.size > self.max_size {
+            match self.entries.pop_lru() {
+                Some((key, entry)) => {
+                    self.size -= entry_size(&key, &entry.value);
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Caches {
+    fn with<R>(&self, database: &str, f: impl FnOnce(&mut Namespace) -> R)
+        -> R
+    {
+        let mut caches = self.0.lock().expect("cache is not poisoned");
+        let ns = caches.entry(database.into())
+            .or_insert_with(|| Namespace::new(MAX_SIZE_PER_DATABASE));
+        f(ns)
+    }
+}
+
+impl State {
+    pub fn new(caches: &Caches, name: &Arc<Name>) -> State {
+        State {
+            caches: caches.clone(),
+            name: name.clone(),
+        }
+    }
+}
+
+impl v1::EdgedbCacheV1 for worker::State {
+    fn get(&mut self, key: &str) -> Option<Vec<u8>> {
+        let cache = &self.cache_v1;
+        cache.caches.with(&cache.name.database, |ns| ns.get(key))
+    }
+    fn set(&mut self, key: &str, value: &[u8], ttl_ms: Option<u64>)
+        -> Result<(), v1::Error>
+    {
+        let entry = Entry {
+            value: value.to_vec(),
+            // too far in the future is the same as never
+            expires: ttl_ms.and_then(|ms| {
+                Instant::now().checked_add(Duration::from_millis(ms))
+            }),
+        };
+        let cache = &self.cache_v1;
+        cache.caches.with(&cache.name.database, |ns| {
+            ns.insert(key.into(), entry)
+        })
+    }
+    fn delete(&mut self, key: &str) -> bool {
+        let cache = &self.cache_v1;
+        cache.caches.with(&cache.name.database, |ns| {
+            ns.remove(key).map(|e| !e.is_expired()).unwrap_or(false)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{entry_size, Entry, Namespace};
+
+    fn entry(value: &[u8], expires: Option<Instant>) -> Entry {
+        Entry { value: value.to_vec(), expires }
+    }
+
+    #[test]
+    fn evict_least_recently_used() {
+        let mut ns = Namespace::new(entry_size("a", b"1234") * 2);
+        ns.insert("a".into(), entry(b"1234", None)).unwrap();
+        ns.insert("b".into(), entry(b"1234", None)).unwrap();
+        assert_eq!(ns.get("a"), Some(b"1234".to_vec()));
+        ns.insert("c".into(), entry(b"1234", None)).unwrap();
+        assert_eq!(ns.get("b"), None);
+        assert_eq!(ns.get("a"), Some(b"1234".to_vec()));
+        assert_eq!(ns.get("c"), Some(b"1234".to_vec()));
+        assert_eq!(ns.size, entry_size("a", b"1234") * 2);
+    }
+
+    #[test]
+    fn overhead() {
+        let mut ns = Namespace::new(entry_size("00", b"") * 10);
+        for i in 0..100 {
+            ns.insert(format!("{:02}", i), entry(b"", None)).unwrap();
+        }
+        assert_eq!(ns.entries.len(), 10);
+        assert_eq!(ns.get("89"), None);
+        assert_eq!(ns.get("90"), Some(Vec::new()));
+    }
+
+    #[test]
+    fn replace() {
+        let mut ns = Namespace::new(1024);
+        ns.insert("a".into(), entry(b"1234", None)).unwrap();
+        ns.insert("a".into(), entry(b"12", None)).unwrap();
+        assert_eq!(ns.get("a"), Some(b"12".to_vec()));
+        assert_eq!(ns.size, entry_size("a", b"12"));
+    }
+
+    #[test]
+    fn too_large() {
+        let mut ns = Namespace::new(entry_size("a", b"1234"));
+        ns.insert("a".into(), entry(b"1234", None)).unwrap();
+        assert!(ns.insert("b".into(), entry(b"12345", None)).is_err());
+        assert_eq!(ns.get("a"), Some(b"1234".to_vec()));
+    }
+
+    #[test]
+    fn expire() {
+        let mut ns = Namespace::new(1024);
+        let now = Instant::now();
+        ns.insert("a".into(), entry(b"1", Some(now))).unwrap();
+        let later = now + Duration::from_secs(3600);
+        ns.insert("b".into(), entry(b"2", Some(later))).unwrap();
+        assert_eq!(ns.get("a"), None);
+        assert_eq!(ns.get("b"), Some(b"2".to_vec()));
+        assert_eq!(ns.size, entry_size("b", b"2"));
+    }
+}
@@ -0,0 +1,7 @@
+wit_bindgen_wasmtime::import!({
+    paths: ["../wit/edgedb_config_notify_v1.wit"],
+    async: *,
+});
+
+pub use edgedb_config_notify_v1::EdgedbConfigNotifyV1 as Handler;
+pub use edgedb_config_notify_v1::EdgedbConfigNotifyV1Data as State;
@@ -0,0 +1,67 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::registry::Registry;
+use crate::worker::{self, Name};
+
+wit_bindgen_wasmtime::export!("../wit/edgedb_config_v1.wit");
+
+use edgedb_config_v1 as v1;
+
+pub use edgedb_config_v1::add_to_linker;
+
+pub type Values = BTreeMap<String, String>;
+
+/// Configuration of all modules by database and module name
+///
+/// This is stored in the tenant, so values are kept when modules reload.
+#[derive(Debug, Clone, Default)]
+pub struct Configs(Registry<Values>);
+
+pub struct State {
+    configs: Configs,
+    name: Arc<Name>,
+}
+
+impl Configs {
+    /// Replaces all values of the module, returns keys that have changed
+    pub fn set(&self, database: &str, module_name: &str, values: Values)
+        -> Vec<String>
+    {
+        let old = self.0.insert(database, module_name, values.clone())
+            .unwrap_or_default();
+        let mut changed = values.iter()
+            .filter(|(k, v)| old.get(*k) != Some(v))
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<_>>();
+        changed.extend(
+            old.keys().filter(|k| !values.contains_key(*k)).cloned());
+        changed.sort();
+        changed
+    }
+    fn get(&self, name: &Name) -> Option<Arc<Values>> {
+        self.0.get(name)
+    }
+}
+
+impl State {
+    pub fn new(configs: &Configs, name: &Arc<Name>) -> State {
+        State {
+            configs: configs.clone(),
+            name: name.clone(),
+        }
+    }
+}
+
+impl v1::EdgedbConfigV1 for worker::State {
+    fn get(&mut self, key: &str) -> Option<String> {
+        let config = &self.config_v1;
+        config.configs.get(&config.name)?.get(key).cloned()
+    }
+    fn keys(&mut self) -> Vec<String> {
+        let config = &self.config_v1;
+        config.configs.get(&config.name)
+            .map(|values| values.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,149 @@
+wit_bindgen_wasmtime::export!({
+    paths: ["../wit/edgedb_http_body_v1.wit"],
+    async: *,
+});
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tokio::sync::{mpsc, Mutex};
+
+pub use edgedb_http_body_v1 as v1;
+pub use edgedb_http_body_v1::add_to_linker;
+pub use edgedb_http_body_v1::EdgedbHttpBodyV1Tables as Tables;
+pub use edgedb_http_body_v1::StreamError;
+
+
+pub type Context<'a> = (&'a mut InnerState, &'a mut Tables<InnerState>);
+
+/// Number of chunks buffered between the worker and the client connection
+const CHANNEL_BUFFER: usize = 16;
+
+/// Source of the request body (implemented by each frontend protocol)
+#[async_trait::async_trait]
+pub trait Source: Send {
+    /// Returns empty bytes at the end of stream
+    async fn read(&mut self, max_size: usize) -> Result<Bytes, StreamError>;
+}
+
+/// Destination of the response body
+#[async_trait::async_trait]
+pub trait Sink: Send {
+    async fn write(&mut self, data: Bytes) -> Result<(), StreamError>;
+    /// Resolves when the peer has disconnected
+    async fn closed(&mut self);
+}
+
+/// Sink that sends data to the connection handler through a channel
+///
+/// Receiver is dropped by the connection handler when peer disconnects.
+pub struct ChannelSink {
+    sender: mpsc::Sender<Bytes>,
+}
+
+type SharedSink = Arc<Mutex<Option<Box<dyn Sink>>>>;
+
+#[derive(Default)]
+pub struct State {
+    inner: InnerState,
+    tables: Tables<InnerState>,
+}
+
+#[derive(Default)]
+pub struct InnerState {
+    request_body: Option<Box<dyn Source>>,
+    response_body: Option<SharedSink>,
+}
+
+pub struct BodyReader {
+    source: Mutex<Box<dyn Source>>,
+}
+
+pub struct BodyWriter {
+    sink: SharedSink,
+}
+
+pub fn channel() -> (Box<dyn Sink>, mpsc::Receiver<Bytes>) {
+    let (sender, receiver) = mpsc::channel(CHANNEL_BUFFER);
+    (Box::new(ChannelSink { sender }), receiver)
+}
+
+#[async_trait::async_trait]
+impl Sink for ChannelSink {
+    async fn write(&mut self, data: Bytes) -> Result<(), StreamError> {
+        self.sender.send(data).await.map_err(|_| StreamError::Closed)
+    }
+    async fn closed(&mut self) {
+        self.sender.closed().await
+    }
+}
+
+impl State {
+    pub fn context(&mut self) -> Context<'_> {
+        (&mut self.inner, &mut self.tables)
+    }
+    /// Makes request body available for `body-reader.request-body`
+    pub fn start_request(&mut self, body: Box<dyn Source>) {
+        self.inner.request_body = Some(body);
+    }
+    /// Drops request body if it wasn't taken by the handler
+    pub fn finish_request(&mut self) {
+        self.inner.request_body = None;
+    }
+    /// Makes response body available for `body-writer.response-body`
+    pub fn start_response(&mut self, body: Box<dyn Sink>) {
+        self.inner.response_body = Some(Arc::new(Mutex::new(Some(body))));
+    }
+    /// Closes response body
+    ///
+    /// This finishes the stream even if the writer is still referenced by
+    /// the guest.
+    pub async fn finish_response(&mut self) {
+        if let Some(sink) = self.inner.response_body.take() {
+            sink.lock().await.take();
+        }
+    }
+}
+
+#[wit_bindgen_wasmtime::async_trait]
+impl v1::EdgedbHttpBodyV1 for InnerState {
+    type BodyReader = BodyReader;
+    type BodyWriter = BodyWriter;
+    async fn body_reader_request_body(&mut self) -> Option<BodyReader> {
+        self.request_body.take().map(|source| BodyReader {
+            source: Mutex::new(source),
+        })
+    }
+    async fn body_reader_read(&mut self, me: &BodyReader, max_size: u32)
+        -> Result<Vec<u8>, StreamError>
+    {
+        let data = me.source.lock().await.read(max_size as usize).await?;
+        Ok(data.to_vec())
+    }
+    async fn body_writer_response_body(&mut self) -> Option<BodyWriter> {
+        self.response_body.clone().map(|sink| BodyWriter { sink })
+    }
+    async fn body_writer_write(&mut self, me: &BodyWriter, data: &[u8])
+        -> Result<(), StreamError>
+    {
+        match &mut *me.sink.lock().await {
+            Some(sink) => sink.write(Bytes::copy_from_slice(data)).await,
+            None => Err(StreamError::Closed),
+        }
+    }
+    async fn body_writer_wait(&mut self, me: &BodyWriter, timeout_ms: u32)
+        -> Result<(), StreamError>
+    {
+        let timeout = Duration::from_millis(timeout_ms.into());
+        match &mut *me.sink.lock().await {
+            Some(sink) => {
+                match tokio::time::timeout(timeout, sink.closed()).await {
+                    Ok(()) => Err(StreamError::Closed),
+                    Err(_) => Ok(()),
+                }
+            }
+            None => Err(StreamError::Closed),
+        }
+    }
+}
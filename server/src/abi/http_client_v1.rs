@@ -0,0 +1,374 @@
+wit_bindgen_wasmtime::export!({
+    paths: ["../wit/edgedb_http_client_v1.wit"],
+    async: *,
+});
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::body::HttpBody;
+use hyper::client::HttpConnector;
+use hyper::header::CONTENT_LENGTH;
+use hyper_rustls::HttpsConnector;
+
+use crate::registry::Registry;
+use crate::worker::Name;
+
+pub use edgedb_http_client_v1 as v1;
+pub use edgedb_http_client_v1::add_to_linker;
+pub use edgedb_http_client_v1::Error;
+
+pub type Client = hyper::Client<HttpsConnector<HttpConnector>>;
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_MAX_RESPONSE_SIZE: usize = 10 << 20;
+
+/// Outbound requests allowed for a module
+#[derive(Debug, Clone)]
+pub struct Policy {
+    /// Either `example.com`, `*.example.com` (subdomains only),
+    /// `localhost:8080` (only this port) or `*` (any host)
+    ///
+    /// IPv6 addresses must be enclosed in brackets: `[::1]:8080`.
+    pub allowed_hosts: Vec<String>,
+    /// Maximum time of the request including reading response body
+    pub timeout: Duration,
+    pub max_response_size: usize,
+}
+
+/// Policies of all modules by database and module name
+///
+/// Modules without a policy can't make any requests.
+#[derive(Debug, Clone, Default)]
+pub struct Policies(Registry<Policy>);
+
+pub struct State {
+    client: Client,
+    policies: Policies,
+    name: Arc<Name>,
+}
+
+pub fn client() -> Client {
+    let connector = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_webpki_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    hyper::Client::builder().build(connector)
+}
+
+/// Both `pattern` and `host` are expected to be lowercase
+fn host_matches(pattern: &str, host: &str, port: u16) -> bool {
+    let (pattern, pattern_port) = match pattern.rsplit_once(':') {
+        // colon inside of brackets is a part of IPv6 address
+        Some((host, port)) if !port.ends_with(']') => {
+            match port.parse::<u16>() {
+                Ok(port) => (host, Some(port)),
+                Err(_) => return false,
+            }
+        }
+        _ => (pattern, None),
+    };
+    if pattern_port.map(|p| p != port).unwrap_or(false) {
+        return false;
+    }
+    if pattern == "*" {
+        return true;
+    }
+    if let Some(domain) = pattern.strip_prefix("*.") {
+        return host.len() > domain.len() + 1 &&
+            host.ends_with(domain) &&
+            host[..host.len() - domain.len()].ends_with('.');
+    }
+    pattern.eq_ignore_ascii_case(host)
+}
+
+impl Policy {
+    pub fn allows(&self, uri: &hyper::Uri) -> bool {
+        let port = match (uri.port_u16(), uri.scheme_str()) {
+            (Some(port), _) => port,
+            (None, Some("http")) => 80,
+            (None, Some("https")) => 443,
+            _ => return false,
+        };
+        let host = match uri.host() {
+            Some(host) => host.to_ascii_lowercase(),
+            None => return false,
+        };
+        self.allowed_hosts.iter().any(|p| host_matches(p, &host, port))
+    }
+}
+
+impl Policies {
+    pub fn set(&self, database: &str, module_name: &str, mut policy: Policy)
+    {
+        for host in &mut policy.allowed_hosts {
+            host.make_ascii_lowercase();
+        }
+        self.0.insert(database, module_name, policy);
+    }
+    fn get(&self, name: &Name) -> Option<Arc<Policy>> {
+        self.0.get(name)
+    }
+}
+
+impl State {
+    pub fn new(client: &Client, policies: &Policies, name: &Arc<Name>)
+        -> State
+    {
+        State {
+            client: client.clone(),
+            policies: policies.clone(),
+            name: name.clone(),
+        }
+    }
+    async fn send(&self, request: hyper::Request<hyper::Body>,
+                  max_size: usize)
+        -> Result<v1::Response, Error>
+    {
+        let response = self.client.request(request).await
+            .map_err(|e| Error::Failed(e.to_string()))?;
+        let (parts, mut body) = response.into_parts();
+        let length = parts.headers.get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<usize>().ok());
+        if length.map(|l| l > max_size).unwrap_or(false) {
+            return Err(Error::TooLarge);
+        }
+        let mut buf = Vec::with_capacity(length.unwrap_or(0));
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.map_err(|e| Error::Failed(e.to_string()))?;
+            if buf.len() + chunk.len() > max_size {
+                return Err(Error::TooLarge);
+            }
+            buf.extend_from_slice(&chunk);
+        }
+        Ok(v1::Response {
+            status_code: parts.status.as_u16(),
+            headers: parts.headers.iter()
+                .map(|(n, v)| (n.as_str().into(), v.as_bytes().into()))
+                .collect(),
+            body: buf,
+        })
+    }
+}
+
+#[wit_bindgen_wasmtime::async_trait]
+impl v1::EdgedbHttpClientV1 for State {
+    async fn fetch(&mut self, req: v1::Request<'_>)
+        -> Result<v1::Response, Error>
+    {
+        let uri: hyper::Uri = req.uri.parse()
+            .map_err(|e| Error::InvalidRequest(format!("{:#}", e)))?;
+        let policy = match self.policies.get(&self.name) {
+            Some(policy) if policy.allows(&uri) => policy,
+            _ => {
+                let host = uri.host().unwrap_or("");
+                log::warn!("Module {}::{} is not allowed to fetch from {:?}",
+                           self.name.database, self.name.wasm_name, host);
+                return Err(Error::NotAllowed(format!(
+                    "host {:?} is not in the allowlist", host)));
+            }
+        };
+        let mut builder = hyper::Request::builder()
+            .method(req.method)
+            .uri(uri);
+        for (name, value) in req.headers {
+            builder = builder.header(name, value);
+        }
+        let request = builder.body(req.body.to_vec().into())
+            .map_err(|e| Error::InvalidRequest(format!("{:#}", e)))?;
+        let timeout = req.timeout_ms
+            .map(|ms| Duration::from_millis(ms.into()).min(policy.timeout))
+            .unwrap_or(policy.timeout);
+        let send = self.send(request, policy.max_response_size);
+        match tokio::time::timeout(timeout, send).await {
+            Ok(result) => result,
+            Err(_) => Err(Error::Timeout),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+
+    use crate::worker::Name;
+    use super::v1::{self, EdgedbHttpClientV1};
+    use super::{client, host_matches, Error, Policies, Policy, State};
+
+    fn name() -> Arc<Name> {
+        Arc::new(Name {
+            database: "db".into(),
+            wasm_name: "mod".into(),
+        })
+    }
+
+    fn policy(hosts: &[&str]) -> Policy {
+        Policy {
+            allowed_hosts: hosts.iter().map(|h| h.to_string()).collect(),
+            timeout: Duration::from_millis(200),
+            max_response_size: 1024,
+        }
+    }
+
+    fn allows(hosts: &[&str], uri: &str) -> bool {
+        policy(hosts).allows(&uri.parse().unwrap())
+    }
+
+    #[test]
+    fn exact_host() {
+        assert!(host_matches("example.com", "example.com", 443));
+        assert!(!host_matches("example.com", "www.example.com", 443));
+        assert!(!host_matches("example.com", "example.org", 443));
+    }
+
+    #[test]
+    fn subdomains() {
+        assert!(host_matches("*.example.com", "www.example.com", 443));
+        assert!(host_matches("*.example.com", "a.b.example.com", 80));
+        assert!(!host_matches("*.example.com", "example.com", 443));
+        assert!(!host_matches("*.example.com", "badexample.com", 443));
+        assert!(!host_matches("*.example.com", ".example.com", 443));
+    }
+
+    #[test]
+    fn ports() {
+        assert!(host_matches("localhost:8080", "localhost", 8080));
+        assert!(!host_matches("localhost:8080", "localhost", 80));
+        assert!(host_matches("localhost", "localhost", 8080));
+        assert!(!host_matches("localhost:http", "localhost", 80));
+        assert!(host_matches("*.example.com:8080", "www.example.com", 8080));
+        assert!(!host_matches("*.example.com:8080", "www.example.com", 80));
+    }
+
+    #[test]
+    fn ipv6() {
+        assert!(host_matches("[::1]", "[::1]", 80));
+        assert!(host_matches("[::1]:8080", "[::1]", 8080));
+        assert!(!host_matches("[::1]:8080", "[::1]", 80));
+        assert!(!host_matches("[::1]", "[::2]", 80));
+    }
+
+    #[test]
+    fn any_host() {
+        assert!(host_matches("*", "example.com", 443));
+        assert!(host_matches("*", "[::1]", 80));
+        assert!(host_matches("*:8080", "example.com", 8080));
+        assert!(!host_matches("*:8080", "example.com", 80));
+    }
+
+    #[test]
+    fn policy_allows() {
+        assert!(allows(&["example.com"], "https://example.com/x"));
+        assert!(allows(&["example.com"], "https://EXAMPLE.com/x"));
+        assert!(allows(&["example.com:443"], "https://example.com/x"));
+        assert!(!allows(&["example.com:443"], "http://example.com/x"));
+        assert!(allows(&["example.com:80"], "http://example.com/x"));
+        assert!(allows(&["[::1]:8080"], "http://[::1]:8080/x"));
+        assert!(!allows(&["*"], "/x"));
+        assert!(!allows(&["*"], "ftp://example.com/x"));
+        assert!(!allows(&[], "https://example.com/x"));
+    }
+
+    #[test]
+    fn uppercase_pattern() {
+        let policies = Policies::default();
+        policies.set("db", "mod", policy(&["*.Example.COM"]));
+        let policy = policies.get(&name()).unwrap();
+        assert!(policy.allows(&"https://www.example.com/".parse().unwrap()));
+    }
+
+    async fn server() -> SocketAddr {
+        let service = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+                let body = match req.uri().path() {
+                    "/slow" => {
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                        Body::empty()
+                    }
+                    "/large" => Body::from(vec![b'x'; 2048]),
+                    "/chunked" => {
+                        // no content-length, so limit is checked on read
+                        let (mut tx, body) = Body::channel();
+                        tokio::spawn(async move {
+                            for _ in 0..4 {
+                                let chunk = vec![b'x'; 512].into();
+                                if tx.send_data(chunk).await.is_err() {
+                                    break;
+                                }
+                            }
+                        });
+                        body
+                    }
+                    _ => Body::from("ok"),
+                };
+                Ok::<_, Infallible>(Response::new(body))
+            }))
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    async fn fetch(allowed: &str, uri: &str, timeout_ms: Option<u32>)
+        -> Result<v1::Response, Error>
+    {
+        let policies = Policies::default();
+        policies.set("db", "mod", policy(&[allowed]));
+        let mut state = State::new(&client(), &policies, &name());
+        state.fetch(v1::Request {
+            method: "GET",
+            uri,
+            headers: Vec::new(),
+            body: &[],
+            timeout_ms,
+        }).await
+    }
+
+    #[tokio::test]
+    async fn fetch_ok() {
+        let addr = server().await.to_string();
+        let uri = format!("http://{}/", addr);
+        let response = fetch(&addr, &uri, None).await.unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.body, b"ok");
+    }
+
+    #[tokio::test]
+    async fn not_allowed() {
+        let addr = server().await;
+        let uri = format!("http://{}/", addr);
+        let other = format!("localhost:{}", addr.port());
+        assert!(matches!(fetch(&other, &uri, None).await,
+                         Err(Error::NotAllowed(_))));
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        let addr = server().await.to_string();
+        let uri = format!("http://{}/slow", addr);
+        assert!(matches!(fetch(&addr, &uri, None).await,
+                         Err(Error::Timeout)));
+        assert!(matches!(fetch(&addr, &uri, Some(10)).await,
+                         Err(Error::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn too_large() {
+        let addr = server().await.to_string();
+        let uri = format!("http://{}/large", addr);
+        assert!(matches!(fetch(&addr, &uri, None).await,
+                         Err(Error::TooLarge)));
+        let uri = format!("http://{}/chunked", addr);
+        assert!(matches!(fetch(&addr, &uri, None).await,
+                         Err(Error::TooLarge)));
+    }
+}
@@ -0,0 +1,8 @@
+wit_bindgen_wasmtime::import!({
+    paths: ["../wit/edgedb_http_server_v2.wit"],
+    async: *,
+});
+
+pub use edgedb_http_server_v2::EdgedbHttpServerV2 as Handler;
+pub use edgedb_http_server_v2::EdgedbHttpServerV2Data as State;
+pub use edgedb_http_server_v2::{Request, Response, Body};
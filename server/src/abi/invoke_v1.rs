@@ -0,0 +1,417 @@
+wit_bindgen_wasmtime::export!({
+    paths: ["../wit/edgedb_invoke_v1.wit"],
+    async: *,
+});
+
+use std::io;
+use std::marker::PhantomData;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use bytes::Bytes;
+use hyper::Uri;
+use tokio::sync::mpsc;
+
+use crate::abi::http_body_v1::{self, Source, Sink, StreamError};
+use crate::abi::http_server_v1 as server_v1;
+use crate::abi::http_server_v2 as server_v2;
+use crate::tenant::{self, Tenant, TenantInner};
+use crate::tenant::http;
+use crate::worker::{self, Name};
+
+pub use edgedb_invoke_v1 as v1;
+pub use edgedb_invoke_v1::add_to_linker;
+pub use edgedb_invoke_v1::Error;
+
+/// Maximum number of modules waiting for a sub-request
+pub const MAX_DEPTH: usize = 8;
+/// Maximum size of the streamed response body, it's read into memory
+const MAX_RESPONSE_SIZE: usize = 10 << 20;
+/// Time to wait for the end of the streamed response body
+///
+/// Some streams (e.g. server-sent events) never end, while the caller keeps
+/// its store locked.
+const STREAM_TIMEOUT: Duration = Duration::from_secs(60);
+/// Header request id is read from and passed to sub-requests in
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+pub struct State {
+    tenant: Weak<TenantInner>,
+    name: Arc<Name>,
+    /// Modules waiting for the current request, outermost first
+    chain: Vec<String>,
+    request_id: Option<Bytes>,
+    /// Init hooks or `main` are running
+    ///
+    /// Tenant holds the lock of all workers while creating one, so
+    /// resolving the target module would deadlock.
+    instantiating: bool,
+}
+
+pub struct Process<'a>(PhantomData<&'a ()>);
+
+/// Sub-request with all the data owned
+#[derive(Debug)]
+pub struct Invocation {
+    method: String,
+    url: String,
+    uri: Uri,
+    headers: Vec<(Bytes, Bytes)>,
+    body: Bytes,
+    chain: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct ConvertRequest<'a> {
+    request: &'a Invocation,
+    headers: Vec<(&'a [u8], &'a [u8])>,
+}
+
+pub enum Body {
+    Full(Vec<u8>),
+    Stream(mpsc::Receiver<Bytes>),
+}
+
+pub struct Response {
+    status_code: u16,
+    headers: Vec<(Vec<u8>, Vec<u8>)>,
+    body: Body,
+}
+
+struct BodySource {
+    buf: Bytes,
+}
+
+impl State {
+    pub fn new(tenant: Weak<TenantInner>, name: &Arc<Name>) -> State {
+        State {
+            tenant,
+            name: name.clone(),
+            chain: Vec::new(),
+            request_id: None,
+            instantiating: true,
+        }
+    }
+    /// Allows invocations, called when instance is initialized
+    pub fn instantiated(&mut self) {
+        self.instantiating = false;
+    }
+    /// Sets up sub-requests made while handling the request
+    pub fn start_request(&mut self, chain: &[String],
+                         headers: &[(&[u8], &[u8])])
+    {
+        self.chain = chain.to_vec();
+        self.request_id = headers.iter()
+            .find(|(n, _)| is_request_id(n))
+            .map(|(_, v)| Bytes::copy_from_slice(v));
+    }
+    /// Resets the state, so that other calls into the guest (e.g. scheduled
+    /// tasks) start a new chain
+    pub fn finish_request(&mut self) {
+        self.chain.clear();
+        self.request_id = None;
+    }
+    fn check_target(&self, module_name: &str) -> Result<(), Error> {
+        if self.instantiating {
+            return Err(Error::Failed(
+                "modules can't be invoked during initialization".into()));
+        }
+        if module_name == self.name.wasm_name ||
+            self.chain.iter().any(|m| m == module_name)
+        {
+            return Err(Error::LoopDetected);
+        }
+        if self.chain.len() >= MAX_DEPTH {
+            return Err(Error::TooDeep);
+        }
+        if !tenant::is_valid_name(module_name) {
+            return Err(Error::NotFound(format!(
+                "invalid module name {:?}", module_name)));
+        }
+        Ok(())
+    }
+    fn invocation(&self, module_name: &str, req: v1::Request<'_>)
+        -> Result<Invocation, Error>
+    {
+        self.check_target(module_name)?;
+        if !req.path.starts_with('/') {
+            return Err(Error::InvalidRequest(format!(
+                "path {:?} must start with a slash", req.path)));
+        }
+        let method = hyper::Method::from_bytes(req.method.as_bytes())
+            .map_err(|e| Error::InvalidRequest(format!("{:#}", e)))?;
+        let url = format!("/db/{}/wasm/{}{}",
+                          self.name.database, module_name, req.path);
+        let uri = url.parse()
+            .map_err(|e| Error::InvalidRequest(format!("{:#}", e)))?;
+        let mut headers = req.headers.iter()
+            .map(|(n, v)| {
+                (Bytes::copy_from_slice(n), Bytes::copy_from_slice(v))
+            })
+            .collect::<Vec<_>>();
+        let has_request_id = headers.iter().any(|(n, _)| is_request_id(n));
+        if let (Some(id), false) = (&self.request_id, has_request_id) {
+            headers.push((REQUEST_ID_HEADER.into(), id.clone()));
+        }
+        let mut chain = self.chain.clone();
+        chain.push(self.name.wasm_name.clone());
+        Ok(Invocation {
+            method: method.as_str().into(),
+            url,
+            uri,
+            headers,
+            body: Bytes::copy_from_slice(req.body),
+            chain,
+        })
+    }
+}
+
+fn is_request_id(header_name: &[u8]) -> bool {
+    header_name.eq_ignore_ascii_case(REQUEST_ID_HEADER.as_bytes())
+}
+
+fn is_not_found(e: &anyhow::Error) -> bool {
+    e.chain().any(|e| {
+        matches!(e.downcast_ref::<io::Error>(),
+                 Some(e) if e.kind() == io::ErrorKind::NotFound)
+    })
+}
+
+async fn read_stream(mut stream: mpsc::Receiver<Bytes>)
+    -> Result<Vec<u8>, Error>
+{
+    let mut buf = Vec::new();
+    let read = async {
+        while let Some(chunk) = stream.recv().await {
+            if buf.len() + chunk.len() > MAX_RESPONSE_SIZE {
+                return Err(Error::Failed(format!(
+                    "response body exceeds {} bytes", MAX_RESPONSE_SIZE)));
+            }
+            buf.extend_from_slice(&chunk);
+        }
+        Ok(())
+    };
+    tokio::time::timeout(STREAM_TIMEOUT, read).await
+        .unwrap_or_else(|_| Err(Error::Failed(format!(
+            "streamed response body hasn't finished in {:?}",
+            STREAM_TIMEOUT))))?;
+    Ok(buf)
+}
+
+#[wit_bindgen_wasmtime::async_trait]
+impl v1::EdgedbInvokeV1 for State {
+    async fn invoke(&mut self, module_name: &str, request: v1::Request<'_>)
+        -> Result<v1::Response, Error>
+    {
+        let invocation = self.invocation(module_name, request)?;
+        let module_name = module_name.to_string();
+        let tenant = Tenant::upgrade(&self.tenant)
+            .ok_or_else(|| Error::Failed("server is shutting down".into()))?;
+        log::debug!("Module {} invokes {:?}", self.name, invocation.url);
+        let worker = match
+            tenant.get_worker(&self.name.database, &module_name).await
+        {
+            Ok(worker) => worker,
+            Err(e) if is_not_found(&e) => {
+                return Err(Error::NotFound(format!(
+                    "no module {:?}", module_name)));
+            }
+            Err(e) => {
+                log::error!("Module {} cannot invoke {:?}: {:#}",
+                            self.name, module_name, e);
+                return Err(Error::Failed(format!("{:#}", e)));
+            }
+        };
+        let request = ConvertRequest::new(&invocation);
+        let response = worker.handle_http::<Process>(request).await
+            .map_err(|e| Error::Failed(format!("{:#}", e)))??;
+        let body = match response.body {
+            Body::Full(body) => body,
+            Body::Stream(stream) => read_stream(stream).await?,
+        };
+        Ok(v1::Response {
+            status_code: response.status_code,
+            headers: response.headers,
+            body,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a> http::Process for Process<'a> {
+    type Input = &'a Invocation;
+    type ConvertInput = ConvertRequest<'a>;
+    type Output = Result<Response, Error>;
+
+    async fn read_head(request: &'a Invocation)
+        -> anyhow::Result<ConvertRequest<'a>>
+    {
+        Ok(ConvertRequest::new(request))
+    }
+    fn err_not_found() -> Self::Output {
+        Err(Error::NotFound("module has no HTTP handler".into()))
+    }
+    fn err_internal_server_error() -> Self::Output {
+        Err(Error::Failed("module failed to handle request".into()))
+    }
+    fn err_service_unavailable() -> Self::Output {
+        Err(Error::Failed("module failed to initialize".into()))
+    }
+}
+
+impl<'a> ConvertRequest<'a> {
+    fn new(request: &'a Invocation) -> ConvertRequest<'a> {
+        ConvertRequest {
+            request,
+            headers: request.headers.iter().map(|(n, v)| {
+                (n.as_ref(), v.as_ref())
+            }).collect(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl http::ConvertInput for ConvertRequest<'_> {
+    fn uri(&self) -> &Uri {
+        &self.request.uri
+    }
+    async fn read_full_body(&mut self) -> anyhow::Result<()> {
+        // body is always passed in full
+        Ok(())
+    }
+    fn take_body(&mut self) -> Box<dyn Source> {
+        Box::new(BodySource {
+            buf: self.request.body.clone(),
+        })
+    }
+    fn invoke_chain(&self) -> &[String] {
+        &self.request.chain
+    }
+    fn as_v1(&self) -> server_v1::Request<'_> {
+        server_v1::Request {
+            method: &self.request.method,
+            uri: &self.request.url,
+            headers: &self.headers,
+            body: &self.request.body,
+        }
+    }
+    fn as_v2<'a>(&'a self, name: &'a worker::Name, mount_prefix: &'a str)
+        -> server_v2::Request<'a>
+    {
+        server_v2::Request {
+            method: &self.request.method,
+            uri: &self.request.url,
+            headers: &self.headers,
+            database: &name.database,
+            module_name: &name.wasm_name,
+            mount_prefix,
+            scheme: None,
+            host: None,
+            remote_addr: None,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for BodySource {
+    async fn read(&mut self, max_size: usize) -> Result<Bytes, StreamError> {
+        let size = self.buf.len().min(max_size);
+        Ok(self.buf.split_to(size))
+    }
+}
+
+impl http::FromWasm for Result<Response, Error> {
+    fn from_wasm(wasm: server_v1::Response) -> anyhow::Result<Self> {
+        Ok(Ok(Response {
+            status_code: wasm.status_code,
+            headers: wasm.headers,
+            body: Body::Full(wasm.body),
+        }))
+    }
+    fn from_wasm_v2(wasm: server_v2::Response)
+        -> anyhow::Result<(Self, Option<Box<dyn Sink>>)>
+    {
+        let (body, sink) = match wasm.body {
+            server_v2::Body::Full(body) => (Body::Full(body), None),
+            server_v2::Body::Stream => {
+                let (sink, receiver) = http_body_v1::channel();
+                (Body::Stream(receiver), Some(sink))
+            }
+        };
+        let response = Response {
+            status_code: wasm.status_code,
+            headers: wasm.headers,
+            body,
+        };
+        Ok((Ok(response), sink))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Weak};
+
+    use super::{State, Error, MAX_DEPTH};
+    use crate::worker::Name;
+
+    fn state(wasm_name: &str, chain: &[&str]) -> State {
+        let name = Arc::new(Name {
+            database: "edgedb".into(),
+            wasm_name: wasm_name.into(),
+        });
+        let mut state = State::new(Weak::new(), &name);
+        state.instantiated();
+        let chain = chain.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+        state.start_request(&chain, &[]);
+        state
+    }
+
+    #[test]
+    fn self_invoke() {
+        assert!(matches!(state("a", &[]).check_target("a"),
+                         Err(Error::LoopDetected)));
+    }
+
+    #[test]
+    fn loop_through_other_module() {
+        // a -> b -> a
+        assert!(matches!(state("b", &["a"]).check_target("a"),
+                         Err(Error::LoopDetected)));
+        assert!(state("b", &["a"]).check_target("c").is_ok());
+    }
+
+    #[test]
+    fn depth() {
+        let names = (0..MAX_DEPTH).map(|i| format!("m{}", i))
+            .collect::<Vec<_>>();
+        let chain = names.iter().map(|n| &n[..]).collect::<Vec<_>>();
+        assert!(state("x", &chain[..MAX_DEPTH-1]).check_target("y").is_ok());
+        assert!(matches!(state("x", &chain).check_target("y"),
+                         Err(Error::TooDeep)));
+    }
+
+    #[test]
+    fn invalid_name() {
+        assert!(matches!(state("a", &[]).check_target("../b"),
+                         Err(Error::NotFound(_))));
+    }
+
+    #[test]
+    fn init_hook() {
+        let name = Arc::new(Name {
+            database: "edgedb".into(),
+            wasm_name: "a".into(),
+        });
+        let mut state = State::new(Weak::new(), &name);
+        assert!(matches!(state.check_target("b"), Err(Error::Failed(_))));
+        state.instantiated();
+        assert!(state.check_target("b").is_ok());
+    }
+
+    #[test]
+    fn chain_reset() {
+        let mut state = state("b", &["a"]);
+        state.finish_request();
+        assert!(state.check_target("a").is_ok());
+    }
+}
@@ -0,0 +1,7 @@
+wit_bindgen_wasmtime::import!({
+    paths: ["../wit/edgedb_job_runner_v1.wit"],
+    async: *,
+});
+
+pub use edgedb_job_runner_v1::EdgedbJobRunnerV1 as Handler;
+pub use edgedb_job_runner_v1::EdgedbJobRunnerV1Data as State;
@@ -0,0 +1,13 @@
+use crate::worker;
+
+wit_bindgen_wasmtime::export!("../wit/edgedb_jobs_v1.wit");
+
+use edgedb_jobs_v1 as v1;
+
+pub use edgedb_jobs_v1::add_to_linker;
+
+impl v1::EdgedbJobsV1 for worker::State {
+    fn module_name(&mut self) -> String {
+        self.name.wasm_name.clone()
+    }
+}
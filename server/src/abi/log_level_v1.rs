@@ -0,0 +1,23 @@
+wit_bindgen_wasmtime::import!({
+    paths: ["../wit/edgedb_log_level_v1.wit"],
+    async: *,
+});
+
+use edgedb_log_level_v1 as v1;
+
+pub use edgedb_log_level_v1::EdgedbLogLevelV1 as Handler;
+pub use edgedb_log_level_v1::EdgedbLogLevelV1Data as State;
+
+pub fn convert_level(value: log::LevelFilter) -> Option<v1::Level> {
+    use log::LevelFilter as S;
+    use v1::Level as T;
+
+    match value {
+        S::Off => None,
+        S::Error => Some(T::Error),
+        S::Warn => Some(T::Warn),
+        S::Debug => Some(T::Debug),
+        S::Info => Some(T::Info),
+        S::Trace => Some(T::Trace),
+    }
+}
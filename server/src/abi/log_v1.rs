@@ -1,3 +1,4 @@
+use crate::logging;
 use crate::worker;
 
 wit_bindgen_wasmtime::export!("../wit/edgedb_log_v1.wit");
@@ -38,6 +39,18 @@ fn convert_level(value: log::LevelFilter) -> Option<v1::Level> {
 
 impl v1::EdgedbLogV1 for worker::State {
     fn log(&mut self, value: v1::LogRecord) {
+        let level: log::Level = value.level.into();
+        let captured = self.log_capture.push(|| {
+            format!("[{} {}] {}", level, value.target, value.message)
+        });
+        if captured {
+            return;
+        }
+        let overridden = match self.log_v2.level_override(&self.name) {
+            Some(max) if level > max => return,
+            Some(_) => true,
+            None => false,
+        };
         let target = format!(
             "wasm::{}::{}::{}",
             self.name.database,
@@ -46,17 +59,17 @@ impl v1::EdgedbLogV1 for worker::State {
         );
         let meta = log::MetadataBuilder::new()
             .target(&target)
-            .level(value.level.into())
+            .level(level)
             .build();
-        log::logger().log(&log::Record::builder()
+        logging::emit(&log::Record::builder()
             .metadata(meta)
             .args(format_args!("{}", value.message))
             .line(value.line)
             .file(value.file)
             .module_path(value.module_path)
-            .build());
+            .build(), overridden);
     }
     fn max_level(&mut self) -> Option<v1::Level> {
-        convert_level(log::max_level())
+        convert_level(self.log_v2.max_level(&self.name))
     }
 }
@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+
+use crate::logging;
+use crate::registry::Registry;
+use crate::worker::{self, Name};
+
+wit_bindgen_wasmtime::export!("../wit/edgedb_log_v2.wit");
+
+use edgedb_log_v2 as v2;
+
+pub use edgedb_log_v2::add_to_linker;
+
+/// Maximum number of spans open at the same time in an instance
+///
+/// New spans are ignored above the limit (e.g. if guest leaks spans).
+const MAX_SPANS: usize = 10000;
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(untagged)]
+enum Value {
+    Str(String),
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    Bool(bool),
+}
+
+type Fields = Vec<(String, Value)>;
+
+#[derive(Debug)]
+struct Span {
+    name: String,
+    fields: Fields,
+}
+
+/// Log levels of the modules overriding the global one
+///
+/// This is stored in the tenant, so levels are kept when modules reload.
+#[derive(Debug, Clone, Default)]
+pub struct LogLevels(Registry<log::LevelFilter>);
+
+/// Spans of the instance
+#[derive(Debug)]
+pub struct State {
+    levels: LogLevels,
+    spans: HashMap<u64, Span>,
+    /// Ids of entered spans, innermost last
+    stack: Vec<u64>,
+}
+
+impl Into<log::Level> for v2::Level {
+    fn into(self) -> log::Level {
+        use log::Level as T;
+        use v2::Level as S;
+
+        match self {
+            S::Error => T::Error,
+            S::Warn => T::Warn,
+            S::Debug => T::Debug,
+            S::Info => T::Info,
+            S::Trace => T::Trace,
+        }
+    }
+}
+
+fn convert_level(value: log::LevelFilter) -> Option<v2::Level> {
+    use log::LevelFilter as S;
+    use v2::Level as T;
+
+    match value {
+        S::Off => None,
+        S::Error => Some(T::Error),
+        S::Warn => Some(T::Warn),
+        S::Debug => Some(T::Debug),
+        S::Info => Some(T::Info),
+        S::Trace => Some(T::Trace),
+    }
+}
+
+fn convert_fields(fields: Vec<v2::Field<'_>>) -> Fields {
+    fields.into_iter().map(|f| {
+        let value = match f.value {
+            v2::Value::Str(s) => Value::Str(s.into()),
+            v2::Value::I64(v) => Value::I64(v),
+            v2::Value::U64(v) => Value::U64(v),
+            v2::Value::F64(v) => Value::F64(v),
+            v2::Value::Bool(v) => Value::Bool(v),
+        };
+        (f.name.into(), value)
+    }).collect()
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Str(s) => write!(f, "{:?}", s),
+            Value::I64(v) => v.fmt(f),
+            Value::U64(v) => v.fmt(f),
+            Value::F64(v) => v.fmt(f),
+            Value::Bool(v) => v.fmt(f),
+        }
+    }
+}
+
+fn write_fields(buf: &mut String, fields: &Fields) {
+    for (name, value) in fields {
+        write!(buf, " {}={}", name, value).unwrap();
+    }
+}
+
+impl LogLevels {
+    /// Sets the level of the module, `None` reverts to the global one
+    pub fn set(&self, database: &str, wasm_name: &str,
+               level: Option<log::LevelFilter>)
+    {
+        match level {
+            Some(level) => self.0.insert(database, wasm_name, level),
+            None => self.0.remove(database, wasm_name),
+        };
+    }
+    pub fn get(&self, name: &Name) -> Option<log::LevelFilter> {
+        self.0.get(name).map(|level| *level)
+    }
+}
+
+impl State {
+    pub fn new(levels: &LogLevels) -> State {
+        State {
+            levels: levels.clone(),
+            spans: HashMap::new(),
+            stack: Vec::new(),
+        }
+    }
+    pub fn levels(&self) -> &LogLevels {
+        &self.levels
+    }
+    /// Level overriding the global one for this module
+    pub fn level_override(&self, name: &Name) -> Option<log::LevelFilter> {
+        self.levels.get(name)
+    }
+    /// Effective level of the module
+    pub fn max_level(&self, name: &Name) -> log::LevelFilter {
+        self.level_override(name).unwrap_or_else(log::max_level)
+    }
+    fn entered(&self) -> impl Iterator<Item=&Span> {
+        self.stack.iter().filter_map(|id| self.spans.get(id))
+    }
+    /// Renders message with spans and fields for the text log
+    ///
+    /// Format is similar to the one of `tracing-subscriber`:
+    /// `outer{a=1}:inner: message b=2`
+    fn format_text(&self, message: &str, fields: &Fields) -> String {
+        let mut buf = String::new();
+        for span in self.entered() {
+            buf.push_str(&span.name);
+            if !span.fields.is_empty() {
+                buf.push('{');
+                let mut inner = String::new();
+                write_fields(&mut inner, &span.fields);
+                buf.push_str(&inner[1..]);
+                buf.push('}');
+            }
+            buf.push(':');
+        }
+        if !buf.is_empty() {
+            buf.push(' ');
+        }
+        buf.push_str(message);
+        write_fields(&mut buf, fields);
+        buf
+    }
+    fn spans_json(&self) -> serde_json::Value {
+        self.entered().map(|span| {
+            let mut obj = serde_json::Map::new();
+            obj.insert("name".into(), span.name.clone().into());
+            for (name, value) in &span.fields {
+                let value = serde_json::to_value(value)
+                    .unwrap_or(serde_json::Value::Null);
+                obj.insert(name.clone(), value);
+            }
+            serde_json::Value::Object(obj)
+        }).collect()
+    }
+}
+
+impl v2::EdgedbLogV2 for worker::State {
+    fn log(&mut self, value: v2::LogRecord<'_>) {
+        let fields = convert_fields(value.fields);
+        let level: log::Level = value.level.into();
+        let captured = self.log_capture.push(|| {
+            let message = self.log_v2.format_text(value.message, &fields);
+            format!("[{} {}] {}", level, value.target, message)
+        });
+        if captured {
+            return;
+        }
+        let overridden = match self.log_v2.level_override(&self.name) {
+            Some(max) if level > max => return,
+            Some(_) => true,
+            None => false,
+        };
+        let target = format!(
+            "wasm::{}::{}::{}",
+            self.name.database,
+            self.name.wasm_name,
+            value.target,
+        );
+        let meta = log::MetadataBuilder::new()
+            .target(&target)
+            .level(level)
+            .build();
+        if logging::is_json() {
+            let spans;
+            let mut kvs = fields.iter()
+                .map(|(k, v)| (&k[..], log::kv::Value::from_serde(v)))
+                .collect::<Vec<_>>();
+            if !self.log_v2.stack.is_empty() {
+                spans = self.log_v2.spans_json();
+                kvs.push((logging::SPANS_KEY,
+                          log::kv::Value::from_serde(&spans)));
+            }
+            logging::emit(&log::Record::builder()
+                .metadata(meta)
+                .args(format_args!("{}", value.message))
+                .key_values(&kvs)
+                .line(value.line)
+                .file(value.file)
+                .module_path(value.module_path)
+                .build(), overridden);
+        } else {
+            let message = self.log_v2.format_text(value.message, &fields);
+            logging::emit(&log::Record::builder()
+                .metadata(meta)
+                .args(format_args!("{}", message))
+                .line(value.line)
+                .file(value.file)
+                .module_path(value.module_path)
+                .build(), overridden);
+        }
+    }
+    fn max_level(&mut self) -> Option<v2::Level> {
+        convert_level(self.log_v2.max_level(&self.name))
+    }
+    fn span_new(&mut self, span: v2::Span<'_>) {
+        let state = &mut self.log_v2;
+        if state.spans.len() >= MAX_SPANS {
+            log::debug!("Worker {} has too many open spans", self.name);
+            return;
+        }
+        state.spans.insert(span.id, Span {
+            name: span.name.into(),
+            fields: convert_fields(span.fields),
+        });
+    }
+    fn span_record(&mut self, id: u64, fields: Vec<v2::Field<'_>>) {
+        if let Some(span) = self.log_v2.spans.get_mut(&id) {
+            for (name, value) in convert_fields(fields) {
+                match span.fields.iter_mut().find(|(n, _)| *n == name) {
+                    Some((_, old)) => *old = value,
+                    None => span.fields.push((name, value)),
+                }
+            }
+        }
+    }
+    fn span_enter(&mut self, id: u64) {
+        let state = &mut self.log_v2;
+        if state.spans.contains_key(&id) {
+            state.stack.push(id);
+        }
+    }
+    fn span_exit(&mut self, id: u64) {
+        let state = &mut self.log_v2;
+        if let Some(pos) = state.stack.iter().rposition(|x| *x == id) {
+            state.stack.remove(pos);
+        }
+    }
+    fn span_close(&mut self, id: u64) {
+        let state = &mut self.log_v2;
+        state.spans.remove(&id);
+        state.stack.retain(|x| *x != id);
+    }
+}
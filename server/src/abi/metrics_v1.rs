@@ -0,0 +1,370 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::worker::{self, Name};
+
+wit_bindgen_wasmtime::export!("../wit/edgedb_metrics_v1.wit");
+
+use edgedb_metrics_v1 as v1;
+
+pub use edgedb_metrics_v1::add_to_linker;
+
+/// Maximum number of distinct label sets of a single metric of a module
+///
+/// Protects server memory from metrics labelled by unbounded values (e.g.
+/// user ids).
+const MAX_SERIES: usize = 1000;
+/// Minimum interval between warnings about invalid metrics of a module
+const WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Upper bounds of the histogram buckets (same as Prometheus defaults)
+const BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+type Labels = Vec<(String, String)>;
+/// Metric name, database and module name
+///
+/// Name goes first, so families of the same name are rendered together.
+type Key = (String, String, String);
+
+/// Metrics of all modules of the tenant
+#[derive(Debug, Clone, Default)]
+pub struct Metrics(Arc<Mutex<BTreeMap<Key, Family>>>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+#[derive(Debug)]
+struct Family {
+    kind: Kind,
+    series: BTreeMap<Labels, Value>,
+    /// Whether warning about exceeding `MAX_SERIES` has been logged
+    overflow_logged: bool,
+}
+
+#[derive(Debug)]
+enum Value {
+    Number(f64),
+    Histogram {
+        buckets: Vec<u64>,
+        sum: f64,
+        count: u64,
+    },
+}
+
+pub struct State {
+    metrics: Metrics,
+    name: Arc<Name>,
+    last_warning: Option<Instant>,
+    /// Number of warnings not logged since the last one
+    suppressed: u64,
+}
+
+fn is_valid_name(name: &str, colon: bool) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || colon && c == ':'
+        => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || colon && c == ':')
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kind::Counter => f.write_str("counter"),
+            Kind::Gauge => f.write_str("gauge"),
+            Kind::Histogram => f.write_str("histogram"),
+        }
+    }
+}
+
+impl Value {
+    fn new(kind: Kind) -> Value {
+        match kind {
+            Kind::Counter | Kind::Gauge => Value::Number(0.0),
+            Kind::Histogram => Value::Histogram {
+                buckets: vec![0; BUCKETS.len()],
+                sum: 0.0,
+                count: 0,
+            },
+        }
+    }
+    fn update(&mut self, kind: Kind, value: f64) {
+        match (self, kind) {
+            (Value::Number(cur), Kind::Counter) => *cur += value,
+            (Value::Number(cur), Kind::Gauge) => *cur = value,
+            (Value::Histogram { buckets, sum, count }, Kind::Histogram) => {
+                for (bucket, bound) in buckets.iter_mut().zip(BUCKETS) {
+                    if value <= *bound {
+                        *bucket += 1;
+                    }
+                }
+                *sum += value;
+                *count += 1;
+            }
+            _ => unreachable!("kind of the family never changes"),
+        }
+    }
+}
+
+fn write_labels(buf: &mut String, labels: &[(String, String)],
+                extra: Option<(&str, &str)>)
+{
+    buf.push('{');
+    let all = labels.iter().map(|(k, v)| (&k[..], &v[..])).chain(extra);
+    for (i, (name, value)) in all.enumerate() {
+        if i > 0 {
+            buf.push(',');
+        }
+        buf.push_str(name);
+        buf.push_str("=\"");
+        for c in value.chars() {
+            match c {
+                '\\' => buf.push_str("\\\\"),
+                '"' => buf.push_str("\\\""),
+                '\n' => buf.push_str("\\n"),
+                c => buf.push(c),
+            }
+        }
+        buf.push('"');
+    }
+    buf.push('}');
+}
+
+impl Metrics {
+    /// Returns warning message if metric is invalid
+    fn update(&self, module: &Name, kind: Kind, name: &str,
+              labels: &[(&str, &str)], value: f64)
+        -> Result<(), String>
+    {
+        if !is_valid_name(name, true) {
+            return Err(format!("invalid metric name {:?}", name));
+        }
+        if let Some((label, _)) = labels.iter().find(|(k, _)| {
+            !is_valid_name(k, false) || k.starts_with("__") ||
+            *k == "database" || *k == "module" ||
+            kind == Kind::Histogram && *k == "le"
+        }) {
+            return Err(format!("metric {:?} with invalid label {:?}",
+                               name, label));
+        }
+        if !value.is_finite() || kind == Kind::Counter && value < 0.0 {
+            return Err(format!("invalid value {} of metric {:?}",
+                               value, name));
+        }
+        let mut series_key = vec![
+            ("database".to_string(), module.database.clone()),
+            ("module".to_string(), module.wasm_name.clone()),
+        ];
+        series_key.extend(labels.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string())));
+        series_key[2..].sort();
+
+        let mut families = self.0.lock().expect("metrics are not poisoned");
+        let key = (name.to_string(), module.database.clone(),
+                   module.wasm_name.clone());
+        if !families.contains_key(&key) {
+            // all series of the name are exported as a single family
+            let start = (name.to_string(), String::new(), String::new());
+            let other = families.range(start..)
+                .take_while(|((n, _, _), _)| n == name)
+                .find(|(_, family)| family.kind != kind);
+            if let Some(((_, database, wasm_name), family)) = other {
+                return Err(format!(
+                    "{} {:?} is ignored, because it's a {} in /db/{}/wasm/{}",
+                    kind, name, family.kind, database, wasm_name));
+            }
+        }
+        let family = families.entry(key).or_insert_with(|| Family {
+            kind,
+            series: BTreeMap::new(),
+            overflow_logged: false,
+        });
+        if family.kind != kind {
+            return Err(format!("{} {:?} is already a {}",
+                               kind, name, family.kind));
+        }
+        if !family.series.contains_key(&series_key) &&
+            family.series.len() >= MAX_SERIES
+        {
+            if !family.overflow_logged {
+                family.overflow_logged = true;
+                return Err(format!("metric {:?} has more than {} label \
+                                    sets, new ones are dropped",
+                                   name, MAX_SERIES));
+            }
+            return Ok(());
+        }
+        family.series.entry(series_key).or_insert_with(|| Value::new(kind))
+            .update(kind, value);
+        Ok(())
+    }
+    /// Renders all metrics in Prometheus text format
+    pub fn render(&self) -> String {
+        let families = self.0.lock().expect("metrics are not poisoned");
+        let mut buf = String::new();
+        let mut prev_name = None;
+        for ((name, _, _), family) in families.iter() {
+            if prev_name != Some(name) {
+                writeln!(buf, "# TYPE {} {}", name, family.kind).unwrap();
+                prev_name = Some(name);
+            }
+            for (labels, value) in &family.series {
+                match value {
+                    Value::Number(value) => {
+                        buf.push_str(name);
+                        write_labels(&mut buf, labels, None);
+                        writeln!(buf, " {}", value).unwrap();
+                    }
+                    Value::Histogram { buckets, sum, count } => {
+                        for (bound, bucket) in BUCKETS.iter().zip(buckets) {
+                            write!(buf, "{}_bucket", name).unwrap();
+                            let bound = bound.to_string();
+                            write_labels(&mut buf, labels,
+                                         Some(("le", &bound)));
+                            writeln!(buf, " {}", bucket).unwrap();
+                        }
+                        write!(buf, "{}_bucket", name).unwrap();
+                        write_labels(&mut buf, labels, Some(("le", "+Inf")));
+                        writeln!(buf, " {}", count).unwrap();
+                        write!(buf, "{}_sum", name).unwrap();
+                        write_labels(&mut buf, labels, None);
+                        writeln!(buf, " {}", sum).unwrap();
+                        write!(buf, "{}_count", name).unwrap();
+                        write_labels(&mut buf, labels, None);
+                        writeln!(buf, " {}", count).unwrap();
+                    }
+                }
+            }
+        }
+        buf
+    }
+}
+
+impl State {
+    pub fn new(metrics: &Metrics, name: &Arc<Name>) -> State {
+        State {
+            metrics: metrics.clone(),
+            name: name.clone(),
+            last_warning: None,
+            suppressed: 0,
+        }
+    }
+    fn update(&mut self, kind: Kind, name: &str, labels: &[(&str, &str)],
+              value: f64)
+    {
+        let result = self.metrics.update(&self.name, kind, name, labels,
+                                         value);
+        if let Err(message) = result {
+            self.warn(message);
+        }
+    }
+    /// Logs warning, unless one was logged recently
+    fn warn(&mut self, message: String) {
+        let now = Instant::now();
+        if matches!(self.last_warning,
+                    Some(last) if now.duration_since(last) < WARNING_INTERVAL)
+        {
+            self.suppressed += 1;
+            return;
+        }
+        if self.suppressed > 0 {
+            log::warn!("Worker {} emitted invalid metric: {} \
+                        ({} similar warnings suppressed)",
+                       self.name, message, self.suppressed);
+        } else {
+            log::warn!("Worker {} emitted invalid metric: {}",
+                       self.name, message);
+        }
+        self.last_warning = Some(now);
+        self.suppressed = 0;
+    }
+}
+
+impl v1::EdgedbMetricsV1 for worker::State {
+    fn counter_increment(&mut self, name: &str, labels: Vec<(&str, &str)>,
+                         value: f64)
+    {
+        self.metrics_v1.update(Kind::Counter, name, &labels, value);
+    }
+    fn gauge_set(&mut self, name: &str, labels: Vec<(&str, &str)>,
+                 value: f64)
+    {
+        self.metrics_v1.update(Kind::Gauge, name, &labels, value);
+    }
+    fn histogram_observe(&mut self, name: &str, labels: Vec<(&str, &str)>,
+                         value: f64)
+    {
+        self.metrics_v1.update(Kind::Histogram, name, &labels, value);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Metrics, Kind, Name, MAX_SERIES};
+
+    fn name(database: &str, wasm_name: &str) -> Name {
+        Name {
+            database: database.into(),
+            wasm_name: wasm_name.into(),
+        }
+    }
+
+    #[test]
+    fn same_name_in_two_modules() {
+        let metrics = Metrics::default();
+        let (a, b) = (name("db1", "a"), name("db2", "b"));
+        metrics.update(&a, Kind::Counter, "hits", &[], 1.0).unwrap();
+        metrics.update(&b, Kind::Counter, "hits", &[], 2.0).unwrap();
+        assert_eq!(metrics.render(), "\
+            # TYPE hits counter\n\
+            hits{database=\"db1\",module=\"a\"} 1\n\
+            hits{database=\"db2\",module=\"b\"} 2\n");
+    }
+
+    #[test]
+    fn kind_conflict() {
+        let metrics = Metrics::default();
+        let (a, b) = (name("db1", "a"), name("db2", "b"));
+        metrics.update(&a, Kind::Counter, "hits", &[], 1.0).unwrap();
+        assert!(metrics.update(&a, Kind::Gauge, "hits", &[], 1.0).is_err());
+        assert!(metrics.update(&b, Kind::Gauge, "hits", &[], 1.0).is_err());
+        metrics.update(&b, Kind::Counter, "hits", &[], 1.0).unwrap();
+    }
+
+    #[test]
+    fn series_limit_per_module() {
+        let metrics = Metrics::default();
+        let (a, b) = (name("db", "a"), name("db", "b"));
+        for i in 0..MAX_SERIES {
+            let id = i.to_string();
+            metrics.update(&a, Kind::Gauge, "x", &[("id", &id)], 1.0)
+                .unwrap();
+        }
+        assert!(metrics.update(&a, Kind::Gauge, "x", &[("id", "y")], 1.0)
+                .is_err());
+        // warned only once
+        metrics.update(&a, Kind::Gauge, "x", &[("id", "z")], 1.0).unwrap();
+        metrics.update(&b, Kind::Gauge, "x", &[("id", "y")], 1.0).unwrap();
+    }
+
+    #[test]
+    fn invalid() {
+        let metrics = Metrics::default();
+        let a = name("db", "a");
+        assert!(metrics.update(&a, Kind::Gauge, "1x", &[], 1.0).is_err());
+        assert!(metrics.update(&a, Kind::Gauge, "x", &[("module", "b")], 1.0)
+                .is_err());
+        assert!(metrics.update(&a, Kind::Counter, "x", &[], -1.0).is_err());
+        assert_eq!(metrics.render(), "");
+    }
+}
@@ -0,0 +1,8 @@
+wit_bindgen_wasmtime::import!({
+    paths: ["../wit/edgedb_scheduler_v1.wit"],
+    async: *,
+});
+
+pub use edgedb_scheduler_v1::EdgedbSchedulerV1 as Handler;
+pub use edgedb_scheduler_v1::EdgedbSchedulerV1Data as State;
+pub use edgedb_scheduler_v1::Schedule;
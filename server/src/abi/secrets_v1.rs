@@ -0,0 +1,65 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Arc;
+
+use crate::registry::Registry;
+use crate::worker::{self, Name};
+
+wit_bindgen_wasmtime::export!("../wit/edgedb_secrets_v1.wit");
+
+use edgedb_secrets_v1 as v1;
+
+pub use edgedb_secrets_v1::add_to_linker;
+
+/// Secret value, which is never printed
+#[derive(Clone)]
+pub struct Secret(String);
+
+pub type Values = BTreeMap<String, Secret>;
+
+/// Secrets of all modules by database and module name
+#[derive(Debug, Clone, Default)]
+pub struct Secrets(Registry<Values>);
+
+pub struct State {
+    secrets: Secrets,
+    name: Arc<Name>,
+}
+
+impl Secret {
+    pub fn new(value: String) -> Secret {
+        Secret(value)
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Secret(..)")
+    }
+}
+
+impl Secrets {
+    /// Replaces secrets of all modules
+    pub fn replace(&self, all: HashMap<Name, Values>) {
+        self.0.replace(all);
+    }
+    fn get(&self, name: &Name) -> Option<Arc<Values>> {
+        self.0.get(name)
+    }
+}
+
+impl State {
+    pub fn new(secrets: &Secrets, name: &Arc<Name>) -> State {
+        State {
+            secrets: secrets.clone(),
+            name: name.clone(),
+        }
+    }
+}
+
+impl v1::EdgedbSecretsV1 for worker::State {
+    fn get(&mut self, name: &str) -> Option<String> {
+        let secrets = &self.secrets_v1;
+        secrets.secrets.get(&secrets.name)?.get(name).map(|s| s.0.clone())
+    }
+}
@@ -0,0 +1,67 @@
+wit_bindgen_wasmtime::export!({
+    paths: ["../wit/edgedb_websocket_connection_v1.wit"],
+    async: *,
+});
+
+use std::collections::HashMap;
+
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message as Frame;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+
+pub use edgedb_websocket_connection_v1 as v1;
+pub use edgedb_websocket_connection_v1::add_to_linker;
+pub use edgedb_websocket_connection_v1::SendError;
+
+
+/// Outgoing frames of open connections of a single worker
+#[derive(Default)]
+pub struct State {
+    last_id: u64,
+    // Channel is unbounded because messages sent in `on-open` are only
+    // read after `on-open` returns
+    connections: HashMap<u64, mpsc::UnboundedSender<Frame>>,
+}
+
+impl State {
+    /// Registers new connection, returns its id and outgoing frames
+    pub fn open(&mut self) -> (u64, mpsc::UnboundedReceiver<Frame>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.last_id += 1;
+        self.connections.insert(self.last_id, sender);
+        (self.last_id, receiver)
+    }
+    /// Forgets the connection, this stops the writer of outgoing frames
+    pub fn close(&mut self, connection: u64) {
+        self.connections.remove(&connection);
+    }
+    fn send(&mut self, connection: u64, frame: Frame)
+        -> Result<(), SendError>
+    {
+        let sender = self.connections.get(&connection)
+            .ok_or(SendError::Closed)?;
+        sender.send(frame).map_err(|_| SendError::Closed)
+    }
+}
+
+#[wit_bindgen_wasmtime::async_trait]
+impl v1::EdgedbWebsocketConnectionV1 for State {
+    async fn send(&mut self, connection: u64, message: v1::Message<'_>)
+        -> Result<(), SendError>
+    {
+        let frame = match message {
+            v1::Message::Text(text) => Frame::Text(text.into()),
+            v1::Message::Binary(data) => Frame::Binary(data.into()),
+        };
+        State::send(self, connection, frame)
+    }
+    async fn close(&mut self, connection: u64, code: u16, reason: &str)
+        -> Result<(), SendError>
+    {
+        let frame = Frame::Close(Some(CloseFrame {
+            code: code.into(),
+            reason: reason.to_owned().into(),
+        }));
+        State::send(self, connection, frame)
+    }
+}
@@ -0,0 +1,8 @@
+wit_bindgen_wasmtime::import!({
+    paths: ["../wit/edgedb_websocket_v1.wit"],
+    async: *,
+});
+
+pub use edgedb_websocket_v1::EdgedbWebsocketV1 as Handler;
+pub use edgedb_websocket_v1::EdgedbWebsocketV1Data as State;
+pub use edgedb_websocket_v1::{Request, Message};
@@ -0,0 +1,50 @@
+//! Configuration values of modules read from a TOML file
+//!
+//! Each table is named by a database and a module, values must be scalars
+//! (they are passed to the module as strings):
+//!
+//! ```toml
+//! [edgedb.my_module]
+//! api_url = "https://example.com/api"
+//! page_size = 20
+//! ```
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::abi::config_v1::Values;
+use crate::tenant::Tenant;
+
+type Table = BTreeMap<String, toml::Value>;
+type Databases = BTreeMap<String, BTreeMap<String, Table>>;
+
+fn convert(module: &str, values: Table) -> anyhow::Result<Values> {
+    values.into_iter().map(|(key, value)| {
+        let value = match value {
+            toml::Value::String(s) => s,
+            toml::Value::Integer(_) | toml::Value::Float(_) |
+            toml::Value::Boolean(_) | toml::Value::Datetime(_)
+            => value.to_string(),
+            toml::Value::Array(_) | toml::Value::Table(_) => {
+                anyhow::bail!("value {:?} of the module {:?} must be a scalar",
+                              key, module);
+            }
+        };
+        Ok((key, value))
+    }).collect()
+}
+
+pub async fn load(tenant: &Tenant, path: &Path) -> anyhow::Result<()> {
+    let data = tokio::fs::read_to_string(path).await
+        .with_context(|| format!("cannot read config file {:?}", path))?;
+    let databases: Databases = toml::from_str(&data)
+        .with_context(|| format!("invalid config file {:?}", path))?;
+    for (database, modules) in databases {
+        for (module, values) in modules {
+            let values = convert(&module, values)?;
+            tenant.set_config(&database, &module, values).await;
+        }
+    }
+    Ok(())
+}
@@ -1,9 +1,19 @@
+use std::convert::Infallible;
 use std::marker::PhantomData;
+use std::mem;
+use std::net::SocketAddr;
 
 use hyper::Uri;
+use hyper::body::{Bytes, HttpBody};
+use tokio_stream::StreamExt;
+use tokio_stream::wrappers::ReceiverStream;
 
 use crate::tenant::http;
+use crate::abi::http_body_v1::{self, Source, Sink, StreamError};
 use crate::abi::http_server_v1 as v1;
+use crate::abi::http_server_v2 as v2;
+use crate::abi::websocket_v1;
+use crate::worker;
 
 
 pub struct Process<'a>(PhantomData<&'a ()>);
@@ -12,7 +22,23 @@ pub struct Process<'a>(PhantomData<&'a ()>);
 pub struct ConvertRequest<'a> {
     hyper: &'a hyper::Request<hyper::Body>,
     headers: Vec<(&'a [u8], &'a [u8])>,
-    body: hyper::body::Bytes,
+    body: Option<hyper::Body>,
+    full_body: Bytes,
+    remote_addr: Option<String>,
+}
+
+struct BodySource {
+    body: hyper::Body,
+    buf: Bytes,
+}
+
+/// Response to `GET /metrics` in Prometheus text format
+pub fn metrics(text: String) -> hyper::Response<hyper::Body> {
+    hyper::Response::builder()
+        .status(hyper::StatusCode::OK)
+        .header("Content-Type", "text/plain; version=0.0.4")
+        .body(text.into())
+        .expect("can compose metrics response")
 }
 
 #[async_trait::async_trait]
@@ -21,17 +47,23 @@ impl<'a> http::Process for Process<'a> {
     type ConvertInput = ConvertRequest<'a>;
     type Output = hyper::Response<hyper::Body>;
 
-    async fn read_full(req: &'a mut hyper::Request<hyper::Body>)
+    async fn read_head(req: &'a mut hyper::Request<hyper::Body>)
         -> anyhow::Result<ConvertRequest<'a>>
     {
-        let body = hyper::body::to_bytes(req.body_mut()).await?;
+        let body = mem::take(req.body_mut());
+        // inserted by the connection handler in main
+        let remote_addr = req.extensions().get::<SocketAddr>()
+            .map(|a| a.to_string());
+        let req: &'a hyper::Request<hyper::Body> = req;
         let headers = req.headers().iter().map(|(n, v)| {
             (n.as_ref(), v.as_bytes())
         }).collect();
         Ok(ConvertRequest {
             hyper: req,
             headers,
-            body,
+            body: Some(body),
+            full_body: Bytes::new(),
+            remote_addr,
         })
     }
     fn err_not_found() -> Self::Output {
@@ -48,33 +80,138 @@ impl<'a> http::Process for Process<'a> {
             .body(b"Wasm failed to handle request"[..].into())
             .expect("can compose static response")
     }
+    fn err_service_unavailable() -> Self::Output {
+        hyper::Response::builder()
+            .status(hyper::StatusCode::SERVICE_UNAVAILABLE)
+            .body(b"Wasm failed to initialize"[..].into())
+            .expect("can compose static response")
+    }
+}
+
+impl ConvertRequest<'_> {
+    fn host(&self) -> Option<&str> {
+        self.hyper.uri().authority().map(|a| a.as_str()).or_else(|| {
+            self.hyper.headers().get(hyper::header::HOST)
+                .and_then(|h| h.to_str().ok())
+        })
+    }
+    fn scheme(&self) -> &str {
+        // test server only listens plain HTTP
+        self.hyper.uri().scheme_str().unwrap_or("http")
+    }
+    fn path(&self) -> &str {
+        self.hyper.uri().path_and_query().map(|p| p.as_str()).unwrap_or("/")
+    }
+    pub fn as_websocket_v1<'b>(&'b self, name: &'b worker::Name,
+                               mount_prefix: &'b str)
+        -> websocket_v1::Request<'b>
+    {
+        websocket_v1::Request {
+            method: self.hyper.method().as_str(),
+            uri: self.path(),
+            headers: &self.headers,
+            database: &name.database,
+            module_name: &name.wasm_name,
+            mount_prefix,
+            scheme: Some(self.scheme()),
+            host: self.host(),
+            remote_addr: self.remote_addr.as_deref(),
+        }
+    }
 }
 
+#[async_trait::async_trait]
 impl<'a> http::ConvertInput for ConvertRequest<'a> {
     fn uri(&self) -> &Uri {
         self.hyper.uri()
     }
+    async fn read_full_body(&mut self) -> anyhow::Result<()> {
+        if let Some(body) = self.body.take() {
+            self.full_body = hyper::body::to_bytes(body).await?;
+        }
+        Ok(())
+    }
+    fn take_body(&mut self) -> Box<dyn Source> {
+        Box::new(BodySource {
+            body: self.body.take().unwrap_or_default(),
+            buf: mem::take(&mut self.full_body),
+        })
+    }
     fn as_v1(&self) -> v1::Request<'_> {
-        // TODO(tailhook) uri that contains authority is skipped here
         v1::Request {
             method: self.hyper.method().as_str(),
-            uri: self.hyper.uri().path_and_query()
-                .map(|p| p.as_str()).unwrap_or("/"),
+            uri: self.path(),
+            headers: &self.headers,
+            body: &self.full_body.as_ref(),
+        }
+    }
+    fn as_v2<'b>(&'b self, name: &'b worker::Name, mount_prefix: &'b str)
+        -> v2::Request<'b>
+    {
+        v2::Request {
+            method: self.hyper.method().as_str(),
+            uri: self.path(),
             headers: &self.headers,
-            body: &self.body.as_ref(),
+            database: &name.database,
+            module_name: &name.wasm_name,
+            mount_prefix,
+            scheme: Some(self.scheme()),
+            host: self.host(),
+            remote_addr: self.remote_addr.as_deref(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for BodySource {
+    async fn read(&mut self, max_size: usize) -> Result<Bytes, StreamError> {
+        if self.buf.is_empty() {
+            match self.body.data().await {
+                Some(Ok(data)) => self.buf = data,
+                Some(Err(e)) => {
+                    log::debug!("Error reading request body: {e:#}");
+                    return Err(StreamError::Failed);
+                }
+                None => return Ok(Bytes::new()),
+            }
         }
+        let size = self.buf.len().min(max_size);
+        Ok(self.buf.split_to(size))
+    }
+}
+
+fn response_head(status_code: u16, headers: Vec<(Vec<u8>, Vec<u8>)>)
+    -> hyper::http::response::Builder
+{
+    let mut resp = hyper::Response::builder();
+    resp = resp.status(status_code);
+    for (n, v) in headers {
+        resp = resp.header(n, v);
     }
+    resp
 }
 
 impl http::FromWasm for hyper::Response<hyper::Body> {
     fn from_wasm(req: v1::Response)
         -> anyhow::Result<hyper::Response<hyper::Body>>
     {
-        let mut resp = hyper::Response::builder();
-        resp = resp.status(req.status_code);
-        for (n, v) in req.headers {
-            resp = resp.header(n, v);
-        }
+        let resp = response_head(req.status_code, req.headers);
         Ok(resp.body(req.body.into())?)
     }
+    fn from_wasm_v2(req: v2::Response)
+        -> anyhow::Result<(Self, Option<Box<dyn Sink>>)>
+    {
+        let resp = response_head(req.status_code, req.headers);
+        match req.body {
+            v2::Body::Full(body) => Ok((resp.body(body.into())?, None)),
+            v2::Body::Stream => {
+                // hyper drops the body (and the receiver) when client
+                // disconnects
+                let (sink, receiver) = http_body_v1::channel();
+                let stream = ReceiverStream::new(receiver)
+                    .map(Ok::<_, Infallible>);
+                Ok((resp.body(hyper::Body::wrap_stream(stream))?, Some(sink)))
+            }
+        }
+    }
 }
@@ -0,0 +1,115 @@
+//! JSON output of the server log and logging of modules with overridden level
+//!
+//! Structured fields of the records (logged by `edgedb_log_v2`) are passed
+//! as key-values of the `log` crate. Text output doesn't print key-values, so
+//! fields are rendered into the message instead, when JSON is not enabled.
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use log::{kv, Log};
+use once_cell::sync::OnceCell;
+use serde_json::{Map, Value};
+
+/// Key-value containing the list of entered spans of the record
+pub const SPANS_KEY: &str = "spans";
+
+static JSON: AtomicBool = AtomicBool::new(false);
+static UNFILTERED: OnceCell<env_logger::Logger> = OnceCell::new();
+
+/// Log lines of a module collected instead of being logged (used by tests)
+///
+/// Shared between the store and the stdout/stderr pipes of the instance.
+#[derive(Debug, Clone, Default)]
+pub struct Capture(Arc<Mutex<Option<Vec<String>>>>);
+
+impl Capture {
+    /// Starts collecting lines
+    pub fn enable(&self) {
+        *self.0.lock().expect("capture is not poisoned") = Some(Vec::new());
+    }
+    /// Stops collecting and returns lines collected so far
+    pub fn take(&self) -> Vec<String> {
+        self.0.lock().expect("capture is not poisoned")
+            .take().unwrap_or_default()
+    }
+    /// Adds a line if capture is enabled, otherwise returns `false`
+    pub fn push(&self, line: impl FnOnce() -> String) -> bool {
+        match &mut *self.0.lock().expect("capture is not poisoned") {
+            Some(lines) => {
+                lines.push(line());
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Whether log records should carry fields as key-values
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+#[derive(Default)]
+struct Fields {
+    fields: Map<String, Value>,
+    spans: Option<Value>,
+}
+
+impl<'kvs> kv::Visitor<'kvs> for Fields {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>)
+        -> Result<(), kv::Error>
+    {
+        let value = serde_json::to_value(&value)
+            .unwrap_or_else(|_| Value::String(value.to_string()));
+        if key.as_str() == SPANS_KEY {
+            self.spans = Some(value);
+        } else {
+            self.fields.insert(key.to_string(), value);
+        }
+        Ok(())
+    }
+}
+
+fn format(buf: &mut env_logger::fmt::Formatter, record: &log::Record)
+    -> io::Result<()>
+{
+    let mut fields = Fields::default();
+    // visitor never fails
+    record.key_values().visit(&mut fields).ok();
+    let mut line = Map::new();
+    line.insert("timestamp".into(), buf.timestamp_millis().to_string().into());
+    line.insert("level".into(), record.level().as_str().into());
+    line.insert("target".into(), record.target().into());
+    line.insert("message".into(), record.args().to_string().into());
+    if !fields.fields.is_empty() {
+        line.insert("fields".into(), Value::Object(fields.fields));
+    }
+    if let Some(spans) = fields.spans {
+        line.insert(SPANS_KEY.into(), spans);
+    }
+    serde_json::to_writer(&mut *buf, &line)?;
+    writeln!(buf)
+}
+
+/// Switches logger to JSON output (one object per line)
+pub fn enable_json(builder: &mut env_logger::Builder) {
+    JSON.store(true, Ordering::Relaxed);
+    builder.format(format);
+}
+
+/// Sets logger used for records which must bypass `RUST_LOG` filters
+pub fn set_unfiltered(logger: env_logger::Logger) {
+    UNFILTERED.set(logger).ok();
+}
+
+/// Logs record of a module
+///
+/// When level of the module is overridden, record is already filtered by that
+/// level and should not be dropped by the global filter.
+pub fn emit(record: &log::Record, overridden: bool) {
+    match UNFILTERED.get() {
+        Some(logger) if overridden => logger.log(record),
+        _ => log::logger().log(record),
+    }
+}
@@ -1,12 +1,20 @@
 mod abi;
 mod bug;
+mod config_file;
 mod hyper;
+mod logging;
 mod options;
+mod registry;
+mod secrets;
+mod stdio;
 mod tenant;
+mod test_runner;
 mod unix_sock;
+mod websocket;
 mod worker;
 mod module;
 
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::net::SocketAddr;
 use std::os::unix::io::FromRawFd;
@@ -15,16 +23,18 @@ use std::env;
 
 use anyhow::Context;
 use clap::Parser;
+use ::hyper::server::conn::AddrStream;
 use ::hyper::service::{make_service_fn, service_fn};
 use ::hyper::{Server};
 use tokio::fs;
 use tokio::net::UnixListener;
 
-use options::Options;
+use options::{Command, LogFormat, Options};
 use tenant::Tenant;
+use abi::http_client_v1;
 
 
-pub fn init_logging() {
+pub fn init_logging(format: LogFormat) {
     let mut builder = env_logger::Builder::from_env(
         env_logger::Env::default().default_filter_or("warn")
     );
@@ -33,13 +43,42 @@ pub fn init_logging() {
         builder.filter_module("wasm", log::LevelFilter::Info);
         builder.filter_module("edgedb_wasm_server", log::LevelFilter::Info);
     }
+    // modules with overridden log level are filtered by the level instead
+    let mut unfiltered = env_logger::Builder::new();
+    unfiltered.filter_level(log::LevelFilter::Trace);
+    if format == LogFormat::Json {
+        logging::enable_json(&mut builder);
+        logging::enable_json(&mut unfiltered);
+    }
+    logging::set_unfiltered(unfiltered.build());
     builder.init();
 }
 
+fn set_http_client_policies(tenant: &Tenant, database: &str,
+                            allow: &[String])
+    -> anyhow::Result<()>
+{
+    let mut hosts = HashMap::<&str, Vec<String>>::new();
+    for item in allow {
+        let (module, host) = item.split_once('=')
+            .with_context(|| format!("invalid --http-client-allow {:?}, \
+                                      expected <module>=<host>", item))?;
+        hosts.entry(module).or_default().push(host.into());
+    }
+    for (module, allowed_hosts) in hosts {
+        tenant.set_http_client_policy(database, module, http_client_v1::Policy {
+            allowed_hosts,
+            timeout: http_client_v1::DEFAULT_TIMEOUT,
+            max_response_size: http_client_v1::DEFAULT_MAX_RESPONSE_SIZE,
+        });
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let options = Options::parse();
-    init_logging();
+    init_logging(options.log_format);
     log::debug!("Options {:#?}", options);
 
     let mut builder = edgedb_tokio::Builder::uninitialized();
@@ -49,8 +88,20 @@ async fn main() -> anyhow::Result<()> {
         builder.host_port(Some("localhost"), Some(5656));
     }
     let tenant = Tenant::new("default", builder).await?;
+    if let Some(path) = &options.config_file {
+        config_file::load(&tenant, path).await?;
+    }
+    if let Some(path) = &options.secrets {
+        secrets::load(&tenant, path).await?;
+    }
 
-    if let Some(fd) = options.fd {
+    if let Some(Command::Test(test)) = &options.command {
+        set_http_client_policies(&tenant, &test.database,
+                                 &options.http_client_allow)?;
+        if !test_runner::run(&tenant, test).await? {
+            std::process::exit(1);
+        }
+    } else if let Some(fd) = options.fd {
         let listener = unsafe { StdUnix::from_raw_fd(fd) };
         listener.set_nonblocking(true).context("error unblocking socket")?;
         let listener: UnixListener = listener.try_into()
@@ -83,6 +134,8 @@ async fn main() -> anyhow::Result<()> {
             }
         }
     } else {
+        set_http_client_policies(&tenant, "edgedb",
+                                 &options.http_client_allow)?;
         if let Some(dir) = &options.wasm_dir {
             tenant.set_directory("edgedb", dir).await;
         } else {
@@ -90,13 +143,21 @@ async fn main() -> anyhow::Result<()> {
         }
         log::warn!("Running in test mode, \
                     only `edgedb` database is supported");
-        let make_svc = make_service_fn(|_conn| {
+        let make_svc = make_service_fn(|conn: &AddrStream| {
             let tenant = tenant.clone();
+            let remote_addr = conn.remote_addr();
             async move {
                 Ok::<_, Infallible>(service_fn(move |req| {
                     let tenant = tenant.clone();
-                    async {
+                    async move {
                         let mut req = req;
+                        req.extensions_mut().insert(remote_addr);
+                        if req.uri().path() == "/metrics" {
+                            return Ok(hyper::metrics(tenant.render_metrics()));
+                        }
+                        if websocket::is_upgrade(&req) {
+                            return websocket::handle(tenant, req).await;
+                        }
                         tenant.handle::<hyper::Process>(&mut req).await
                     }
                 }))
@@ -5,9 +5,25 @@ use std::time::SystemTime;
 
 use crate::tenant::TenantInner;
 
+/// Prefix of the exports declaring schedules: `<prefix><name>:<cron>`
+const SCHEDULE_PREFIX: &str = "_edgedb_sdk_schedule:";
+
 pub struct Module {
     pub path: Arc<PathBuf>,
     pub tenant: Arc<TenantInner>,
     pub modification_time: SystemTime,
     pub wasm: wasmtime::Module,
 }
+
+impl Module {
+    /// Returns `(name, cron)` pairs declared by the `schedule` attribute
+    ///
+    /// These are read from exports, so module is not instantiated.
+    pub fn schedules(&self) -> Vec<(String, String)> {
+        self.wasm.exports().filter_map(|e| {
+            let (name, cron) = e.name().strip_prefix(SCHEDULE_PREFIX)?
+                .split_once(':')?;
+            Some((name.to_string(), cron.to_string()))
+        }).collect()
+    }
+}
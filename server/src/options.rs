@@ -23,4 +23,57 @@ pub struct Options {
     /// Directory with wasm files (for single tenant)
     #[clap(long, conflicts_with="unix-socket")]
     pub wasm_dir: Option<PathBuf>,
+
+    /// Allow module to send HTTP requests to the host, in the form of
+    /// `<module>=<host>` (for single tenant and tests). Host might be
+    /// `example.com`, `*.example.com`, `localhost:8080` or `*`.
+    #[clap(long, multiple_occurrences=true, conflicts_with="unix-socket")]
+    pub http_client_allow: Vec<String>,
+
+    /// TOML file with configuration values of modules
+    #[clap(long)]
+    pub config_file: Option<PathBuf>,
+
+    /// TOML file or directory with secrets of modules, must not be
+    /// accessible by group or others
+    #[clap(long)]
+    pub secrets: Option<PathBuf>,
+
+    /// Format of the server log
+    #[clap(long, arg_enum, default_value="text")]
+    pub log_format: LogFormat,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(clap::ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    /// JSON object per line, with structured fields of `edgedb_log_v2`
+    Json,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+    /// Run tests (functions marked with `#[edgedb_sdk::test]`) of a module
+    Test(TestOptions),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct TestOptions {
+    /// WebAssembly file to test
+    pub file: PathBuf,
+
+    /// Database to run tests against
+    #[clap(long, default_value="edgedb")]
+    pub database: String,
+
+    /// Only run tests whose name contains this string
+    #[clap(long)]
+    pub filter: Option<String>,
+
+    /// Show log output of passed tests too
+    #[clap(long)]
+    pub show_output: bool,
 }
@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use crate::worker::Name;
+
+/// Per-module values by database and module name
+///
+/// Clones share the same values. Registries are stored in the tenant, so
+/// values are kept when modules reload.
+pub struct Registry<T>(Arc<RwLock<HashMap<Name, Arc<T>>>>);
+
+impl<T> Clone for Registry<T> {
+    fn clone(&self) -> Registry<T> {
+        Registry(self.0.clone())
+    }
+}
+
+impl<T> Default for Registry<T> {
+    fn default() -> Registry<T> {
+        Registry(Default::default())
+    }
+}
+
+impl<T> fmt::Debug for Registry<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut dbg = f.debug_struct("Registry");
+        match self.0.try_read() {
+            Ok(s) => dbg.field("modules", &s.len()),
+            Err(_) => dbg.field("modules", &"--locked--"),
+        };
+        dbg.finish()
+    }
+}
+
+impl<T> Registry<T> {
+    pub fn get(&self, name: &Name) -> Option<Arc<T>> {
+        self.0.read().expect("registry is not poisoned").get(name).cloned()
+    }
+    /// Sets the value of the module, returns the previous one
+    pub fn insert(&self, database: &str, wasm_name: &str, value: T)
+        -> Option<Arc<T>>
+    {
+        let name = Name {
+            database: database.into(),
+            wasm_name: wasm_name.into(),
+        };
+        self.0.write().expect("registry is not poisoned")
+            .insert(name, Arc::new(value))
+    }
+    pub fn remove(&self, database: &str, wasm_name: &str) -> Option<Arc<T>> {
+        let name = Name {
+            database: database.into(),
+            wasm_name: wasm_name.into(),
+        };
+        self.0.write().expect("registry is not poisoned").remove(&name)
+    }
+    /// Replaces values of all modules
+    pub fn replace(&self, all: HashMap<Name, T>) {
+        let all = all.into_iter().map(|(k, v)| (k, Arc::new(v))).collect();
+        *self.0.write().expect("registry is not poisoned") = all;
+    }
+}
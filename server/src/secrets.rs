@@ -0,0 +1,122 @@
+//! Loads secrets of modules from a file or a directory
+//!
+//! File is TOML with a table per database and module:
+//!
+//! ```toml
+//! [edgedb.my_module]
+//! api_key = "..."
+//! ```
+//!
+//! Directory contains a file per secret: `<database>/<module>/<name>`
+//! (trailing newline of the file is stripped).
+//!
+//! Neither the file nor the directory may be accessible by group or others.
+//! Errors never include secret values, only names and positions.
+use std::collections::{BTreeMap, HashMap};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use tokio::fs;
+
+use crate::abi::secrets_v1::{Secret, Values};
+use crate::tenant::Tenant;
+use crate::worker::Name;
+
+type All = HashMap<Name, Values>;
+type Databases = BTreeMap<String, BTreeMap<String, BTreeMap<String, String>>>;
+
+async fn check_permissions(path: &Path) -> anyhow::Result<()> {
+    let meta = fs::metadata(path).await
+        .with_context(|| format!("cannot stat {:?}", path))?;
+    if meta.permissions().mode() & 0o077 != 0 {
+        anyhow::bail!("{:?} must not be accessible by group or others, \
+                       run `chmod go-rwx` on it", path);
+    }
+    Ok(())
+}
+
+async fn read_file(path: &Path) -> anyhow::Result<All> {
+    let data = fs::read_to_string(path).await
+        .with_context(|| format!("cannot read {:?}", path))?;
+    // error message may contain a part of the file, so only position is shown
+    let databases: Databases = toml::from_str(&data).map_err(|e| {
+        match e.line_col() {
+            Some((line, col)) => {
+                anyhow::anyhow!("invalid secrets file {:?} at line {}, \
+                                 column {}: expected tables of strings",
+                                path, line + 1, col + 1)
+            }
+            None => {
+                anyhow::anyhow!("invalid secrets file {:?}: \
+                                 expected tables of strings", path)
+            }
+        }
+    })?;
+    let mut all = HashMap::new();
+    for (database, modules) in databases {
+        for (module, values) in modules {
+            let values = values.into_iter()
+                .map(|(k, v)| (k, Secret::new(v)))
+                .collect();
+            let name = Name {
+                database: database.clone(),
+                wasm_name: module,
+            };
+            all.insert(name, values);
+        }
+    }
+    Ok(all)
+}
+
+async fn read_dir_names(path: &Path) -> anyhow::Result<Vec<(String, PathBuf)>> {
+    let mut result = Vec::new();
+    let mut dir = fs::read_dir(path).await
+        .with_context(|| format!("cannot read directory {:?}", path))?;
+    while let Some(entry) = dir.next_entry().await? {
+        let name = match entry.file_name().to_str() {
+            Some(name) if !name.starts_with('.') => name.to_string(),
+            _ => continue,
+        };
+        result.push((name, entry.path()));
+    }
+    Ok(result)
+}
+
+async fn read_dir(path: &Path) -> anyhow::Result<All> {
+    let mut all = HashMap::new();
+    for (database, db_path) in read_dir_names(path).await? {
+        for (module, mod_path) in read_dir_names(&db_path).await? {
+            let mut values = BTreeMap::new();
+            for (name, secret_path) in read_dir_names(&mod_path).await? {
+                check_permissions(&secret_path).await?;
+                let mut value = fs::read_to_string(&secret_path).await
+                    .with_context(|| {
+                        format!("cannot read {:?}", secret_path)
+                    })?;
+                if value.ends_with('\n') {
+                    value.pop();
+                }
+                values.insert(name, Secret::new(value));
+            }
+            let name = Name {
+                database: database.clone(),
+                wasm_name: module,
+            };
+            all.insert(name, values);
+        }
+    }
+    Ok(all)
+}
+
+pub async fn load(tenant: &Tenant, path: &Path) -> anyhow::Result<()> {
+    check_permissions(path).await?;
+    let all = if fs::metadata(path).await?.is_dir() {
+        read_dir(path).await?
+    } else {
+        read_file(path).await?
+    };
+    log::info!("Loaded secrets of {} modules from {:?}", all.len(), path);
+    tenant.set_secrets(all);
+    Ok(())
+}
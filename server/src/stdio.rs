@@ -0,0 +1,181 @@
+//! Capturing of stdout and stderr of the modules into the log
+//!
+//! Output is split into lines which are logged with the
+//! `wasm::<database>::<module>::stdout` (or `stderr`) target. Like other log
+//! records of the module, lines are subject to its log level and are
+//! captured when running tests.
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::time::Instant;
+
+use wasi_common::pipe::WritePipe;
+
+use crate::abi::log_v2::LogLevels;
+use crate::logging::{self, Capture};
+use crate::worker::Name;
+
+/// Maximum length of the line in bytes, the rest of the line is dropped
+const MAX_LINE_LENGTH: usize = 4096;
+/// Number of lines that can be logged at once after a period of silence
+const BURST_LINES: f64 = 100.;
+/// Number of lines per second that can be logged continuously
+const LINES_PER_SECOND: f64 = 20.;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Writer that logs every line written
+#[derive(Debug)]
+pub struct LogWriter {
+    name: Arc<Name>,
+    stream: &'static str,
+    target: String,
+    level: log::Level,
+    levels: LogLevels,
+    capture: Capture,
+    line: Vec<u8>,
+    truncated: bool,
+    limit: RateLimit,
+}
+
+/// Token bucket of lines
+#[derive(Debug)]
+struct RateLimit {
+    tokens: f64,
+    updated: Instant,
+    dropped: u64,
+}
+
+impl RateLimit {
+    fn new() -> RateLimit {
+        RateLimit {
+            tokens: BURST_LINES,
+            updated: Instant::now(),
+            dropped: 0,
+        }
+    }
+    fn allow(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * LINES_PER_SECOND)
+            .min(BURST_LINES);
+        self.updated = now;
+        if self.tokens >= 1. {
+            self.tokens -= 1.;
+            true
+        } else {
+            self.dropped += 1;
+            false
+        }
+    }
+}
+
+/// Creates a pipe to be used as stdout or stderr of the instance
+pub fn pipe(name: &Arc<Name>, stream: Stream, levels: &LogLevels,
+            capture: &Capture)
+    -> WritePipe<LogWriter>
+{
+    let (suffix, level) = match stream {
+        Stream::Stdout => ("stdout", log::Level::Info),
+        Stream::Stderr => ("stderr", log::Level::Warn),
+    };
+    WritePipe::new(LogWriter {
+        name: name.clone(),
+        stream: suffix,
+        target: format!("wasm::{}::{}::{}",
+                        name.database, name.wasm_name, suffix),
+        level,
+        levels: levels.clone(),
+        capture: capture.clone(),
+        line: Vec::new(),
+        truncated: false,
+        limit: RateLimit::new(),
+    })
+}
+
+impl LogWriter {
+    fn push(&mut self, data: &[u8]) {
+        let room = MAX_LINE_LENGTH.saturating_sub(self.line.len());
+        if data.len() > room {
+            self.line.extend_from_slice(&data[..room]);
+            self.truncated = true;
+        } else {
+            self.line.extend_from_slice(data);
+        }
+    }
+    /// Logs the same way as `edgedb_log_v1` does
+    fn emit(&self, level: log::Level, message: fmt::Arguments) {
+        let captured = self.capture.push(|| {
+            format!("[{} {}] {}", level, self.stream, message)
+        });
+        if captured {
+            return;
+        }
+        let overridden = match self.levels.get(&self.name) {
+            Some(max) if level > max => return,
+            Some(_) => true,
+            None => false,
+        };
+        let meta = log::MetadataBuilder::new()
+            .target(&self.target)
+            .level(level)
+            .build();
+        logging::emit(&log::Record::builder()
+            .metadata(meta)
+            .args(message)
+            .build(), overridden);
+    }
+    fn log_skipped(&mut self) {
+        if self.limit.dropped > 0 {
+            self.emit(log::Level::Warn, format_args!(
+                "{} lines skipped because of rate limit",
+                self.limit.dropped));
+            self.limit.dropped = 0;
+        }
+    }
+    fn log_line(&mut self) {
+        if self.limit.allow() {
+            self.log_skipped();
+            let line = String::from_utf8_lossy(&self.line);
+            let line = line.strip_suffix('\r').unwrap_or(&line);
+            if self.truncated {
+                self.emit(self.level,
+                          format_args!("{}... (truncated)", line));
+            } else {
+                self.emit(self.level, format_args!("{}", line));
+            }
+        }
+        self.line.clear();
+        self.truncated = false;
+    }
+}
+
+impl io::Write for LogWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let mut rest = data;
+        while let Some(pos) = rest.iter().position(|&b| b == b'\n') {
+            self.push(&rest[..pos]);
+            self.log_line();
+            rest = &rest[pos+1..];
+        }
+        self.push(rest);
+        Ok(data.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        // incomplete line is logged when complete or on drop
+        Ok(())
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        if !self.line.is_empty() {
+            self.log_line();
+        }
+        self.log_skipped();
+    }
+}
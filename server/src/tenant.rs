@@ -1,4 +1,6 @@
 pub mod http;
+pub mod jobs;
+pub mod scheduler;
 
 use std::collections::{HashMap, HashSet};
 use std::collections::hash_map::Entry;
@@ -6,6 +8,7 @@ use std::fmt;
 use std::mem;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Weak};
+use std::time::Duration;
 
 use anyhow::Context;
 use async_once_cell::OnceCell as Cell;
@@ -21,6 +24,11 @@ use crate::module::Module;
 
 type Database = String;
 
+/// Interval at which running wasm code yields to the runtime
+///
+/// This is also the precision of the time limits of the guest calls.
+const EPOCH_INTERVAL: Duration = Duration::from_millis(10);
+
 #[derive(Clone)]
 pub struct Tenant(Arc<TenantInner>);
 
@@ -32,9 +40,18 @@ pub struct TenantInner {
     modules: Mutex<HashMap<Arc<PathBuf>, Arc<Cell<Weak<Module>>>>>,
     engine: wasmtime::Engine,
     linker: wasmtime::Linker<worker::State>,
+    scheduler: scheduler::Scheduler,
+    jobs: jobs::Jobs,
+    http_client: abi::http_client_v1::Client,
+    http_client_policies: abi::http_client_v1::Policies,
+    caches: abi::cache_v1::Caches,
+    configs: abi::config_v1::Configs,
+    secrets: abi::secrets_v1::Secrets,
+    metrics: abi::metrics_v1::Metrics,
+    log_levels: abi::log_v2::LogLevels,
 }
 
-fn is_valid_name(name: &str) -> bool {
+pub fn is_valid_name(name: &str) -> bool {
     let mut chars = name.chars();
     if let Some(c) = chars.next() {
         if !c.is_ascii_alphabetic() {
@@ -44,6 +61,31 @@ fn is_valid_name(name: &str) -> bool {
     return chars.all(|x| x.is_ascii_alphanumeric() || x == '_' || x == '-');
 }
 
+/// Returns database and wasm name from `/db/<database>/wasm/<name>/..` path
+pub fn parse_path(path: &str) -> Option<(&str, &str)> {
+    let mut parts = path.split('/');
+    if parts.next() != Some("") {
+        return None;
+    }
+    if parts.next() != Some("db") {
+        return None;
+    }
+    let database = parts.next()?;
+    if parts.next() != Some("wasm") {
+        return None;
+    }
+    let wasm_name = parts.next()?;
+    if !is_valid_name(wasm_name) {
+        return None;
+    }
+    Some((database, wasm_name))
+}
+
+/// Runs shutdown hooks of the worker in background
+fn evict(wrk: worker::Worker) {
+    tokio::spawn(async move { wrk.shutdown().await });
+}
+
 impl Tenant {
     pub async fn new(_name: &str, config: edgedb_tokio::Builder)
         -> anyhow::Result<Tenant>
@@ -51,17 +93,58 @@ impl Tenant {
         let engine = wasmtime::Engine::new(
             wasmtime::Config::new()
             .async_support(true)
+            .epoch_interruption(true)
         ).context("cannot create engine")?;
+        let ticker = engine.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(EPOCH_INTERVAL);
+            loop {
+                interval.tick().await;
+                ticker.increment_epoch();
+            }
+        });
         let mut linker = wasmtime::Linker::new(&engine);
         wasmtime_wasi::add_to_linker(&mut linker, worker::State::wasi)
             .context("error linking WASI")?;
         abi::log_v1::add_to_linker(&mut linker, |s| s)
             .context("error linking edgedb_log_v1")?;
+        abi::log_v2::add_to_linker(&mut linker, |s| s)
+            .context("error linking edgedb_log_v2")?;
         abi::client_v1::add_to_linker(&mut linker, worker::State::client_v1)
             .context("error linking edgedb_client_v1")?;
         abi::http_server_v1::Handler::add_to_linker(
             &mut linker, worker::State::http_server_v1)
             .context("error linking edgedb_http_server_v1")?;
+        abi::http_server_v2::Handler::add_to_linker(
+            &mut linker, worker::State::http_server_v2)
+            .context("error linking edgedb_http_server_v2")?;
+        abi::http_body_v1::add_to_linker(
+            &mut linker, worker::State::http_body_v1)
+            .context("error linking edgedb_http_body_v1")?;
+        abi::websocket_v1::Handler::add_to_linker(
+            &mut linker, worker::State::websocket_v1)
+            .context("error linking edgedb_websocket_v1")?;
+        abi::websocket_connection_v1::add_to_linker(
+            &mut linker, worker::State::websocket_connection_v1)
+            .context("error linking edgedb_websocket_connection_v1")?;
+        abi::scheduler_v1::Handler::add_to_linker(
+            &mut linker, worker::State::scheduler_v1)
+            .context("error linking edgedb_scheduler_v1")?;
+        abi::http_client_v1::add_to_linker(
+            &mut linker, worker::State::http_client_v1)
+            .context("error linking edgedb_http_client_v1")?;
+        abi::cache_v1::add_to_linker(&mut linker, |s| s)
+            .context("error linking edgedb_cache_v1")?;
+        abi::config_v1::add_to_linker(&mut linker, |s| s)
+            .context("error linking edgedb_config_v1")?;
+        abi::secrets_v1::add_to_linker(&mut linker, |s| s)
+            .context("error linking edgedb_secrets_v1")?;
+        abi::metrics_v1::add_to_linker(&mut linker, |s| s)
+            .context("error linking edgedb_metrics_v1")?;
+        abi::jobs_v1::add_to_linker(&mut linker, |s| s)
+            .context("error linking edgedb_jobs_v1")?;
+        abi::invoke_v1::add_to_linker(&mut linker, worker::State::invoke_v1)
+            .context("error linking edgedb_invoke_v1")?;
 
         Ok(Tenant(Arc::new(TenantInner {
             config,
@@ -71,42 +154,37 @@ impl Tenant {
             directories: RwLock::new(HashMap::new()),
             engine,
             linker,
+            scheduler: scheduler::Scheduler::default(),
+            jobs: jobs::Jobs::default(),
+            http_client: abi::http_client_v1::client(),
+            http_client_policies: Default::default(),
+            caches: Default::default(),
+            configs: Default::default(),
+            secrets: Default::default(),
+            metrics: Default::default(),
+            log_levels: Default::default(),
         })))
     }
 
     pub async fn handle<P>(self, req: P::Input) -> anyhow::Result<P::Output>
         where P: http::Process,
     {
-        let cvt = P::read_full(req).await?;
-        let mut parts = cvt.uri().path().split('/');
-        if parts.next() != Some("") {
-            return Ok(P::err_not_found())
-        }
-        if parts.next() != Some("db") {
-            return Ok(P::err_not_found())
-        }
-        let database = if let Some(db) = parts.next() {
-            db
-        } else {
-            return Ok(P::err_not_found())
-        };
-        if parts.next() != Some("wasm") {
-            return Ok(P::err_not_found())
-        }
-        let wasm_name = if let Some(name) = parts.next() {
-            name
-        } else {
-            return Ok(P::err_not_found())
+        let cvt = P::read_head(req).await?;
+        let (database, wasm_name) = match parse_path(cvt.uri().path()) {
+            Some(pair) => pair,
+            None => return Ok(P::err_not_found()),
         };
-        if !is_valid_name(wasm_name) {
-            return Ok(P::err_not_found())
-        }
         // TODO(tailhook) capture unknown worker error and convert to 404
         match self.get_worker(database, wasm_name).await {
             Ok(worker) => {
                 log::debug!("Worker for {database:?} name {wasm_name:?} found");
                 worker.handle_http::<P>(cvt).await
             }
+            Err(e) if e.is::<worker::InitError>() => {
+                log::error!("Worker {database:?} name {wasm_name:?} \
+                             is not initialized: {:#}", e);
+                return Ok(P::err_service_unavailable());
+            }
             Err(e) => {
                 log::error!("Get worker error: {:#}", e);
                 return Ok(P::err_internal_server_error());
@@ -114,6 +192,11 @@ impl Tenant {
         }
     }
 
+    /// Returns tenant if it's not dropped yet
+    pub fn upgrade(weak: &Weak<TenantInner>) -> Option<Tenant> {
+        weak.upgrade().map(Tenant)
+    }
+
     pub fn get_engine(&self) -> &wasmtime::Engine {
         &self.0.engine
     }
@@ -133,8 +216,142 @@ impl Tenant {
                 wrk.module().path.parent() == Some(directory)
             {
                 wrks.insert(wrk);
+            } else {
+                evict(wrk);
             }
         }
+        drop(wrks);
+        self.0.scheduler.start(self, database, directory);
+        self.0.jobs.start(self, database);
+    }
+
+    pub fn scheduled_tasks(&self, database: Option<&str>)
+        -> Vec<scheduler::TaskStatus>
+    {
+        self.0.scheduler.status(database)
+    }
+
+    /// Sets hosts that module is allowed to send HTTP requests to
+    ///
+    /// Applies to running workers immediately.
+    pub fn set_http_client_policy(&self, database: &str, wasm_name: &str,
+                                  policy: abi::http_client_v1::Policy)
+    {
+        self.0.http_client_policies.set(database, wasm_name, policy);
+    }
+
+    pub fn http_client_state(&self, name: &Arc<worker::Name>)
+        -> abi::http_client_v1::State
+    {
+        abi::http_client_v1::State::new(
+            &self.0.http_client, &self.0.http_client_policies, name)
+    }
+
+    pub fn cache_state(&self, name: &Arc<worker::Name>)
+        -> abi::cache_v1::State
+    {
+        abi::cache_v1::State::new(&self.0.caches, name)
+    }
+
+    pub fn config_state(&self, name: &Arc<worker::Name>)
+        -> abi::config_v1::State
+    {
+        abi::config_v1::State::new(&self.0.configs, name)
+    }
+
+    /// Replaces configuration values of the module
+    ///
+    /// Running worker of the module is notified about changed keys in
+    /// background, or before the next call if it's busy.
+    pub async fn set_config(&self, database: &str, wasm_name: &str,
+                            values: abi::config_v1::Values)
+    {
+        let changed = self.0.configs.set(database, wasm_name, values);
+        if changed.is_empty() {
+            return;
+        }
+        let name = worker::Name {
+            database: database.into(),
+            wasm_name: wasm_name.into(),
+        };
+        if let Some(wrk) = self.0.workers.read().await.get(&name) {
+            wrk.config_changed(changed);
+        }
+    }
+
+    pub fn secrets_state(&self, name: &Arc<worker::Name>)
+        -> abi::secrets_v1::State
+    {
+        abi::secrets_v1::State::new(&self.0.secrets, name)
+    }
+
+    /// Replaces secrets of all modules
+    pub fn set_secrets(&self,
+                       all: HashMap<worker::Name, abi::secrets_v1::Values>)
+    {
+        self.0.secrets.replace(all);
+    }
+
+    pub fn metrics_state(&self, name: &Arc<worker::Name>)
+        -> abi::metrics_v1::State
+    {
+        abi::metrics_v1::State::new(&self.0.metrics, name)
+    }
+
+    /// Metrics of all modules in Prometheus text format
+    pub fn render_metrics(&self) -> String {
+        self.0.metrics.render()
+    }
+
+    pub fn log_v2_state(&self) -> abi::log_v2::State {
+        abi::log_v2::State::new(&self.0.log_levels)
+    }
+
+    pub fn invoke_state(&self, name: &Arc<worker::Name>)
+        -> abi::invoke_v1::State
+    {
+        // weak reference, as the state is owned by the tenant's worker
+        abi::invoke_v1::State::new(Arc::downgrade(&self.0), name)
+    }
+
+    /// Overrides log level of the module, `None` reverts to the global one
+    ///
+    /// Running worker of the module is notified in background, or before
+    /// the next call if it's busy.
+    pub async fn set_log_level(&self, database: &str, wasm_name: &str,
+                               level: Option<log::LevelFilter>)
+    {
+        self.0.log_levels.set(database, wasm_name, level);
+        let name = worker::Name {
+            database: database.into(),
+            wasm_name: wasm_name.into(),
+        };
+        if let Some(wrk) = self.0.workers.read().await.get(&name) {
+            wrk.log_level_changed();
+        }
+    }
+
+    /// Returns worker of the module if it's running and available
+    pub async fn running_worker(&self, database: &str, wasm_name: &str)
+        -> Option<worker::Worker>
+    {
+        let name = worker::Name {
+            database: database.into(),
+            wasm_name: wasm_name.into(),
+        };
+        match self.0.workers.read().await.get(&name) {
+            Some(wrk) if wrk.is_available() => Some(wrk.clone()),
+            _ => None,
+        }
+    }
+
+    /// Client for the queries made by the server itself (not by modules)
+    pub fn server_client(&self, database: &str)
+        -> anyhow::Result<edgedb_tokio::Client>
+    {
+        let mut builder = self.0.config.clone();
+        builder.database(database);
+        Ok(edgedb_tokio::Client::new(&builder.build()?))
     }
 
     pub async fn get_client(&self, database: &str) -> anyhow::Result<Pool> {
@@ -161,6 +378,13 @@ impl Tenant {
         self._get_module(path).await
     }
 
+    /// Loads module by path, not necessarily in any configured directory
+    pub async fn load_module(&self, path: &Path)
+        -> anyhow::Result<Arc<Module>>
+    {
+        self._get_module(path.to_path_buf()).await
+    }
+
     async fn _get_module(&self, path: impl Into<Arc<PathBuf>>)
         -> anyhow::Result<Arc<Module>>
     {
@@ -258,7 +482,7 @@ impl Tenant {
         let wrks = &self.0.workers;
         if let Some(wrk) = wrks.read().await.get(&name) {
             // this checks timestamp
-            if Arc::ptr_eq(wrk.module(), &module) {
+            if Arc::ptr_eq(wrk.module(), &module) && wrk.is_available() {
                 return Ok(wrk.clone());
             }
         }
@@ -266,14 +490,27 @@ impl Tenant {
         if let Some(wrk) = wrks.get(&name) {
             // This presumably has to be refreshed, but some other thread
             // could have already done that
-            if Arc::ptr_eq(wrk.module(), &module) {
+            if Arc::ptr_eq(wrk.module(), &module) && wrk.is_available() {
                 return Ok(wrk.clone());
             }
         }
         let wrk = worker::Worker::new(self, database, wasm_name, module).await?;
-        wrks.replace(wrk.clone());
+        if let Some(old) = wrks.replace(wrk.clone()) {
+            evict(old);
+        }
         Ok(wrk)
     }
+    /// Creates a worker that is not used for HTTP requests
+    ///
+    /// Long running calls (e.g. scheduled tasks) use such worker, so they
+    /// don't hold the store of the request handling worker. Caller must
+    /// shut the worker down when done.
+    pub async fn dedicated_worker(&self, database: &str, wasm_name: &str)
+        -> anyhow::Result<worker::Worker>
+    {
+        let module = self.get_module(database, wasm_name).await?;
+        worker::Worker::new(self, database, wasm_name, module).await
+    }
 }
 
 impl fmt::Debug for Tenant {
@@ -2,7 +2,10 @@ use std::fmt;
 
 use hyper::Uri;
 
+use crate::abi::http_body_v1::{Source, Sink};
 use crate::abi::http_server_v1 as v1;
+use crate::abi::http_server_v2 as v2;
+use crate::worker;
 
 
 #[async_trait::async_trait]
@@ -10,17 +13,35 @@ pub trait Process {
     type Input;
     type ConvertInput: ConvertInput;
     type Output: FromWasm;
-    async fn read_full(input: Self::Input)
+    /// Reads request headers, body is read later by the worker
+    async fn read_head(input: Self::Input)
         -> anyhow::Result<Self::ConvertInput>;
     fn err_not_found() -> Self::Output;
     fn err_internal_server_error() -> Self::Output;
+    /// Worker can't be initialized (e.g. init hook failed)
+    fn err_service_unavailable() -> Self::Output;
 }
 
-pub trait ConvertInput: fmt::Debug {
+#[async_trait::async_trait]
+pub trait ConvertInput: fmt::Debug + Send {
     fn uri(&self) -> &Uri;
+    /// Reads full body into memory (needed for v1 handlers)
+    async fn read_full_body(&mut self) -> anyhow::Result<()>;
+    /// Takes request body for streaming (v2 handlers)
+    fn take_body(&mut self) -> Box<dyn Source>;
+    /// Modules waiting for this request if it's made by another module
+    /// through `edgedb_invoke_v1`, outermost first
+    fn invoke_chain(&self) -> &[String] {
+        &[]
+    }
     fn as_v1(&self) -> v1::Request<'_>;
+    fn as_v2<'a>(&'a self, name: &'a worker::Name, mount_prefix: &'a str)
+        -> v2::Request<'a>;
 }
 
 pub trait FromWasm: Sized {
     fn from_wasm(wasm: v1::Response) -> anyhow::Result<Self>;
+    /// Returns sink for the response body if response is streamed
+    fn from_wasm_v2(wasm: v2::Response)
+        -> anyhow::Result<(Self, Option<Box<dyn Sink>>)>;
 }
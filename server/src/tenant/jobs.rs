@@ -0,0 +1,229 @@
+//! Runs background jobs enqueued by modules (`edgedb_jobs_v1`)
+//!
+//! Jobs are stored in the `WasmJob` type of the database, so they are
+//! enqueued atomically with other changes of the transaction. There is a
+//! single polling loop for every database. It claims due jobs by moving their
+//! `run_at` forward, so a job is retried if the server crashes while running
+//! it. Jobs failed too many times are kept with `dead := true`.
+//!
+//! Every job runs on a fresh instance of the module, so jobs don't wait for
+//! each other or for HTTP requests, and the lease starts when the job does.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use edgedb_errors::InvalidReferenceError;
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+
+use crate::tenant::{Tenant, TenantInner};
+
+/// How often database is checked for due jobs
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How often to check whether `WasmJob` type is added to the schema
+const NO_SCHEMA_INTERVAL: Duration = Duration::from_secs(60);
+/// Maximum number of jobs of a database running at once
+const MAX_RUNNING: usize = 16;
+/// Time after which claimed but unfinished job is run again
+///
+/// Must be longer than `worker::JOB_BUDGET` plus the time to start and shut
+/// down the instance, so job is not run twice at once.
+const LEASE_TIME: Duration = Duration::from_secs(600);
+/// Number of runs after which failing job is not retried
+const MAX_ATTEMPTS: i64 = 5;
+/// Delay before the first retry, doubled for every next one
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(3600);
+
+const CLAIM_QUERY: &str = "
+    WITH jobs := (
+        SELECT WasmJob
+        FILTER NOT .dead AND .run_at <= datetime_current()
+        ORDER BY .run_at
+        LIMIT <int64>$0
+    )
+    SELECT (
+        UPDATE jobs
+        SET {
+            run_at := datetime_current()
+                      + to_duration(seconds := <float64>$1),
+            attempts := .attempts + 1,
+        }
+    ) {
+        id,
+        module_name,
+        name,
+        payload,
+        attempts,
+    }
+";
+
+const FINISH_QUERY: &str = "
+    DELETE WasmJob FILTER .id = <uuid><str>$0
+";
+
+const FAIL_QUERY: &str = "
+    UPDATE WasmJob FILTER .id = <uuid><str>$0
+    SET {
+        run_at := datetime_current() + to_duration(seconds := <float64>$1),
+        last_error := <str>$2,
+        dead := <bool>$3,
+    }
+";
+
+#[derive(Default)]
+pub struct Jobs {
+    loops: Mutex<HashMap<String, JoinHandle<()>>>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Job {
+    id: String,
+    module_name: String,
+    name: String,
+    payload: String,
+    attempts: i64,
+}
+
+impl Jobs {
+    /// (Re)starts polling loop for the database
+    pub fn start(&self, tenant: &Tenant, database: &str) {
+        let client = match tenant.server_client(database) {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Cannot run jobs of database {:?}: {:#}",
+                            database, e);
+                return;
+            }
+        };
+        let task = tokio::spawn(polling_loop(
+            Arc::downgrade(&tenant.0),
+            client,
+            database.to_string(),
+        ));
+        let mut loops = self.loops.lock().expect("jobs are not poisoned");
+        if let Some(old) = loops.insert(database.to_string(), task) {
+            old.abort();
+        }
+    }
+}
+
+fn retry_delay(attempts: i64) -> Duration {
+    let exp = attempts.saturating_sub(1).clamp(0, 16) as u32;
+    RETRY_DELAY.saturating_mul(1 << exp).min(MAX_RETRY_DELAY)
+}
+
+/// Whether error means that schema has no `WasmJob` type
+fn is_missing_type(e: &anyhow::Error) -> bool {
+    matches!(e.downcast_ref::<edgedb_errors::Error>(),
+             Some(e) if e.is::<InvalidReferenceError>())
+}
+
+async fn claim(client: &edgedb_tokio::Client, limit: usize)
+    -> anyhow::Result<Vec<Job>>
+{
+    let json = client.query_json(CLAIM_QUERY,
+        &(limit as i64, LEASE_TIME.as_secs_f64())).await?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+async fn polling_loop(tenant: Weak<TenantInner>,
+                      client: edgedb_tokio::Client,
+                      database: String)
+{
+    let mut no_schema_logged = false;
+    // jobs are not awaited, so a slow job doesn't delay the others
+    let running = Arc::new(Semaphore::new(MAX_RUNNING));
+    loop {
+        let tenant = match tenant.upgrade() {
+            Some(inner) => Tenant(inner),
+            None => return,
+        };
+        let free = running.available_permits();
+        if free == 0 {
+            drop(tenant);
+            tokio::time::sleep(POLL_INTERVAL).await;
+            continue;
+        }
+        let mut sleep = POLL_INTERVAL;
+        // lease runs from now, so only jobs that can start right away
+        // are claimed
+        match claim(&client, free).await {
+            Ok(jobs) => {
+                no_schema_logged = false;
+                let full_batch = jobs.len() >= free;
+                for job in jobs {
+                    // only this loop acquires permits
+                    let permit = running.clone().try_acquire_owned()
+                        .expect("permits are checked above");
+                    let task = run_job(tenant.clone(), client.clone(),
+                                       database.clone(), job);
+                    tokio::spawn(async move {
+                        task.await;
+                        drop(permit);
+                    });
+                }
+                if full_batch {
+                    // there are probably more due jobs
+                    continue;
+                }
+            }
+            Err(e) if is_missing_type(&e) => {
+                if !no_schema_logged {
+                    log::info!("No WasmJob type in database {:?}, \
+                                background jobs are disabled", database);
+                    no_schema_logged = true;
+                }
+                sleep = NO_SCHEMA_INTERVAL;
+            }
+            Err(e) => {
+                log::error!("Error polling jobs of database {:?}: {:#}",
+                            database, e);
+            }
+        }
+        tokio::time::sleep(sleep).await;
+    }
+}
+
+async fn run_job(tenant: Tenant, client: edgedb_tokio::Client,
+                 database: String, job: Job)
+{
+    let Job { id, module_name, name, .. } = &job;
+    log::debug!("Starting job {database}::{module_name}::{name} ({id})");
+    let worker = tenant.dedicated_worker(&database, module_name).await;
+    let result = match worker {
+        Ok(worker) => {
+            let result = worker.run_job(name, &job.payload).await;
+            worker.shutdown().await;
+            result
+        }
+        Err(e) => Err(e),
+    };
+    let error = match result {
+        Ok(Ok(())) => {
+            log::debug!("Job {database}::{module_name}::{name} ({id}) done");
+            if let Err(e) = client.query_json(FINISH_QUERY, &(id.clone(),))
+                .await
+            {
+                log::error!("Cannot delete finished job {}: {:#}", id, e);
+            }
+            return;
+        }
+        Ok(Err(message)) => message,
+        Err(e) => format!("{:#}", e),
+    };
+    let dead = job.attempts >= MAX_ATTEMPTS;
+    if dead {
+        log::error!("Job {database}::{module_name}::{name} ({id}) \
+                     failed {} times, giving up: {error}", job.attempts);
+    } else {
+        log::warn!("Job {database}::{module_name}::{name} ({id}) \
+                    failed, will retry: {error}");
+    }
+    let delay = retry_delay(job.attempts).as_secs_f64();
+    if let Err(e) = client.query_json(FAIL_QUERY,
+        &(id.clone(), delay, error, dead)).await
+    {
+        log::error!("Cannot update failed job {}: {:#}", id, e);
+    }
+}
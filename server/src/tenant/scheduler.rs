@@ -0,0 +1,454 @@
+//! Runs tasks scheduled by modules (`edgedb_scheduler_v1`)
+//!
+//! There is a single scheduling loop for every database. It periodically
+//! rescans the wasm directory, so new and updated modules are picked up.
+//! Schedules declared by the `schedule` attribute are read from exports of
+//! the module, so modules are not instantiated until a task is run.
+//! Schedules registered at runtime are only known by a running instance.
+//!
+//! Every run of a task gets a fresh instance of the module, which is shut
+//! down afterwards, so long tasks don't delay HTTP requests.
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::{DateTime, Utc};
+use tokio::fs;
+use tokio::task::JoinHandle;
+
+use crate::tenant::{Tenant, TenantInner, is_valid_name};
+
+/// How often wasm directory is checked for new or updated modules
+const RESCAN_INTERVAL: Duration = Duration::from_secs(60);
+
+type Key = (String, String, String);
+/// Schedules declared in the module by its modification time
+type Declared = HashMap<String, (SystemTime, Vec<(String, String)>)>;
+
+#[derive(Default)]
+pub struct Scheduler {
+    loops: Mutex<HashMap<String, JoinHandle<()>>>,
+    status: Arc<Mutex<BTreeMap<Key, TaskStatus>>>,
+}
+
+/// Status of the scheduled task as reported via unix socket
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct TaskStatus {
+    pub database: String,
+    pub module_name: String,
+    pub name: String,
+    pub cron: String,
+    pub running: bool,
+    /// Unix timestamp of the last start
+    pub last_started: Option<f64>,
+    pub last_duration: Option<f64>,
+    /// Failure message of the last run, `None` if it succeeded
+    pub last_error: Option<String>,
+    pub runs: u64,
+    pub failures: u64,
+    /// Number of times task wasn't started because previous run was not
+    /// finished yet
+    pub skipped: u64,
+}
+
+struct Task {
+    key: Key,
+    cron: String,
+    schedule: cron::Schedule,
+    next: Option<DateTime<Utc>>,
+}
+
+const WEEKDAYS: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+/// Parses day of week, both 0 and 7 are Sunday (returned as is)
+fn parse_weekday(value: &str) -> anyhow::Result<usize> {
+    if let Some(day) = WEEKDAYS.iter()
+        .position(|d| d.eq_ignore_ascii_case(value))
+    {
+        return Ok(day);
+    }
+    match value.parse() {
+        Ok(day) if day <= 7 => Ok(day),
+        _ => anyhow::bail!("invalid day of week {:?}", value),
+    }
+}
+
+/// Converts standard day-of-week field into the list of day names
+///
+/// `cron` crate numbers days from 1 (Sunday) to 7, while standard cron
+/// numbers them from 0 (Sunday) to 6, with 7 being Sunday too. Names are
+/// the same in both.
+fn convert_weekdays(field: &str) -> anyhow::Result<String> {
+    if field == "*" || field == "?" {
+        return Ok(field.to_string());
+    }
+    let mut days = [false; 7];
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => match step.parse() {
+                Ok(step) if step > 0 => (range, Some(step)),
+                _ => anyhow::bail!("invalid step {:?}", step),
+            },
+            None => (item, None),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (0, 6),
+            Some((start, end)) => (parse_weekday(start)?, parse_weekday(end)?),
+            // `1/2` means every second day starting from Monday
+            None if step.is_some() => (parse_weekday(range)?, 6),
+            None => {
+                let day = parse_weekday(range)?;
+                (day, day)
+            }
+        };
+        if start > end {
+            anyhow::bail!("invalid range of days {:?}", range);
+        }
+        for day in (start..=end).step_by(step.unwrap_or(1)) {
+            days[day % 7] = true;
+        }
+    }
+    Ok(WEEKDAYS.iter().zip(days)
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(","))
+}
+
+/// Converts standard five-field expression into the one with seconds
+fn parse_cron(expr: &str) -> anyhow::Result<cron::Schedule> {
+    let fields = expr.split_whitespace().collect::<Vec<_>>();
+    let expr = if let [min, hour, day, month, weekday] = fields[..] {
+        format!("0 {} {} {} {} {}", min, hour, day, month,
+                convert_weekdays(weekday)?)
+    } else {
+        expr.to_string()
+    };
+    Ok(cron::Schedule::from_str(&expr)?)
+}
+
+impl Scheduler {
+    /// (Re)starts scheduling loop for the database
+    pub fn start(&self, tenant: &Tenant, database: &str, directory: &Path) {
+        let task = tokio::spawn(scheduling_loop(
+            Arc::downgrade(&tenant.0),
+            self.status.clone(),
+            database.to_string(),
+            directory.to_path_buf(),
+        ));
+        let mut loops = self.loops.lock().expect("scheduler is not poisoned");
+        if let Some(old) = loops.insert(database.to_string(), task) {
+            old.abort();
+        }
+    }
+    pub fn status(&self, database: Option<&str>) -> Vec<TaskStatus> {
+        let status = self.status.lock().expect("scheduler is not poisoned");
+        status.values()
+            .filter(|s| database.map(|db| s.database == db).unwrap_or(true))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Returns schedules of the module
+///
+/// Module is compiled only if it's changed since the last scan.
+async fn module_schedules(tenant: &Tenant, database: &str, wasm_name: &str,
+                          modified: SystemTime, declared: &mut Declared)
+    -> anyhow::Result<Vec<(String, String)>>
+{
+    let mut schedules = match declared.get(wasm_name) {
+        Some((time, schedules)) if *time == modified => schedules.clone(),
+        _ => {
+            let module = tenant.get_module(database, wasm_name).await?;
+            let schedules = module.schedules();
+            declared.insert(wasm_name.to_string(),
+                            (modified, schedules.clone()));
+            schedules
+        }
+    };
+    if let Some(worker) = tenant.running_worker(database, wasm_name).await {
+        match worker.schedules().await {
+            Ok(registered) => {
+                for (name, cron) in registered {
+                    if !schedules.iter().any(|(n, _)| *n == name) {
+                        schedules.push((name, cron));
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("Worker {} failed to list schedules: {:#}",
+                            worker.full_name(), e);
+            }
+        }
+    }
+    Ok(schedules)
+}
+
+async fn scan(tenant: &Tenant, database: &str, directory: &Path,
+              old_tasks: Vec<Task>, declared: &mut Declared)
+    -> anyhow::Result<Vec<Task>>
+{
+    let mut old_tasks = old_tasks.into_iter()
+        .map(|t| (t.key.clone(), t))
+        .collect::<HashMap<_, _>>();
+    let mut tasks = Vec::new();
+    let mut modules = Vec::new();
+    let mut dir = fs::read_dir(directory).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        let file_name = entry.file_name();
+        let wasm_name = match file_name.to_str()
+            .and_then(|n| n.strip_suffix(".wasm"))
+        {
+            Some(name) if is_valid_name(name) => name,
+            _ => continue,
+        };
+        modules.push(wasm_name.to_string());
+        let modified = entry.metadata().await.and_then(|m| m.modified());
+        let schedules = match modified {
+            Ok(modified) => {
+                module_schedules(tenant, database, wasm_name,
+                                 modified, declared).await
+            }
+            Err(e) => Err(e.into()),
+        };
+        let schedules = match schedules {
+            Ok(schedules) => schedules,
+            Err(e) => {
+                log::error!("Cannot load module {:?} for scheduling: {:#}",
+                            entry.path(), e);
+                continue;
+            }
+        };
+        for (name, cron) in schedules {
+            let key = (database.to_string(), wasm_name.to_string(), name);
+            match old_tasks.remove(&key) {
+                Some(task) if task.cron == cron => {
+                    tasks.push(task);
+                    continue;
+                }
+                _ => {}
+            }
+            match parse_cron(&cron) {
+                Ok(schedule) => {
+                    log::info!("Scheduled task {}::{}::{} at {:?}",
+                               key.0, key.1, key.2, cron);
+                    let next = schedule.upcoming(Utc).next();
+                    tasks.push(Task { key, cron, schedule, next });
+                }
+                Err(e) => {
+                    log::error!("Invalid schedule {:?} of {}::{}::{}: {:#}",
+                                cron, key.0, key.1, key.2, e);
+                }
+            }
+        }
+    }
+    declared.retain(|name, _| modules.contains(name));
+    Ok(tasks)
+}
+
+async fn scheduling_loop(tenant: Weak<TenantInner>,
+                         status: Arc<Mutex<BTreeMap<Key, TaskStatus>>>,
+                         database: String, directory: PathBuf)
+{
+    let mut tasks = Vec::new();
+    let mut declared = Declared::new();
+    let mut next_scan = Instant::now();
+    loop {
+        let tenant = match tenant.upgrade() {
+            Some(inner) => Tenant(inner),
+            None => return,
+        };
+        if Instant::now() >= next_scan {
+            let result = scan(&tenant, &database, &directory, tasks,
+                              &mut declared).await;
+            match result {
+                Ok(new_tasks) => tasks = new_tasks,
+                Err(e) => {
+                    log::error!("Error scanning {:?} for schedules: {:#}",
+                                directory, e);
+                    tasks = Vec::new();
+                }
+            }
+            update_status(&status, &database, &tasks);
+            next_scan = Instant::now() + RESCAN_INTERVAL;
+        }
+        let now = Utc::now();
+        for task in &mut tasks {
+            if matches!(task.next, Some(next) if next <= now) {
+                start_task(&tenant, &status, &task.key);
+                task.next = task.schedule.after(&now).next();
+            }
+        }
+        let mut sleep = next_scan.saturating_duration_since(Instant::now());
+        if let Some(next) = tasks.iter().filter_map(|t| t.next).min() {
+            let till_next = (next - Utc::now()).to_std()
+                .unwrap_or(Duration::ZERO);
+            sleep = sleep.min(till_next);
+        }
+        drop(tenant);
+        tokio::time::sleep(sleep).await;
+    }
+}
+
+fn update_status(status: &Mutex<BTreeMap<Key, TaskStatus>>, database: &str,
+                 tasks: &[Task])
+{
+    let mut status = status.lock().expect("scheduler is not poisoned");
+    status.retain(|key, _| {
+        key.0 != database || tasks.iter().any(|t| &t.key == key)
+    });
+    for task in tasks {
+        let entry = status.entry(task.key.clone()).or_insert_with(|| {
+            TaskStatus {
+                database: task.key.0.clone(),
+                module_name: task.key.1.clone(),
+                name: task.key.2.clone(),
+                cron: task.cron.clone(),
+                running: false,
+                last_started: None,
+                last_duration: None,
+                last_error: None,
+                runs: 0,
+                failures: 0,
+                skipped: 0,
+            }
+        });
+        entry.cron = task.cron.clone();
+    }
+}
+
+fn start_task(tenant: &Tenant, status: &Arc<Mutex<BTreeMap<Key, TaskStatus>>>,
+              key: &Key)
+{
+    {
+        let mut status = status.lock().expect("scheduler is not poisoned");
+        let entry = match status.get_mut(key) {
+            Some(entry) => entry,
+            None => return,
+        };
+        if entry.running {
+            log::warn!("Scheduled task {}::{}::{} is still running, \
+                        skipping", key.0, key.1, key.2);
+            entry.skipped += 1;
+            return;
+        }
+        entry.running = true;
+    }
+    let tenant = tenant.clone();
+    let status = status.clone();
+    let key = key.clone();
+    tokio::spawn(async move {
+        let (database, wasm_name, name) = &key;
+        let started = Utc::now();
+        let start = Instant::now();
+        log::debug!("Starting scheduled task {database}::{wasm_name}::{name}");
+        let worker = tenant.dedicated_worker(database, wasm_name).await;
+        let result = match worker {
+            Ok(worker) => {
+                let result = worker.run_scheduled(name).await;
+                worker.shutdown().await;
+                result
+            }
+            Err(e) => Err(e),
+        };
+        let duration = start.elapsed();
+        let error = match result {
+            Ok(Ok(())) => {
+                log::info!("Scheduled task {database}::{wasm_name}::{name} \
+                            finished in {duration:?}");
+                None
+            }
+            Ok(Err(message)) => {
+                log::error!("Scheduled task {database}::{wasm_name}::{name} \
+                             failed: {message}");
+                Some(message)
+            }
+            Err(e) => {
+                log::error!("Scheduled task {database}::{wasm_name}::{name} \
+                             crashed: {e:#}");
+                Some(format!("{:#}", e))
+            }
+        };
+        let mut status = status.lock().expect("scheduler is not poisoned");
+        if let Some(entry) = status.get_mut(&key) {
+            entry.running = false;
+            entry.last_started = Some(started.timestamp_millis() as f64 / 1e3);
+            entry.last_duration = Some(duration.as_secs_f64());
+            entry.runs += 1;
+            if error.is_some() {
+                entry.failures += 1;
+            }
+            entry.last_error = error;
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, Datelike, Utc, Weekday};
+
+    use super::{convert_weekdays, parse_cron};
+
+    /// Days of the week the expression fires on during a week from Sunday
+    fn weekdays(expr: &str) -> Vec<Weekday> {
+        let start = DateTime::parse_from_rfc3339("2022-06-04T23:59:59Z")
+            .unwrap().with_timezone(&Utc);
+        let end = DateTime::parse_from_rfc3339("2022-06-11T23:59:59Z")
+            .unwrap().with_timezone(&Utc);
+        parse_cron(expr).unwrap().after(&start)
+            .take_while(|t| *t < end)
+            .map(|t| t.weekday())
+            .collect()
+    }
+
+    #[test]
+    fn weekday_numbers() {
+        use Weekday::*;
+        assert_eq!(weekdays("0 9 * * 1"), vec![Mon]);
+        assert_eq!(weekdays("0 9 * * 0"), vec![Sun]);
+        assert_eq!(weekdays("0 9 * * 7"), vec![Sun]);
+        assert_eq!(weekdays("0 9 * * 6"), vec![Sat]);
+        assert_eq!(weekdays("0 9 * * 1-5"), vec![Mon, Tue, Wed, Thu, Fri]);
+        assert_eq!(weekdays("0 9 * * 5-7"), vec![Sun, Fri, Sat]);
+        assert_eq!(weekdays("0 9 * * 0,3"), vec![Sun, Wed]);
+        assert_eq!(weekdays("0 9 * * */2"), vec![Sun, Tue, Thu, Sat]);
+        assert_eq!(weekdays("0 9 * * 1-5/2"), vec![Mon, Wed, Fri]);
+        assert_eq!(weekdays("0 9 * * 1/3"), vec![Mon, Thu]);
+        assert_eq!(weekdays("0 9 * * *").len(), 7);
+    }
+
+    #[test]
+    fn weekday_names() {
+        use Weekday::*;
+        assert_eq!(weekdays("0 9 * * mon-fri"), vec![Mon, Tue, Wed, Thu, Fri]);
+        assert_eq!(weekdays("0 9 * * SUN,SAT"), vec![Sun, Sat]);
+    }
+
+    #[test]
+    fn every_minute_on_sunday() {
+        let start = DateTime::parse_from_rfc3339("2022-06-05T10:00:30Z")
+            .unwrap().with_timezone(&Utc);
+        let next = parse_cron("* * * * 0").unwrap().after(&start).next()
+            .unwrap();
+        assert_eq!(next.to_rfc3339(), "2022-06-05T10:01:00+00:00");
+    }
+
+    #[test]
+    fn with_seconds() {
+        // six and seven field expressions use the syntax of `cron` crate
+        assert!(parse_cron("30 0 9 * * Mon").is_ok());
+        assert!(parse_cron("30 0 9 * * Mon 2030").is_ok());
+    }
+
+    #[test]
+    fn invalid_weekday() {
+        assert!(convert_weekdays("8").is_err());
+        assert!(convert_weekdays("5-1").is_err());
+        assert!(convert_weekdays("1/0").is_err());
+        assert!(convert_weekdays("funday").is_err());
+        assert!(parse_cron("0 9 * * 8").is_err());
+    }
+}
@@ -0,0 +1,116 @@
+//! Runs tests exported by `#[edgedb_sdk::test]`
+//!
+//! Each test is run in a fresh instance of the module (with init hooks
+//! already run), so tests can't influence each other except via database.
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+
+use crate::module::Module;
+use crate::options::TestOptions;
+use crate::tenant::Tenant;
+use crate::worker;
+
+const TEST_PREFIX: &str = "_edgedb_sdk_test_";
+
+/// Time each test may run
+const TEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+enum Outcome {
+    Pass,
+    /// Test returned an error (error itself is logged by the guest)
+    Fail,
+    /// Test has panicked or otherwise crashed
+    Trap(anyhow::Error),
+    Timeout,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Pass => f.write_str("ok"),
+            Outcome::Fail => f.write_str("FAILED"),
+            Outcome::Trap(_) => f.write_str("TRAPPED"),
+            Outcome::Timeout => f.write_str("TIMED OUT"),
+        }
+    }
+}
+
+async fn run_test(tenant: &Tenant, name: &Arc<worker::Name>,
+                  module: &Module, export: &str)
+    -> anyhow::Result<(Outcome, Vec<String>)>
+{
+    let (mut store, instance) = worker::instantiate(tenant, name, module)
+        .await
+        .context("cannot initialize module")?;
+    // logs of the initialization are not captured, as they are the same
+    // for every test
+    store.data().log_capture.enable();
+    let func = instance.get_typed_func::<(), i32, _>(&mut store, export)
+        .with_context(|| format!("{:?} has wrong type", export))?;
+    let call = func.call_async(&mut store, ());
+    let outcome = match tokio::time::timeout(TEST_TIMEOUT, call).await {
+        Ok(Ok(0)) => Outcome::Pass,
+        Ok(Ok(_)) => Outcome::Fail,
+        Ok(Err(trap)) => Outcome::Trap(trap.into()),
+        Err(_) => Outcome::Timeout,
+    };
+    let logs = store.data().log_capture.take();
+    Ok((outcome, logs))
+}
+
+/// Runs tests, returns `false` if any of them failed
+pub async fn run(tenant: &Tenant, options: &TestOptions)
+    -> anyhow::Result<bool>
+{
+    let module = tenant.load_module(&options.file).await?;
+    let wasm_name = options.file.file_stem()
+        .and_then(|n| n.to_str())
+        .context("invalid file name")?;
+    let name = Arc::new(worker::Name {
+        database: options.database.clone(),
+        wasm_name: wasm_name.into(),
+    });
+    let mut tests = module.wasm.exports()
+        .filter_map(|e| e.name().strip_prefix(TEST_PREFIX))
+        .filter(|n| {
+            options.filter.as_ref().map(|f| n.contains(&f[..]))
+                .unwrap_or(true)
+        })
+        .map(|n| n.to_string())
+        .collect::<Vec<_>>();
+    tests.sort();
+
+    println!("running {} tests", tests.len());
+    let mut failed = Vec::new();
+    for test in &tests {
+        let export = format!("{}{}", TEST_PREFIX, test);
+        let (outcome, logs) = run_test(tenant, &name, &module, &export).await
+            .with_context(|| format!("error running test {:?}", test))?;
+        println!("test {} ... {}", test, outcome);
+        let passed = matches!(outcome, Outcome::Pass);
+        if let Outcome::Trap(e) = &outcome {
+            println!("  {:#}", e);
+        }
+        if !passed || options.show_output {
+            for line in &logs {
+                println!("  {}", line);
+            }
+        }
+        if !passed {
+            failed.push(test);
+        }
+    }
+    if !failed.is_empty() {
+        println!("\nfailures:");
+        for test in &failed {
+            println!("    {}", test);
+        }
+    }
+    println!("\ntest result: {}. {} passed; {} failed",
+             if failed.is_empty() { "ok" } else { "FAILED" },
+             tests.len() - failed.len(), failed.len());
+    Ok(failed.is_empty())
+}
@@ -1,19 +1,33 @@
-use std::collections::HashMap;
 use std::fmt;
 use std::future::Future;
 use std::marker::PhantomData;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use bytes::Bytes;
 use hyper::Uri;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::UnixStream;
+use tokio::sync::mpsc;
 
 use crate::tenant::Tenant;
 use crate::tenant::http;
+use crate::tenant::scheduler;
+use crate::abi::config_v1;
+use crate::abi::http_client_v1::{self, Policy};
+use crate::abi::http_body_v1::{self, Source, Sink, StreamError};
 use crate::abi::http_server_v1 as v1;
+use crate::abi::http_server_v2 as v2;
+use crate::worker;
 
 
+/// Maximum size of the streamed body buffered for non-streaming client
+const MAX_BUFFERED_BODY: usize = 10 << 20;
+/// Time to wait for the end of the streamed body for non-streaming client
+///
+/// Some streams (e.g. server-sent events) never end.
+const BUFFERED_BODY_TIMEOUT: Duration = Duration::from_secs(60);
+
 pub struct Process<'a>(PhantomData<&'a ()>);
 
 #[derive(serde::Deserialize, Debug)]
@@ -21,6 +35,11 @@ pub struct Process<'a>(PhantomData<&'a ()>);
 pub enum Request {
     SetDirectory(SetDirectory),
     Http(HttpRequest),
+    ScheduledTasks(ScheduledTasks),
+    SetHttpClientPolicy(SetHttpClientPolicy),
+    SetConfig(SetConfig),
+    Metrics(GetMetrics),
+    SetLogLevel(SetLogLevel),
 }
 
 // We can't use unit type instead, because we serialize `Success` as dict,
@@ -55,12 +74,75 @@ pub struct SetDirectory {
     directory: PathBuf,
 }
 
+#[derive(serde::Deserialize, Debug)]
+pub struct SetConfig {
+    database: String,
+    module_name: String,
+    /// Replaces all values previously set for the module
+    values: config_v1::Values,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ScheduledTasks {
+    /// Only return tasks of this database (all databases if not set)
+    #[serde(default)]
+    database: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct SetHttpClientPolicy {
+    database: String,
+    module_name: String,
+    /// Host patterns: `example.com`, `*.example.com`, `localhost:8080`, `*`
+    allowed_hosts: Vec<String>,
+    /// Timeout of each request in seconds
+    #[serde(default)]
+    timeout: Option<f64>,
+    /// Maximum size of the response body in bytes
+    #[serde(default)]
+    max_response_size: Option<usize>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct GetMetrics {
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct SetLogLevel {
+    database: String,
+    module_name: String,
+    /// One of `off`, `error`, `warn`, `info`, `debug`, `trace`, or none to
+    /// revert to the log level of the server
+    #[serde(default)]
+    level: Option<String>,
+}
+
+/// Metrics of all modules in Prometheus text format
+#[derive(serde::Serialize, Debug)]
+struct MetricsText {
+    text: String,
+}
+
+#[derive(serde::Serialize, Debug)]
+struct TaskList {
+    tasks: Vec<scheduler::TaskStatus>,
+}
+
 #[derive(serde::Deserialize, Debug)]
 pub struct HttpRequest {
     method: String,
     url: String,
     headers: Vec<(Bytes, Bytes)>,
     body: Option<Bytes>,
+    #[serde(default)]
+    scheme: Option<String>,
+    #[serde(default)]
+    remote_addr: Option<String>,
+    /// Client accepts streamed response body. Streamed body is sent as a
+    /// sequence of pickled byte strings after the response itself, up to the
+    /// end of the connection.
+    #[serde(default)]
+    streaming: bool,
 }
 
 #[derive(Debug)]
@@ -73,8 +155,23 @@ pub struct ConvertRequest<'a> {
 #[derive(serde::Serialize, Debug)]
 pub struct Response {
     status: u16,
-    headers: HashMap<Bytes, Bytes>,
+    /// List of pairs rather than a dict to keep repeated headers (e.g.
+    /// multiple `Set-Cookie`)
+    headers: Vec<(Bytes, Bytes)>,
     body: Option<Bytes>,
+    streaming: bool,
+    #[serde(skip)]
+    stream: Option<mpsc::Receiver<Bytes>>,
+}
+
+/// Request body already received from the socket
+///
+/// Request is a single pickled object framed by the end of the stream (see
+/// `process_request`), so the body can't be streamed from the socket: it's
+/// fully read before the request is parsed. Chunks are split off the shared
+/// buffer, so this doesn't copy the body.
+struct BodySource {
+    buf: Bytes,
 }
 
 
@@ -84,7 +181,7 @@ impl<'a> http::Process for Process<'a> {
     type ConvertInput = ConvertRequest<'a>;
     type Output = Response;
 
-    async fn read_full(request: &'a HttpRequest)
+    async fn read_head(request: &'a HttpRequest)
         -> anyhow::Result<ConvertRequest<'a>>
     {
         Ok(ConvertRequest {
@@ -100,16 +197,29 @@ impl<'a> http::Process for Process<'a> {
         // TODO(tailhook) only in debug mode
         Response {
             status: hyper::StatusCode::NOT_FOUND.as_u16(),
-            headers: HashMap::new(),
+            headers: Vec::new(),
             body: Some(b"Try /db/<database>/wasm/<wasm-file-name>/"[..].into()),
+            streaming: false,
+            stream: None,
         }
     }
     fn err_internal_server_error() -> Self::Output {
         // TODO(tailhook) only in debug mode
         Response {
             status: hyper::StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
-            headers: HashMap::new(),
+            headers: Vec::new(),
             body: Some(b"Wasm failed to handle request"[..].into()),
+            streaming: false,
+            stream: None,
+        }
+    }
+    fn err_service_unavailable() -> Self::Output {
+        Response {
+            status: hyper::StatusCode::SERVICE_UNAVAILABLE.as_u16(),
+            headers: Vec::new(),
+            body: Some(b"Wasm failed to initialize"[..].into()),
+            streaming: false,
+            stream: None,
         }
     }
 }
@@ -128,6 +238,96 @@ async fn respond<T>(mut sock: UnixStream, response: impl Into<Signal<T>>)
     Ok(())
 }
 
+/// Reads the whole streamed body, dropping the stream on error
+async fn buffer_body(mut stream: mpsc::Receiver<Bytes>)
+    -> anyhow::Result<Bytes>
+{
+    let mut body = Vec::new();
+    while let Some(chunk) = stream.recv().await {
+        if body.len() + chunk.len() > MAX_BUFFERED_BODY {
+            anyhow::bail!("streamed response body exceeds {} bytes, \
+                           request with `streaming` to receive it",
+                          MAX_BUFFERED_BODY);
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body.into())
+}
+
+async fn respond_http(mut sock: UnixStream, request: &HttpRequest,
+                      response: anyhow::Result<Response>)
+    -> anyhow::Result<()>
+{
+    let mut response = match response {
+        Ok(response) => response,
+        Err(e) => return respond(sock, Err::<Response, _>(e)).await,
+    };
+    let mut stream = match response.stream.take() {
+        Some(stream) => stream,
+        None => return respond(sock, Signal::Success(response)).await,
+    };
+    if !request.streaming {
+        // client can't handle streaming, so we buffer the body
+        let body = tokio::time::timeout(BUFFERED_BODY_TIMEOUT,
+                                        buffer_body(stream)).await
+            .unwrap_or_else(|_| Err(anyhow::anyhow!(
+                "streamed response body hasn't finished in {:?}, \
+                 request with `streaming` to receive it",
+                BUFFERED_BODY_TIMEOUT)));
+        return match body {
+            Ok(body) => {
+                response.body = Some(body);
+                respond(sock, Signal::Success(response)).await
+            }
+            Err(e) => respond(sock, Err::<Response, _>(e)).await,
+        };
+    }
+    response.streaming = true;
+    let response = Signal::Success(response);
+    sock.write_all(
+        &serde_pickle::to_vec(&response, serde_pickle::SerOptions::new())?
+    ).await?;
+    while let Some(chunk) = stream.recv().await {
+        let chunk = serde_pickle::to_vec(
+            &chunk, serde_pickle::SerOptions::new())?;
+        // on error stream is dropped, so worker is notified of disconnect
+        sock.write_all(&chunk).await?;
+    }
+    log::debug!("Streamed response written");
+    Ok(())
+}
+
+fn set_http_client_policy(tenant: &Tenant, req: SetHttpClientPolicy)
+    -> anyhow::Result<()>
+{
+    let timeout = match req.timeout {
+        Some(secs) if secs.is_finite() && secs > 0.0 => {
+            Duration::from_secs_f64(secs)
+        }
+        Some(secs) => anyhow::bail!("invalid timeout {}", secs),
+        None => http_client_v1::DEFAULT_TIMEOUT,
+    };
+    tenant.set_http_client_policy(&req.database, &req.module_name, Policy {
+        allowed_hosts: req.allowed_hosts,
+        timeout,
+        max_response_size: req.max_response_size
+            .unwrap_or(http_client_v1::DEFAULT_MAX_RESPONSE_SIZE),
+    });
+    Ok(())
+}
+
+async fn set_log_level(tenant: &Tenant, req: SetLogLevel)
+    -> anyhow::Result<()>
+{
+    let level = match req.level {
+        Some(level) => Some(level.parse::<log::LevelFilter>()
+            .map_err(|_| anyhow::anyhow!("invalid log level {:?}", level))?),
+        None => None,
+    };
+    tenant.set_log_level(&req.database, &req.module_name, level).await;
+    Ok(())
+}
+
 async fn process_request(mut sock: UnixStream, tenant: Tenant)
     -> anyhow::Result<()>
 {
@@ -139,12 +339,32 @@ async fn process_request(mut sock: UnixStream, tenant: Tenant)
         Request::Http(request) => {
             let response = tenant.handle::<Process>(&request).await;
             log::debug!("Request handled");
-            respond(sock, response).await?;
+            respond_http(sock, &request, response).await?;
         }
         Request::SetDirectory(SetDirectory { database, directory }) => {
             tenant.set_directory(&database, &directory).await;
             respond(sock, Signal::Success(PyNone {})).await?;
         }
+        Request::ScheduledTasks(ScheduledTasks { database }) => {
+            let tasks = tenant.scheduled_tasks(database.as_deref());
+            respond(sock, Signal::Success(TaskList { tasks })).await?;
+        }
+        Request::SetConfig(SetConfig { database, module_name, values }) => {
+            tenant.set_config(&database, &module_name, values).await;
+            respond(sock, Signal::Success(PyNone {})).await?;
+        }
+        Request::Metrics(GetMetrics {}) => {
+            let text = tenant.render_metrics();
+            respond(sock, Signal::Success(MetricsText { text })).await?;
+        }
+        Request::SetHttpClientPolicy(req) => {
+            let result = set_http_client_policy(&tenant, req);
+            respond(sock, result.map(|()| PyNone {})).await?;
+        }
+        Request::SetLogLevel(req) => {
+            let result = set_log_level(&tenant, req).await;
+            respond(sock, result.map(|()| PyNone {})).await?;
+        }
     }
     Ok(())
 }
@@ -158,10 +378,34 @@ pub fn service(sock: UnixStream, tenant: &Tenant) -> impl Future<Output=()> {
     }
 }
 
+impl ConvertRequest<'_> {
+    fn scheme(&self) -> Option<&str> {
+        self.request.scheme.as_deref().or_else(|| self.uri.scheme_str())
+    }
+    fn host(&self) -> Option<&str> {
+        self.uri.authority().map(|a| a.as_str()).or_else(|| {
+            self.headers.iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(b"host"))
+                .and_then(|(_, v)| std::str::from_utf8(v).ok())
+        })
+    }
+}
+
+#[async_trait::async_trait]
 impl http::ConvertInput for ConvertRequest<'_> {
     fn uri(&self) -> &hyper::Uri {
         &self.uri
     }
+    async fn read_full_body(&mut self) -> anyhow::Result<()> {
+        // protocol always sends full body
+        Ok(())
+    }
+    fn take_body(&mut self) -> Box<dyn Source> {
+        // `Bytes` are reference counted, so clone doesn't copy the body
+        Box::new(BodySource {
+            buf: self.request.body.clone().unwrap_or_default(),
+        })
+    }
     fn as_v1(&self) -> v1::Request<'_> {
         v1::Request {
             method: &self.request.method[..],
@@ -170,6 +414,35 @@ impl http::ConvertInput for ConvertRequest<'_> {
             body: self.request.body.as_deref().unwrap_or(b""),
         }
     }
+    fn as_v2<'a>(&'a self, name: &'a worker::Name, mount_prefix: &'a str)
+        -> v2::Request<'a>
+    {
+        v2::Request {
+            method: &self.request.method[..],
+            uri: &self.request.url[..],
+            headers: &self.headers,
+            database: &name.database,
+            module_name: &name.wasm_name,
+            mount_prefix,
+            scheme: self.scheme(),
+            host: self.host(),
+            remote_addr: self.request.remote_addr.as_deref(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for BodySource {
+    async fn read(&mut self, max_size: usize) -> Result<Bytes, StreamError> {
+        let size = self.buf.len().min(max_size);
+        Ok(self.buf.split_to(size))
+    }
+}
+
+fn convert_headers(headers: Vec<(Vec<u8>, Vec<u8>)>) -> Vec<(Bytes, Bytes)> {
+    headers.into_iter()
+        .map(|(k, v)| (k.into(), v.into()))
+        .collect()
 }
 
 impl http::FromWasm for Response {
@@ -177,10 +450,30 @@ impl http::FromWasm for Response {
         log::debug!("Converting response");
         Ok(Response {
             status: wasm.status_code,
-            headers: wasm.headers.into_iter()
-                .map(|(k, v)| (k.into(), v.into()))
-                .collect(),
+            headers: convert_headers(wasm.headers),
             body: Some(wasm.body.into()),
+            streaming: false,
+            stream: None,
         })
     }
+    fn from_wasm_v2(wasm: v2::Response)
+        -> anyhow::Result<(Self, Option<Box<dyn Sink>>)>
+    {
+        log::debug!("Converting response");
+        let (body, stream, sink) = match wasm.body {
+            v2::Body::Full(body) => (Some(body.into()), None, None),
+            v2::Body::Stream => {
+                let (sink, receiver) = http_body_v1::channel();
+                (None, Some(receiver), Some(sink))
+            }
+        };
+        let response = Response {
+            status: wasm.status_code,
+            headers: convert_headers(wasm.headers),
+            body,
+            streaming: false,
+            stream,
+        };
+        Ok((response, sink))
+    }
 }
@@ -0,0 +1,171 @@
+use futures_util::{SinkExt, StreamExt};
+use hyper::header::{self, HeaderValue};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use hyper::upgrade::Upgraded;
+use tokio::sync::mpsc;
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::Message as Frame;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+
+use crate::abi::websocket_v1::Message;
+use crate::tenant::{self, Tenant};
+use crate::tenant::http::Process as _;
+use crate::worker::{self, Worker};
+
+
+pub fn is_upgrade(req: &Request<Body>) -> bool {
+    req.headers().get(header::UPGRADE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.eq_ignore_ascii_case("websocket"))
+        .unwrap_or(false)
+}
+
+fn error(status: StatusCode, text: &'static str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(text.into())
+        .expect("can compose static response")
+}
+
+pub async fn handle(tenant: Tenant, mut req: Request<Body>)
+    -> anyhow::Result<Response<Body>>
+{
+    if req.method() != Method::GET {
+        return Ok(error(StatusCode::METHOD_NOT_ALLOWED,
+                        "WebSocket upgrade requires GET"));
+    }
+    let version = req.headers().get(header::SEC_WEBSOCKET_VERSION);
+    if version.map(|v| v.as_bytes()) != Some(&b"13"[..]) {
+        // RFC 6455 requires to reply with the supported version
+        return Ok(Response::builder()
+            .status(StatusCode::UPGRADE_REQUIRED)
+            .header(header::SEC_WEBSOCKET_VERSION,
+                    HeaderValue::from_static("13"))
+            .body("Unsupported WebSocket version".into())?);
+    }
+    let accept_key = match req.headers().get(header::SEC_WEBSOCKET_KEY) {
+        Some(key) => derive_accept_key(key.as_bytes()),
+        None => {
+            return Ok(error(StatusCode::BAD_REQUEST,
+                            "No Sec-WebSocket-Key header"));
+        }
+    };
+    let (database, wasm_name) = match tenant::parse_path(req.uri().path()) {
+        Some(pair) => pair,
+        None => return Ok(error(StatusCode::NOT_FOUND, "Not Found")),
+    };
+    let worker = match tenant.get_worker(database, wasm_name).await {
+        Ok(worker) => worker,
+        Err(e) if e.is::<worker::InitError>() => {
+            log::error!("Worker is not initialized: {:#}", e);
+            return Ok(error(StatusCode::SERVICE_UNAVAILABLE,
+                            "Wasm failed to initialize"));
+        }
+        Err(e) => {
+            log::error!("Get worker error: {:#}", e);
+            return Ok(error(StatusCode::INTERNAL_SERVER_ERROR,
+                            "Wasm failed to handle request"));
+        }
+    };
+    if !worker.supports_websocket() {
+        return Ok(error(StatusCode::NOT_FOUND,
+                        "WebSocket handler is not set"));
+    }
+
+    let upgrade = hyper::upgrade::on(&mut req);
+    let cvt = crate::hyper::Process::read_head(&mut req).await?;
+    let request = cvt.as_websocket_v1(worker.full_name(),
+                                      worker.mount_prefix());
+    let (connection, frames) = match worker.websocket_open(request).await {
+        Ok(Some(pair)) => pair,
+        Ok(None) => return Ok(error(StatusCode::FORBIDDEN, "Forbidden")),
+        Err(e) => {
+            log::error!("Worker {} failed to open websocket: {:#}. \
+                         Request: {:?}",
+                        worker.full_name(), e, cvt);
+            return Ok(error(StatusCode::INTERNAL_SERVER_ERROR,
+                            "Wasm failed to handle request"));
+        }
+    };
+
+    tokio::spawn(async move {
+        match upgrade.await {
+            Ok(upgraded) => {
+                let ws = WebSocketStream::from_raw_socket(
+                    upgraded, Role::Server, None).await;
+                run(&worker, connection, ws, frames).await;
+            }
+            Err(e) => {
+                log::debug!("WebSocket upgrade failed: {:#}", e);
+                close(&worker, connection, None, "").await;
+            }
+        }
+    });
+
+    Ok(Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(header::CONNECTION, HeaderValue::from_static("upgrade"))
+        .header(header::UPGRADE, HeaderValue::from_static("websocket"))
+        .header(header::SEC_WEBSOCKET_ACCEPT, accept_key)
+        .body(Body::empty())?)
+}
+
+async fn run(worker: &Worker, connection: u64,
+             ws: WebSocketStream<Upgraded>,
+             mut frames: mpsc::UnboundedReceiver<Frame>)
+{
+    let (mut sink, mut stream) = ws.split();
+    // stops when worker forgets the connection or on write error
+    tokio::spawn(async move {
+        while let Some(frame) = frames.recv().await {
+            if let Err(e) = sink.send(frame).await {
+                log::debug!("Error writing websocket: {:#}", e);
+                break;
+            }
+        }
+    });
+    let mut code = None;
+    let mut reason = String::new();
+    while let Some(frame) = stream.next().await {
+        let result = match frame {
+            Ok(Frame::Text(text)) => {
+                worker.websocket_message(connection, Message::Text(&text))
+                    .await
+            }
+            Ok(Frame::Binary(data)) => {
+                worker.websocket_message(connection, Message::Binary(&data))
+                    .await
+            }
+            Ok(Frame::Close(frame)) => {
+                if let Some(frame) = frame {
+                    code = Some(frame.code.into());
+                    reason = frame.reason.into_owned();
+                }
+                // stream ends when close reply is sent
+                continue;
+            }
+            // pings are answered by tungstenite
+            Ok(_) => continue,
+            Err(e) => {
+                log::debug!("Error reading websocket: {:#}", e);
+                break;
+            }
+        };
+        if let Err(e) = result {
+            log::error!("Worker {} failed to handle websocket message: {:#}",
+                        worker.full_name(), e);
+            break;
+        }
+    }
+    close(worker, connection, code, &reason).await;
+}
+
+async fn close(worker: &Worker, connection: u64,
+               code: Option<u16>, reason: &str)
+{
+    if let Err(e) = worker.websocket_close(connection, code, reason).await {
+        log::error!("Worker {} failed to close websocket: {:#}",
+                    worker.full_name(), e);
+    }
+}
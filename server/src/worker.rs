@@ -1,18 +1,38 @@
+use std::collections::BTreeSet;
 use std::default::Default;
 use std::fmt;
 use std::hash;
+use std::mem;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
 use anyhow::Context;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex, MutexGuard, OwnedMutexGuard};
+use tokio_tungstenite::tungstenite::Message as Frame;
 use wasmtime::Instance;
 
 use crate::abi;
+use crate::logging;
 use crate::module::Module;
+use crate::stdio::{self, Stream};
 use crate::tenant::Tenant;
 use crate::tenant::http::{self, ConvertInput as _};
 
 
+/// Time deferred tasks may run after each response
+const DEFERRED_BUDGET: Duration = Duration::from_secs(10);
+/// Time all shutdown hooks of the worker may run
+const SHUTDOWN_BUDGET: Duration = Duration::from_secs(5);
+/// Time scheduled task may run
+const SCHEDULED_BUDGET: Duration = Duration::from_secs(300);
+/// Time background job may run, must be less than lease time of the job
+pub const JOB_BUDGET: Duration = Duration::from_secs(300);
+/// Time request made by another module waits for the worker to be free
+///
+/// Two modules invoking each other concurrently would deadlock otherwise.
+const INVOKE_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Clone)]
 pub struct Worker(Arc<WorkerInner>);
 
@@ -27,17 +47,52 @@ pub struct Name {
 pub struct State {
     pub name: Arc<Name>,
     pub wasi: wasmtime_wasi::WasiCtx,
+    /// Collects log records instead of logging them (used by tests)
+    pub log_capture: logging::Capture,
+    pub log_v2: abi::log_v2::State,
+    pub log_level_v1: abi::log_level_v1::State,
     pub http_server_v1: abi::http_server_v1::State,
+    pub http_server_v2: abi::http_server_v2::State,
+    pub http_body_v1: abi::http_body_v1::State,
+    pub websocket_v1: abi::websocket_v1::State,
+    pub websocket_connection_v1: abi::websocket_connection_v1::State,
+    pub scheduler_v1: abi::scheduler_v1::State,
     pub client_v1: abi::client_v1::State,
+    pub http_client_v1: abi::http_client_v1::State,
+    pub cache_v1: abi::cache_v1::State,
+    pub config_v1: abi::config_v1::State,
+    pub config_notify_v1: abi::config_notify_v1::State,
+    pub secrets_v1: abi::secrets_v1::State,
+    pub metrics_v1: abi::metrics_v1::State,
+    pub job_runner_v1: abi::job_runner_v1::State,
+    pub invoke_v1: abi::invoke_v1::State,
 }
 
 struct WorkerInner {
     name: Arc<Name>,
+    mount_prefix: String,
     module: Arc<Module>,
-    store: Mutex<wasmtime::Store<State>>,
-    #[allow(dead_code)] // TODO
+    store: Arc<Mutex<wasmtime::Store<State>>>,
+    /// Store is busy writing a streaming response (possibly endless)
+    streaming: AtomicBool,
+    /// Guest has trapped (e.g. panicked), so its state is unknown
+    poisoned: AtomicBool,
+    /// Changes to deliver to the guest before the next call
+    pending: std::sync::Mutex<Pending>,
     instance: Instance,
     http_server_v1: Option<abi::http_server_v1::Handler<State>>,
+    http_server_v2: Option<abi::http_server_v2::Handler<State>>,
+    websocket_v1: Option<abi::websocket_v1::Handler<State>>,
+    scheduler_v1: Option<abi::scheduler_v1::Handler<State>>,
+    config_notify_v1: Option<abi::config_notify_v1::Handler<State>>,
+    log_level_v1: Option<abi::log_level_v1::Handler<State>>,
+    job_runner_v1: Option<abi::job_runner_v1::Handler<State>>,
+}
+
+#[derive(Default)]
+struct Pending {
+    log_level: bool,
+    config_keys: BTreeSet<String>,
 }
 
 impl Drop for LogGuard {
@@ -56,6 +111,79 @@ impl State {
     pub fn http_server_v1(&mut self) -> &mut abi::http_server_v1::State {
         &mut self.http_server_v1
     }
+    pub fn http_server_v2(&mut self) -> &mut abi::http_server_v2::State {
+        &mut self.http_server_v2
+    }
+    pub fn http_body_v1(&mut self) -> abi::http_body_v1::Context {
+        self.http_body_v1.context()
+    }
+    pub fn websocket_v1(&mut self) -> &mut abi::websocket_v1::State {
+        &mut self.websocket_v1
+    }
+    pub fn websocket_connection_v1(&mut self)
+        -> &mut abi::websocket_connection_v1::State
+    {
+        &mut self.websocket_connection_v1
+    }
+    pub fn scheduler_v1(&mut self) -> &mut abi::scheduler_v1::State {
+        &mut self.scheduler_v1
+    }
+    pub fn http_client_v1(&mut self) -> &mut abi::http_client_v1::State {
+        &mut self.http_client_v1
+    }
+    pub fn invoke_v1(&mut self) -> &mut abi::invoke_v1::State {
+        &mut self.invoke_v1
+    }
+}
+
+/// Init hook reported failure, so worker is not usable
+///
+/// Error itself is logged by the guest.
+#[derive(Debug)]
+pub struct InitError {
+    pub hook: String,
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "init hook {:?} failed", self.hook)
+    }
+}
+
+impl std::error::Error for InitError {}
+
+/// Parses `<name>@<priority>` suffix of the init hook export
+///
+/// Hooks without priority have priority of zero.
+fn parse_init_hook(suffix: &str) -> (i32, &str) {
+    if let Some((name, priority)) = suffix.rsplit_once('@') {
+        if let Ok(priority) = priority.parse() {
+            return (priority, name);
+        }
+    }
+    (0, suffix)
+}
+
+async fn call_hook(store: &mut wasmtime::Store<State>, name: &str,
+                   func: wasmtime::Func)
+    -> anyhow::Result<()>
+{
+    // hooks returning status code are fallible
+    if func.ty(&*store).results().len() == 1 {
+        let status = func.typed::<(), i32, _>(&mut *store)
+            .with_context(|| format!("{:?} has wrong type", name))?
+            .call_async(&mut *store, ()).await
+            .with_context(|| format!("error calling {:?}", name))?;
+        if status != 0 {
+            return Err(InitError { hook: name.into() }.into());
+        }
+    } else {
+        func.typed::<(), (), _>(&mut *store)
+            .with_context(|| format!("{:?} has wrong type", name))?
+            .call_async(&mut *store, ()).await
+            .with_context(|| format!("error calling {:?}", name))?;
+    }
+    Ok(())
 }
 
 async fn call_init(store: &mut wasmtime::Store<State>, instance: &Instance)
@@ -63,7 +191,7 @@ async fn call_init(store: &mut wasmtime::Store<State>, instance: &Instance)
 {
     let mut pre_init = None;
     let mut post_init = None;
-    let init_funcs = instance.exports(&mut *store).filter_map(|e| {
+    let mut init_funcs = instance.exports(&mut *store).filter_map(|e| {
         match e.name() {
             "_edgedb_sdk_pre_init" => {
                 pre_init = e.into_func();
@@ -73,33 +201,101 @@ async fn call_init(store: &mut wasmtime::Store<State>, instance: &Instance)
                 post_init = e.into_func();
                 None
             }
-            name if name.starts_with("_edgedb_sdk_init_") => {
-                e.into_func()
+            name => {
+                let suffix = name.strip_prefix("_edgedb_sdk_init_")?;
+                let (priority, hook) = parse_init_hook(suffix);
+                let hook = hook.to_string();
+                e.into_func().map(|func| (priority, hook, func))
             }
-            _ => None,
         }
     }).collect::<Vec<_>>();
+    // order of exports is unspecified, ties are ordered by name
+    init_funcs.sort_by(|(p1, n1, _), (p2, n2, _)| (p1, n1).cmp(&(p2, n2)));
     if let Some(pre_init) = pre_init {
-        pre_init.typed(&mut *store)
-            .with_context(|| format!("{:?} has wrong type", pre_init))?
-            .call_async(&mut *store, ()).await
-            .with_context(|| format!("error calling {:?}", pre_init))?;
+        call_hook(&mut *store, "pre_init", pre_init).await?;
     }
-    for init_func in init_funcs {
-        init_func.typed(&mut *store)
-            .with_context(|| format!("{:?} has wrong type", init_func))?
-            .call_async(&mut *store, ()).await
-            .with_context(|| format!("error calling {:?}", init_func))?;
+    for (_, name, init_func) in init_funcs {
+        log::debug!("Running init hook {:?}", name);
+        call_hook(&mut *store, &name, init_func).await?;
     }
     if let Some(post_init) = post_init {
-        post_init.typed(&mut *store)
-            .with_context(|| format!("{:?} has wrong type", post_init))?
-            .call_async(&mut *store, ()).await
-            .with_context(|| format!("error calling {:?}", post_init))?;
+        call_hook(&mut *store, "post_init", post_init).await?;
     }
     Ok(())
 }
 
+/// Runs all shutdown hooks, failure of one hook doesn't stop the others
+async fn call_shutdown(store: &mut wasmtime::Store<State>,
+                       instance: &Instance, worker: &Name)
+{
+    let mut funcs = instance.exports(&mut *store).filter_map(|e| {
+        let name = e.name().strip_prefix("_edgedb_sdk_shutdown_")?
+            .to_string();
+        e.into_func().map(|func| (name, func))
+    }).collect::<Vec<_>>();
+    // order of exports is unspecified
+    funcs.sort_by(|(n1, _), (n2, _)| n1.cmp(n2));
+    for (name, func) in funcs {
+        log::debug!("Running shutdown hook {:?}", name);
+        if let Err(e) = call_hook(&mut *store, &name, func).await {
+            log::error!("Worker {} failed to run shutdown hook {:?}: {:#}",
+                        worker, name, e);
+        }
+    }
+}
+
+/// Creates a store and instance of the module and runs its init hooks
+pub async fn instantiate(tenant: &Tenant, name: &Arc<Name>, module: &Module)
+    -> anyhow::Result<(wasmtime::Store<State>, Instance)>
+{
+    let log_capture = logging::Capture::default();
+    let log_v2 = tenant.log_v2_state();
+    let stdout = stdio::pipe(name, Stream::Stdout,
+                             log_v2.levels(), &log_capture);
+    let stderr = stdio::pipe(name, Stream::Stderr,
+                             log_v2.levels(), &log_capture);
+    let wasi = wasmtime_wasi::sync::WasiCtxBuilder::new()
+        .stdout(Box::new(stdout))
+        .stderr(Box::new(stderr))
+        .build();
+    let cli = tenant.get_client(&name.database).await?;
+    let state = State {
+        name: name.clone(),
+        wasi,
+        log_capture,
+        log_v2,
+        log_level_v1: Default::default(),
+        http_server_v1: Default::default(),
+        http_server_v2: Default::default(),
+        http_body_v1: Default::default(),
+        websocket_v1: Default::default(),
+        websocket_connection_v1: Default::default(),
+        scheduler_v1: Default::default(),
+        client_v1: abi::client_v1::State::new(&cli),
+        http_client_v1: tenant.http_client_state(name),
+        cache_v1: tenant.cache_state(name),
+        config_v1: tenant.config_state(name),
+        config_notify_v1: Default::default(),
+        secrets_v1: tenant.secrets_state(name),
+        metrics_v1: tenant.metrics_state(name),
+        job_runner_v1: Default::default(),
+        invoke_v1: tenant.invoke_state(name),
+    };
+    let mut store = wasmtime::Store::new(tenant.get_engine(), state);
+    // yield on every epoch tick, so calls can be cancelled by timeout
+    store.epoch_deadline_async_yield_and_update(1);
+
+    let instance = tenant.get_linker()
+        .instantiate(&mut store, &module.wasm)?;
+
+    call_init(&mut store, &instance).await?;
+    let main = instance.get_typed_func::<(), (), _>(&mut store, "_start")
+        .context("get main(_start) function")?;
+    main.call_async(&mut store, ()).await.context("call main function")?;
+    store.data_mut().invoke_v1.instantiated();
+    Ok((store, instance))
+}
+
 impl Worker {
     /*
     pub fn name(&self) -> &str {
@@ -112,6 +308,148 @@ impl Worker {
     pub fn module(&self) -> &Arc<Module> {
         &self.0.module
     }
+    pub fn mount_prefix(&self) -> &str {
+        &self.0.mount_prefix
+    }
+    pub fn supports_websocket(&self) -> bool {
+        self.0.websocket_v1.is_some()
+    }
+    /// Returns `false` if worker is occupied by a streaming response or is
+    /// poisoned by a trap
+    ///
+    /// Such worker should not receive new requests.
+    pub fn is_available(&self) -> bool {
+        !self.0.streaming.load(Ordering::SeqCst) &&
+            !self.0.poisoned.load(Ordering::SeqCst)
+    }
+    /// Marks worker as not usable for new requests
+    fn poison(&self) {
+        if !self.0.poisoned.swap(true, Ordering::SeqCst) {
+            log::warn!("Worker {} is poisoned, \
+                        new instance is used for the next requests",
+                       self.full_name());
+        }
+    }
+    /// Marks worker as poisoned if call into the guest trapped
+    fn check_trap<T>(&self, result: Result<T, wasmtime::Trap>)
+        -> Result<T, wasmtime::Trap>
+    {
+        if result.is_err() {
+            self.poison();
+        }
+        result
+    }
+    /// Locks the store, returns `None` if worker is poisoned
+    ///
+    /// Flag is checked after the lock is acquired, because the previous
+    /// holder of the lock might have trapped or run shutdown hooks.
+    async fn lock(&self) -> Option<MutexGuard<'_, wasmtime::Store<State>>> {
+        let mut store = self.0.store.lock().await;
+        self.deliver_pending(&mut *store).await;
+        if self.0.poisoned.load(Ordering::SeqCst) {
+            return None;
+        }
+        Some(store)
+    }
+    /// Locks the store for handling HTTP request
+    ///
+    /// Returns `None` if worker is poisoned, same as [`Worker::lock`].
+    async fn lock_for_http(&self, req: &impl http::ConvertInput)
+        -> anyhow::Result<Option<OwnedMutexGuard<wasmtime::Store<State>>>>
+    {
+        let lock = self.0.store.clone().lock_owned();
+        let mut store = if req.invoke_chain().is_empty() {
+            lock.await
+        } else {
+            tokio::time::timeout(INVOKE_LOCK_TIMEOUT, lock).await
+                .map_err(|_| anyhow::anyhow!("worker {} is busy for {:?}",
+                                             self.full_name(),
+                                             INVOKE_LOCK_TIMEOUT))?
+        };
+        self.deliver_pending(&mut *store).await;
+        if self.0.poisoned.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+        Ok(Some(store))
+    }
+    /// Delivers changes made while the worker was busy
+    ///
+    /// Must be called with the store locked. Nothing is delivered to a
+    /// poisoned worker.
+    async fn deliver_pending(&self, store: &mut wasmtime::Store<State>) {
+        if self.0.poisoned.load(Ordering::SeqCst) {
+            return;
+        }
+        let pending = mem::take(
+            &mut *self.0.pending.lock().expect("pending is not poisoned"));
+        if pending.log_level {
+            if let Err(e) = self.push_log_level(&mut *store).await {
+                log::error!("Worker {} failed to change log level: {:#}",
+                            self.full_name(), e);
+            }
+        }
+        if !pending.config_keys.is_empty() {
+            let keys = pending.config_keys.iter()
+                .map(|k| &k[..]).collect::<Vec<_>>();
+            if let Err(e) = self.notify_config(&mut *store, &keys).await {
+                log::error!("Worker {} failed to handle config change: {:#}",
+                            self.full_name(), e);
+            }
+        }
+    }
+    /// Delivers pending changes in background, if worker is idle
+    ///
+    /// Busy worker receives them before the next call.
+    fn deliver_soon(&self) {
+        if self.is_available() {
+            let worker = self.clone();
+            tokio::spawn(async move {
+                // changes are delivered when the lock is acquired
+                drop(worker.lock().await);
+            });
+        }
+    }
+    async fn run_deferred(&self, store: &mut wasmtime::Store<State>) {
+        let api = self.0.http_server_v2.as_ref()
+            .expect("deferred tasks are only supported by http_server_v2");
+        let _log_guard = LogGuard("run_deferred");
+        let call = api.run_deferred(&mut *store);
+        match tokio::time::timeout(DEFERRED_BUDGET, call).await {
+            Ok(result) => {
+                if let Err(e) = self.check_trap(result) {
+                    log::error!("Worker {} failed to run deferred tasks: {:#}",
+                                self.full_name(), e);
+                }
+            }
+            Err(_) => {
+                // guest is interrupted at unknown point
+                self.poison();
+                log::error!("Worker {} exceeded time budget of {:?} \
+                             running deferred tasks",
+                            self.full_name(), DEFERRED_BUDGET);
+            }
+        }
+    }
+    /// Runs shutdown hooks of the module
+    ///
+    /// Should be called when worker is evicted. This waits for the current
+    /// request (or a streaming response) to finish, but hooks themselves are
+    /// limited by the time budget. Hooks of a poisoned worker are not run.
+    pub async fn shutdown(&self) {
+        let mut store = self.0.store.lock().await;
+        // instance must not be used after shutdown hooks
+        if self.0.poisoned.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let _log_guard = LogGuard("shutdown");
+        let call = call_shutdown(&mut *store, &self.0.instance,
+                                 &self.0.name);
+        if tokio::time::timeout(SHUTDOWN_BUDGET, call).await.is_err() {
+            log::error!("Worker {} exceeded time budget of {:?} \
+                         running shutdown hooks",
+                        self.full_name(), SHUTDOWN_BUDGET);
+        }
+    }
     pub async fn new(tenant: &Tenant, database: &str, wasm_name: &str,
                      module: Arc<Module>)
         -> anyhow::Result<Worker>
@@ -120,45 +458,80 @@ impl Worker {
             database: database.into(),
             wasm_name: wasm_name.into(),
         });
-        let wasi = wasmtime_wasi::sync::WasiCtxBuilder::new()
-            .inherit_stdio() // temporary
-            .build();
-        let cli = tenant.get_client(database).await?;
-        let state = State {
-            name: name.clone(),
-            wasi,
-            http_server_v1: Default::default(),
-            client_v1: abi::client_v1::State::new(&cli),
+        let (mut store, instance) = instantiate(tenant, &name, &module)
+            .await?;
+        // modules built with older SDK only export v1
+        let http_server_v2 = abi::http_server_v2::Handler::new(
+            &mut store, &instance, |s: &mut State| &mut s.http_server_v2)
+            .map_err(|e| log::debug!("No edgedb_http_server_v2: {e:#}"))
+            .ok();
+        let http_server_v1 = if http_server_v2.is_none() {
+            abi::http_server_v1::Handler::new(
+                &mut store, &instance, |s: &mut State| &mut s.http_server_v1)
+                .map_err(|e| log::debug!("No edgedb_http_server_v1: {e:#}"))
+                .ok()
+        } else {
+            None
         };
-        let mut store = wasmtime::Store::new(tenant.get_engine(), state);
-
-        let instance = tenant.get_linker()
-            .instantiate(&mut store, &module.wasm)?;
-        let http_server_v1 = abi::http_server_v1::Handler::new(
-            &mut store, &instance, |s: &mut State| &mut s.http_server_v1)
-            .context("error reading edgedb_http_server_v1 handler")?;
-
-        call_init(&mut store, &instance).await?;
-        let main = instance.get_typed_func::<(), (), _>(&mut store, "_start")
-            .context("get main(_start) function")?;
-        main.call_async(&mut store, ()).await.context("call main function")?;
+        let websocket_v1 = abi::websocket_v1::Handler::new(
+            &mut store, &instance, |s: &mut State| &mut s.websocket_v1)
+            .map_err(|e| log::debug!("No edgedb_websocket_v1: {e:#}"))
+            .ok();
+        let scheduler_v1 = abi::scheduler_v1::Handler::new(
+            &mut store, &instance, |s: &mut State| &mut s.scheduler_v1)
+            .map_err(|e| log::debug!("No edgedb_scheduler_v1: {e:#}"))
+            .ok();
+        let config_notify_v1 = abi::config_notify_v1::Handler::new(
+            &mut store, &instance, |s: &mut State| &mut s.config_notify_v1)
+            .map_err(|e| log::debug!("No edgedb_config_notify_v1: {e:#}"))
+            .ok();
+        let log_level_v1 = abi::log_level_v1::Handler::new(
+            &mut store, &instance, |s: &mut State| &mut s.log_level_v1)
+            .map_err(|e| log::debug!("No edgedb_log_level_v1: {e:#}"))
+            .ok();
+        let job_runner_v1 = abi::job_runner_v1::Handler::new(
+            &mut store, &instance, |s: &mut State| &mut s.job_runner_v1)
+            .map_err(|e| log::debug!("No edgedb_job_runner_v1: {e:#}"))
+            .ok();
 
         Ok(Worker(Arc::new(WorkerInner {
+            mount_prefix: name.to_string(),
             name,
             module,
-            store: Mutex::new(store),
+            store: Arc::new(Mutex::new(store)),
+            streaming: AtomicBool::new(false),
+            poisoned: AtomicBool::new(false),
+            pending: Default::default(),
             instance,
-            http_server_v1: Some(http_server_v1),
+            http_server_v1,
+            http_server_v2,
+            websocket_v1,
+            scheduler_v1,
+            config_notify_v1,
+            log_level_v1,
+            job_runner_v1,
         })))
     }
-    pub async fn handle_http<P: http::Process>(&self, req: P::ConvertInput)
+    pub async fn handle_http<P: http::Process>(&self,
+                                               mut req: P::ConvertInput)
         -> anyhow::Result<P::Output>
     {
-        if let Some(api) = &self.0.http_server_v1 {
+        if self.0.http_server_v2.is_some() {
+            self.handle_http_v2::<P>(req).await
+        } else if let Some(api) = &self.0.http_server_v1 {
+            req.read_full_body().await?;
             let response;
             let _log_guard = LogGuard("handle_http");
-            let mut store = self.0.store.lock().await;
-            match api.handle_request(&mut *store, req.as_v1()).await {
+            let mut store = match self.lock_for_http(&req).await? {
+                Some(store) => store,
+                None => return Ok(P::err_service_unavailable()),
+            };
+            let request = req.as_v1();
+            store.data_mut().invoke_v1
+                .start_request(req.invoke_chain(), request.headers);
+            let result = api.handle_request(&mut *store, request).await;
+            store.data_mut().invoke_v1.finish_request();
+            match self.check_trap(result) {
                 Ok(resp) => response = resp,
                 Err(e) => {
                     log::error!("Worker {} failed to handle request: {:#}. \
@@ -181,6 +554,224 @@ impl Worker {
             Ok(P::err_not_found())
         }
     }
+    async fn handle_http_v2<P: http::Process>(&self,
+                                              mut req: P::ConvertInput)
+        -> anyhow::Result<P::Output>
+    {
+        let api = self.0.http_server_v2.as_ref()
+            .context("no http_server_v2 handler")?;
+        let _log_guard = LogGuard("handle_http");
+        let mut store = match self.lock_for_http(&req).await? {
+            Some(store) => store,
+            None => return Ok(P::err_service_unavailable()),
+        };
+        store.data_mut().http_body_v1.start_request(req.take_body());
+        let request = req.as_v2(&self.0.name, &self.0.mount_prefix);
+        // sub-requests are also allowed in body writer and deferred tasks
+        store.data_mut().invoke_v1
+            .start_request(req.invoke_chain(), request.headers);
+        let result = api.handle_http_request(&mut *store, request).await;
+        let result = self.check_trap(result);
+        store.data_mut().http_body_v1.finish_request();
+        let response = match result {
+            Ok(resp) => resp,
+            Err(e) => {
+                store.data_mut().invoke_v1.finish_request();
+                log::error!("Worker {} failed to handle request: {:#}. \
+                             Request: {:?}",
+                            self.full_name(), e, req);
+                return Ok(P::err_internal_server_error())
+            }
+        };
+        log::debug!("Response generated, code: {:?}", response.status_code);
+        let has_deferred = response.has_deferred;
+        let (response, sink) = match http::FromWasm::from_wasm_v2(response) {
+            Ok(pair) => pair,
+            Err(e) => {
+                store.data_mut().invoke_v1.finish_request();
+                log::error!("Worker {} returned invalid response: {:#}. \
+                             Request: {:?}",
+                            self.full_name(), e, req);
+                return Ok(P::err_internal_server_error())
+            }
+        };
+        if let Some(sink) = sink {
+            // Store is kept locked until the whole body is written, so
+            // tenant starts a new worker for the next requests
+            self.0.streaming.store(true, Ordering::SeqCst);
+            let worker = self.clone();
+            tokio::spawn(async move {
+                let mut store = store;
+                let _log_guard = LogGuard("write_http_body");
+                let api = worker.0.http_server_v2.as_ref()
+                    .expect("http_server_v2 checked above");
+                store.data_mut().http_body_v1.start_response(sink);
+                let result = api.write_http_body(&mut *store).await;
+                if let Err(e) = worker.check_trap(result) {
+                    log::error!("Worker {} failed to write response body: \
+                                 {:#}", worker.full_name(), e);
+                }
+                store.data_mut().http_body_v1.finish_response().await;
+                if has_deferred {
+                    worker.run_deferred(&mut *store).await;
+                }
+                store.data_mut().invoke_v1.finish_request();
+                // store is unlocked right after this, so worker can
+                // receive new requests again
+                worker.0.streaming.store(false, Ordering::SeqCst);
+            });
+        } else if has_deferred {
+            // response is sent while store is kept locked by deferred tasks
+            let worker = self.clone();
+            tokio::spawn(async move {
+                let mut store = store;
+                worker.run_deferred(&mut *store).await;
+                store.data_mut().invoke_v1.finish_request();
+            });
+        } else {
+            store.data_mut().invoke_v1.finish_request();
+        }
+        Ok(response)
+    }
+    /// Asks worker whether to accept WebSocket connection
+    ///
+    /// Returns connection id and outgoing frames if connection is accepted.
+    pub async fn websocket_open(&self, req: abi::websocket_v1::Request<'_>)
+        -> anyhow::Result<Option<(u64, mpsc::UnboundedReceiver<Frame>)>>
+    {
+        let api = self.0.websocket_v1.as_ref()
+            .context("no websocket_v1 handler")?;
+        let _log_guard = LogGuard("websocket_open");
+        let mut store = self.lock().await.context("worker is poisoned")?;
+        let (id, frames) = store.data_mut().websocket_connection_v1.open();
+        match self.check_trap(api.on_open(&mut *store, id, req).await) {
+            Ok(true) => Ok(Some((id, frames))),
+            Ok(false) => {
+                store.data_mut().websocket_connection_v1.close(id);
+                Ok(None)
+            }
+            Err(e) => {
+                store.data_mut().websocket_connection_v1.close(id);
+                Err(e.into())
+            }
+        }
+    }
+    pub async fn websocket_message(&self, connection: u64,
+                                   message: abi::websocket_v1::Message<'_>)
+        -> anyhow::Result<()>
+    {
+        let api = self.0.websocket_v1.as_ref()
+            .context("no websocket_v1 handler")?;
+        let _log_guard = LogGuard("websocket_message");
+        let mut store = self.lock().await.context("worker is poisoned")?;
+        let result = api.on_message(&mut *store, connection, message).await;
+        self.check_trap(result)?;
+        Ok(())
+    }
+    pub async fn websocket_close(&self, connection: u64,
+                                 code: Option<u16>, reason: &str)
+        -> anyhow::Result<()>
+    {
+        let api = self.0.websocket_v1.as_ref()
+            .context("no websocket_v1 handler")?;
+        let _log_guard = LogGuard("websocket_close");
+        let mut store = self.0.store.lock().await;
+        store.data_mut().websocket_connection_v1.close(connection);
+        // guest must not be called after a trap or shutdown hooks
+        if self.0.poisoned.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let result = api.on_close(&mut *store, connection, code, reason)
+            .await;
+        self.check_trap(result)?;
+        Ok(())
+    }
+    /// Returns `(name, cron)` pairs of the tasks scheduled by the module
+    pub async fn schedules(&self) -> anyhow::Result<Vec<(String, String)>> {
+        let api = match &self.0.scheduler_v1 {
+            Some(api) => api,
+            None => return Ok(Vec::new()),
+        };
+        let _log_guard = LogGuard("list_schedules");
+        let mut store = self.lock().await.context("worker is poisoned")?;
+        let schedules = self.check_trap(api.list_schedules(&mut *store).await)?;
+        Ok(schedules.into_iter().map(|s| (s.name, s.cron)).collect())
+    }
+    /// Runs scheduled task, inner error is the failure reported by the task
+    pub async fn run_scheduled(&self, name: &str)
+        -> anyhow::Result<Result<(), String>>
+    {
+        let api = self.0.scheduler_v1.as_ref()
+            .context("no scheduler_v1 handler")?;
+        let _log_guard = LogGuard("run_scheduled");
+        let mut store = self.lock().await.context("worker is poisoned")?;
+        let call = api.run_scheduled(&mut *store, name);
+        match tokio::time::timeout(SCHEDULED_BUDGET, call).await {
+            Ok(result) => Ok(self.check_trap(result)?),
+            Err(_) => {
+                // guest is interrupted at unknown point
+                self.poison();
+                anyhow::bail!("task exceeded time budget of {:?}",
+                              SCHEDULED_BUDGET);
+            }
+        }
+    }
+    /// Runs background job, inner error is the failure reported by the job
+    pub async fn run_job(&self, name: &str, payload: &str)
+        -> anyhow::Result<Result<(), String>>
+    {
+        let api = self.0.job_runner_v1.as_ref()
+            .context("no job_runner_v1 handler")?;
+        let _log_guard = LogGuard("run_job");
+        let mut store = self.lock().await.context("worker is poisoned")?;
+        let call = api.run_job(&mut *store, name, payload);
+        match tokio::time::timeout(JOB_BUDGET, call).await {
+            Ok(result) => Ok(self.check_trap(result)?),
+            Err(_) => {
+                // guest is interrupted at unknown point
+                self.poison();
+                anyhow::bail!("job exceeded time budget of {:?}", JOB_BUDGET);
+            }
+        }
+    }
+    /// Schedules notifying module that configuration values have changed
+    pub fn config_changed(&self, keys: Vec<String>) {
+        self.0.pending.lock().expect("pending is not poisoned")
+            .config_keys.extend(keys);
+        self.deliver_soon();
+    }
+    async fn notify_config(&self, store: &mut wasmtime::Store<State>,
+                           keys: &[&str])
+        -> anyhow::Result<()>
+    {
+        let api = match &self.0.config_notify_v1 {
+            Some(api) => api,
+            None => return Ok(()),
+        };
+        let _log_guard = LogGuard("config_changed");
+        let result = api.on_change(&mut *store, keys).await;
+        Ok(self.check_trap(result)?)
+    }
+    /// Schedules pushing the current log level of the module into the
+    /// instance
+    pub fn log_level_changed(&self) {
+        self.0.pending.lock().expect("pending is not poisoned")
+            .log_level = true;
+        self.deliver_soon();
+    }
+    async fn push_log_level(&self, store: &mut wasmtime::Store<State>)
+        -> anyhow::Result<()>
+    {
+        let api = match &self.0.log_level_v1 {
+            Some(api) => api,
+            None => return Ok(()),
+        };
+        let _log_guard = LogGuard("log_level_changed");
+        let level = store.data().log_v2.max_level(&self.0.name);
+        let level = abi::log_level_v1::convert_level(level);
+        let result = api.set_max_level(&mut *store, level).await;
+        Ok(self.check_trap(result)?)
+    }
 }
 
 impl fmt::Debug for Worker {
@@ -230,3 +821,39 @@ impl PartialEq for Worker {
 
 impl Eq for Worker {}
 
+
+#[cfg(test)]
+mod test {
+    use super::parse_init_hook;
+
+    #[test]
+    fn init_hook_priority() {
+        assert_eq!(parse_init_hook("setup"), (0, "setup"));
+        assert_eq!(parse_init_hook("setup@10"), (10, "setup"));
+        assert_eq!(parse_init_hook("setup@-5"), (-5, "setup"));
+        assert_eq!(parse_init_hook("setup@+3"), (3, "setup"));
+    }
+
+    #[test]
+    fn init_hook_invalid_priority() {
+        assert_eq!(parse_init_hook("setup@"), (0, "setup@"));
+        assert_eq!(parse_init_hook("setup@high"), (0, "setup@high"));
+        assert_eq!(parse_init_hook("setup@99999999999"),
+                   (0, "setup@99999999999"));
+    }
+
+    #[test]
+    fn init_hook_last_at() {
+        assert_eq!(parse_init_hook("a@b@7"), (7, "a@b"));
+        assert_eq!(parse_init_hook("a@7@b"), (0, "a@7@b"));
+    }
+
+    #[test]
+    fn init_hook_order() {
+        let mut hooks = ["b", "a@1", "c@-1", "a"].iter()
+            .map(|s| parse_init_hook(s))
+            .collect::<Vec<_>>();
+        hooks.sort();
+        assert_eq!(hooks, [(-1, "c"), (0, "a"), (0, "b"), (1, "a")]);
+    }
+}